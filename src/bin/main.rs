@@ -1,9 +1,29 @@
 use chrono::Local;
-use clap::{crate_authors, crate_description, crate_name, crate_version, load_yaml, App};
+use clap::{
+    crate_authors, crate_description, crate_name, crate_version, load_yaml, App, ErrorKind,
+};
 use log::{error, LevelFilter};
+use pwned_rs::database::LookupOutcome;
+use pwned_rs::error::PwnedError;
+use pwned_rs::subcommands::adaudit::run_subcommand as run_subcommand_adaudit;
+use pwned_rs::subcommands::applypatch::run_subcommand as run_subcommand_applypatch;
+use pwned_rs::subcommands::audit::run_subcommand as run_subcommand_audit;
+use pwned_rs::subcommands::batchlookup::run_subcommand as run_subcommand_batchlookup;
+use pwned_rs::subcommands::buildfilter::run_subcommand as run_subcommand_buildfilter;
+use pwned_rs::subcommands::compile::run_subcommand as run_subcommand_compile;
+use pwned_rs::subcommands::daemon::run_subcommand as run_subcommand_daemon;
+use pwned_rs::subcommands::diff::run_subcommand as run_subcommand_diff;
+use pwned_rs::subcommands::filterlookup::run_subcommand as run_subcommand_filterlookup;
 use pwned_rs::subcommands::lookup::run_subcommand as run_subcommand_lookup;
+use pwned_rs::subcommands::merge::run_subcommand as run_subcommand_merge;
+use pwned_rs::subcommands::mirror::run_subcommand as run_subcommand_mirror;
 use pwned_rs::subcommands::optimize::run_subcommand as run_subcommand_optimize;
 use pwned_rs::subcommands::quicklookup::run_subcommand as run_subcommand_quicklookup;
+use pwned_rs::subcommands::serve::run_subcommand as run_subcommand_serve;
+use pwned_rs::subcommands::sort::run_subcommand as run_subcommand_sort;
+use pwned_rs::subcommands::streamlookup::run_subcommand as run_subcommand_streamlookup;
+use pwned_rs::subcommands::verify::run_subcommand as run_subcommand_verify;
+use std::process::exit;
 
 #[cfg(debug_assertions)]
 const LOGGING_LEVEL: LevelFilter = LevelFilter::Trace;
@@ -11,6 +31,40 @@ const LOGGING_LEVEL: LevelFilter = LevelFilter::Trace;
 #[cfg(not(debug_assertions))]
 const LOGGING_LEVEL: LevelFilter = LevelFilter::Info;
 
+/// The exit code if the subcommand was successful (or the password was not found).
+const EXIT_CODE_SUCCESS: i32 = 0;
+
+/// The exit code if the password was found by one of the lookup subcommands.
+const EXIT_CODE_PASSWORD_FOUND: i32 = 1;
+
+/// The exit code if an argument is missing, unknown or not valid.
+const EXIT_CODE_INVALID_ARGUMENT: i32 = 2;
+
+/// Get the exit code which reports the outcome of a lookup.
+fn get_outcome_exit_code(outcome: LookupOutcome) -> i32 {
+    match outcome {
+        LookupOutcome::Found(_) => EXIT_CODE_PASSWORD_FOUND,
+        LookupOutcome::NotFound => EXIT_CODE_SUCCESS,
+    }
+}
+
+/// Get the exit code which is used for terminating the application because of the supplied error.
+/// The codes are documented in the README and must not change, since scripts depend on them.
+fn get_exit_code(error: &PwnedError) -> i32 {
+    match error {
+        PwnedError::InvalidArgument(_) => EXIT_CODE_INVALID_ARGUMENT,
+        PwnedError::Io(..) => 3,
+        PwnedError::Format { .. } | PwnedError::Export(..) => 4,
+        PwnedError::UnsortedInput { .. } => 5,
+        PwnedError::Database(..) => 6,
+        PwnedError::Lookup(_) => 7,
+        PwnedError::CorruptDatabase(_) => 8,
+        PwnedError::Compile(_) | PwnedError::Filter(_) => 9,
+        PwnedError::Patch(_) => 10,
+        PwnedError::Range(_) => 11,
+    }
+}
+
 fn initialize_logging() {
     // configure the logging framework and set the corresponding log level
     let logging_framework = fern::Dispatch::new()
@@ -24,7 +78,7 @@ fn initialize_logging() {
             ))
         })
         .level(LOGGING_LEVEL)
-        .chain(std::io::stdout())
+        .chain(std::io::stderr())
         .apply();
 
     // ensure the logging framework was successfully initialized
@@ -43,16 +97,67 @@ fn main() {
         .version(crate_version!())
         .name(crate_name!())
         .about(crate_description!())
-        .get_matches();
+        .get_matches_safe();
+
+    // clap would exit with 1 on a usage error, which could not be told apart from a found password
+    let matches = match matches {
+        Ok(matches) => matches,
+        Err(error) => match error.kind {
+            ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed => error.exit(),
+            _ => {
+                eprintln!("{}", error.message);
+                exit(EXIT_CODE_INVALID_ARGUMENT);
+            }
+        },
+    };
 
     // check which subcommand should be executed and call it
-    if let Some(matches) = matches.subcommand_matches("optimize") {
-        run_subcommand_optimize(matches);
+    let result = if let Some(matches) = matches.subcommand_matches("optimize") {
+        run_subcommand_optimize(matches).map(|_| EXIT_CODE_SUCCESS)
     } else if let Some(matches) = matches.subcommand_matches("lookup") {
-        run_subcommand_lookup(matches);
+        run_subcommand_lookup(matches).map(get_outcome_exit_code)
     } else if let Some(matches) = matches.subcommand_matches("quick-lookup") {
-        run_subcommand_quicklookup(matches);
+        run_subcommand_quicklookup(matches).map(get_outcome_exit_code)
+    } else if let Some(matches) = matches.subcommand_matches("batch-lookup") {
+        run_subcommand_batchlookup(matches).map(|_| EXIT_CODE_SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches("compile") {
+        run_subcommand_compile(matches).map(|_| EXIT_CODE_SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches("serve") {
+        run_subcommand_serve(matches).map(|_| EXIT_CODE_SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches("verify") {
+        run_subcommand_verify(matches).map(|_| EXIT_CODE_SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches("build-filter") {
+        run_subcommand_buildfilter(matches).map(|_| EXIT_CODE_SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches("filter-lookup") {
+        run_subcommand_filterlookup(matches).map(|_| EXIT_CODE_SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches("audit") {
+        run_subcommand_audit(matches).map(get_outcome_exit_code)
+    } else if let Some(matches) = matches.subcommand_matches("ad-audit") {
+        run_subcommand_adaudit(matches).map(get_outcome_exit_code)
+    } else if let Some(matches) = matches.subcommand_matches("stream-lookup") {
+        run_subcommand_streamlookup(matches).map(|_| EXIT_CODE_SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches("sort") {
+        run_subcommand_sort(matches).map(|_| EXIT_CODE_SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches("merge") {
+        run_subcommand_merge(matches).map(|_| EXIT_CODE_SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches("diff") {
+        run_subcommand_diff(matches).map(|_| EXIT_CODE_SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches("apply-patch") {
+        run_subcommand_applypatch(matches).map(|_| EXIT_CODE_SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches("mirror") {
+        run_subcommand_mirror(matches).map(|_| EXIT_CODE_SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches("daemon") {
+        run_subcommand_daemon(matches).map(|_| EXIT_CODE_SUCCESS)
     } else {
-        error!("No known subcommand was selected. Please refer to the help for information about how to use this application.");
+        Err(PwnedError::InvalidArgument("No known subcommand was selected. Please refer to the help for information about how to use this application.".to_string()))
+    };
+
+    // terminate with the exit code which belongs to the outcome or the error
+    match result {
+        Ok(exit_code) => exit(exit_code),
+        Err(error) => {
+            error!("{}", error);
+            exit(get_exit_code(&error));
+        }
     }
 }
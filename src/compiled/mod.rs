@@ -0,0 +1,628 @@
+use crate::database::{LookupError, PasswordDatabase};
+use crate::haveibeenpwned::{CreateInstanceError, FormatErrorKind};
+use crate::{decode_hex, encode_hex, HashDigest, HashType, PasswordHashEntry};
+use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::{remove_file, rename, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Error, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The magic bytes every compiled password database starts with.
+const MAGIC_BYTES: &[u8; 8] = b"PWNEDRS\0";
+
+/// The version of the format which is written by the [CompiledDatabaseWriter](struct.CompiledDatabaseWriter.html).
+const FORMAT_VERSION: u16 = 1;
+
+/// The size of the header in front of the first record.
+const HEADER_SIZE: u64 = 64;
+
+/// The number of bytes used for storing the occurrence count of a record.
+const OCCURRENCES_SIZE: usize = 4;
+
+/// The possible errors which can occur while compiling a password database.
+#[derive(Debug)]
+pub enum CompileError {
+    /// There was a generic IO error.
+    Io(Error),
+    /// The hash of an entry is not a valid hash of the database's hash type.
+    InvalidHash,
+    /// The entries were not supplied in ascending order of their hashes.
+    UnsortedEntries,
+    /// The occurrence count of an entry does not fit into the record.
+    OccurrencesTooLarge,
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            CompileError::Io(ref err) => write!(f, "IO error: {}", err),
+            CompileError::InvalidHash => write!(
+                f,
+                "the hash of an entry does not match the hash type of the database"
+            ),
+            CompileError::UnsortedEntries => {
+                write!(f, "the entries are not ordered by their hashes")
+            }
+            CompileError::OccurrencesTooLarge => write!(
+                f,
+                "the occurrence count of an entry is too large to be stored"
+            ),
+        }
+    }
+}
+
+pub(crate) fn hash_type_to_byte(hash_type: HashType) -> u8 {
+    match hash_type {
+        HashType::Sha1 => 0,
+        HashType::Ntlm => 1,
+    }
+}
+
+pub(crate) fn hash_type_from_byte(byte: u8) -> Option<HashType> {
+    match byte {
+        0 => Some(HashType::Sha1),
+        1 => Some(HashType::Ntlm),
+        _ => None,
+    }
+}
+
+fn get_record_size(hash_type: HashType) -> usize {
+    hash_type.get_hash_length() / 2 + OCCURRENCES_SIZE
+}
+
+/// Check if the supplied file starts with the magic bytes of a compiled password database.
+pub fn is_compiled_database(path_to_file: &Path) -> bool {
+    let mut magic_bytes = [0; 8];
+    match File::open(path_to_file) {
+        Ok(mut file_handle) => {
+            file_handle.read_exact(&mut magic_bytes).is_ok() && magic_bytes == *MAGIC_BYTES
+        }
+        Err(_) => false,
+    }
+}
+
+/// This class writes password hash entries into a compiled password database.
+///
+/// A compiled database consists of a header of 64 bytes (magic bytes, format version, hash type,
+/// number of applied patches, number of records and the SHA-256 checksum of the source file)
+/// followed by fixed-width records.
+/// Each record contains the raw bytes of the hash and the occurrence count as 32 bit little endian
+/// number. All records have to be written in ascending order of their hashes.
+///
+/// The records are written into a temporary file next to the database which replaces the database
+/// as soon as the writer is finished. If the writer is dropped before, the temporary file is removed
+/// and an existing database stays untouched.
+pub struct CompiledDatabaseWriter {
+    output_file: BufWriter<File>,
+    database_path: PathBuf,
+    temporary_path: PathBuf,
+    finished: bool,
+    hash_type: HashType,
+    version: u32,
+    record_count: u64,
+    last_hash: Vec<u8>,
+}
+
+impl CompiledDatabaseWriter {
+    /// Create a new compiled password database at the supplied path. An existing file will be
+    /// replaced when the writer is finished.
+    pub fn create(
+        path_to_file: &Path,
+        hash_type: HashType,
+    ) -> Result<CompiledDatabaseWriter, CompileError> {
+        let mut temporary_name = path_to_file.as_os_str().to_owned();
+        temporary_name.push(".tmp");
+        let temporary_path = PathBuf::from(temporary_name);
+        let file_handle = match OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temporary_path)
+        {
+            Ok(handle) => handle,
+            Err(error) => return Err(CompileError::Io(error)),
+        };
+
+        // reserve the space for the header, it is written as soon as all records are known
+        let mut output_file = BufWriter::with_capacity(1024 * 1024 * 8, file_handle);
+        if let Err(error) = output_file.write_all(&[0; HEADER_SIZE as usize]) {
+            return Err(CompileError::Io(error));
+        }
+
+        Ok(CompiledDatabaseWriter {
+            output_file,
+            database_path: path_to_file.to_path_buf(),
+            temporary_path,
+            finished: false,
+            hash_type,
+            version: 0,
+            record_count: 0,
+            last_hash: Vec::new(),
+        })
+    }
+
+    /// Set the number of patches which were applied to the database (0 for a new database).
+    pub fn set_version(&mut self, version: u32) {
+        self.version = version;
+    }
+
+    /// Append the supplied entry as a new record to the database.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the entry is not a valid hash of the database's hash
+    /// type, if it is not greater than the last written entry or if it could not be written.
+    pub fn write_entry(&mut self, entry: &PasswordHashEntry) -> Result<(), CompileError> {
+        if entry.get_hash_type() != self.hash_type {
+            return Err(CompileError::InvalidHash);
+        }
+        let hash_bytes = match entry.get_hash_bytes() {
+            Some(bytes) => bytes,
+            None => return Err(CompileError::InvalidHash),
+        };
+        if hash_bytes <= self.last_hash {
+            return Err(CompileError::UnsortedEntries);
+        }
+        if entry.get_occurrences() > u64::from(u32::MAX) {
+            return Err(CompileError::OccurrencesTooLarge);
+        }
+
+        // write the record itself
+        let occurrences = entry.get_occurrences() as u32;
+        if let Err(error) = self
+            .output_file
+            .write_all(&hash_bytes)
+            .and_then(|_| self.output_file.write_all(&occurrences.to_le_bytes()))
+        {
+            return Err(CompileError::Io(error));
+        }
+        self.record_count += 1;
+        self.last_hash = hash_bytes;
+        Ok(())
+    }
+
+    /// Write the header of the database and move it to its final path. The supplied checksum is the
+    /// hexadecimal SHA-256 checksum of the file the database was compiled from.
+    ///
+    /// Returns the number of records written to the database.
+    pub fn finish(mut self, source_checksum: &str) -> Result<u64, CompileError> {
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(MAGIC_BYTES);
+        header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        header.push(hash_type_to_byte(self.hash_type));
+        header.resize(12, 0);
+        header.extend_from_slice(&self.version.to_le_bytes());
+        header.extend_from_slice(&self.record_count.to_le_bytes());
+        header.extend_from_slice(&decode_hex(source_checksum).unwrap_or_else(|| vec![0; 32]));
+        header.resize(HEADER_SIZE as usize, 0);
+
+        let write_result = self
+            .output_file
+            .flush()
+            .and_then(|_| self.output_file.seek(SeekFrom::Start(0)))
+            .and_then(|_| self.output_file.write_all(&header))
+            .and_then(|_| self.output_file.flush())
+            .and_then(|_| rename(&self.temporary_path, &self.database_path));
+        match write_result {
+            Ok(_) => {
+                self.finished = true;
+                Ok(self.record_count)
+            }
+            Err(error) => Err(CompileError::Io(error)),
+        }
+    }
+}
+
+impl Drop for CompiledDatabaseWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = remove_file(&self.temporary_path);
+        }
+    }
+}
+
+/// This class can be used to look up password hashes in a compiled password database by a binary
+/// search over the fixed-width records.
+pub struct CompiledDatabase {
+    file_handle: Mutex<File>,
+    hash_type: HashType,
+    version: u32,
+    record_count: u64,
+    source_checksum: String,
+}
+
+impl CompiledDatabase {
+    /// Open a compiled password database and validate its header.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    ///  * The file does not exist or cannot be read.
+    ///  * The file is not a compiled password database or has an unsupported version.
+    ///  * The size of the file does not match the number of records stored in the header.
+    ///
+    /// # Example
+    /// ```
+    /// use pwned_rs::compiled::CompiledDatabase;
+    /// use std::path::Path;
+    ///
+    /// match CompiledDatabase::from_file(Path::new("/path/to/the/compiled/database.bin")) {
+    ///     Ok(instance) => println!("The database contains {} hashes", instance.get_record_count()),
+    ///     Err(error) => println!("Could not get an instance, the error was: {}", error)
+    /// }
+    /// ```
+    pub fn from_file(path_to_file: &Path) -> Result<CompiledDatabase, CreateInstanceError> {
+        let mut file_handle = match File::open(path_to_file) {
+            Ok(handle) => handle,
+            Err(error) => return Err(CreateInstanceError::Io(error)),
+        };
+        let file_size = match file_handle.metadata() {
+            Ok(data) => data.len(),
+            Err(error) => return Err(CreateInstanceError::Io(error)),
+        };
+
+        // read and validate the header of the file
+        let mut header = [0; HEADER_SIZE as usize];
+        if file_handle.read_exact(&mut header).is_err() || header[..8] != *MAGIC_BYTES {
+            return Err(CreateInstanceError::Format(
+                FormatErrorKind::NotACompiledDatabase,
+            ));
+        }
+        if u16::from_le_bytes([header[8], header[9]]) != FORMAT_VERSION {
+            return Err(CreateInstanceError::Format(
+                FormatErrorKind::UnsupportedVersion,
+            ));
+        }
+        let hash_type = match hash_type_from_byte(header[10]) {
+            Some(hash_type) => hash_type,
+            None => {
+                return Err(CreateInstanceError::Format(
+                    FormatErrorKind::UnknownHashType,
+                ))
+            }
+        };
+        let record_count = u64::from_le_bytes(header[16..24].try_into().unwrap());
+
+        // the file has to contain exactly the number of records stated in the header (a record
+        // count which overflows the file size can never match)
+        let record_size = get_record_size(hash_type) as u64;
+        let expected_size = record_count
+            .checked_mul(record_size)
+            .and_then(|records_size| records_size.checked_add(HEADER_SIZE));
+        if expected_size != Some(file_size) {
+            return Err(CreateInstanceError::Format(
+                FormatErrorKind::TruncatedDatabase,
+            ));
+        }
+
+        Ok(CompiledDatabase {
+            file_handle: Mutex::new(file_handle),
+            hash_type,
+            version: u32::from_le_bytes(header[12..16].try_into().unwrap()),
+            record_count,
+            source_checksum: encode_hex(&header[24..56]),
+        })
+    }
+
+    /// Get the type of the hashes stored in the database.
+    pub fn get_hash_type(&self) -> HashType {
+        self.hash_type
+    }
+
+    /// Get the number of patches which were applied to the database since it was compiled.
+    pub fn get_version(&self) -> u32 {
+        self.version
+    }
+
+    /// Get the number of password hashes stored in the database.
+    pub fn get_record_count(&self) -> u64 {
+        self.record_count
+    }
+
+    /// Get the hexadecimal SHA-256 checksum of the file the database was compiled from.
+    pub fn get_source_checksum(&self) -> String {
+        self.source_checksum.clone()
+    }
+}
+
+impl PasswordDatabase for CompiledDatabase {
+    fn occurrences(&self, hash: &HashDigest) -> Result<Option<u64>, LookupError> {
+        if hash.get_hash_type() != self.hash_type {
+            return Err(LookupError::HashTypeMismatch {
+                database: self.hash_type,
+                requested: hash.get_hash_type(),
+            });
+        }
+        let seeked_hash = hash.as_bytes();
+        let mut file_handle = match self.file_handle.lock() {
+            Ok(file_handle) => file_handle,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        // do a binary search over the fixed-width records
+        let record_size = get_record_size(self.hash_type);
+        let hash_size = record_size - OCCURRENCES_SIZE;
+        let mut record = vec![0; record_size];
+        let mut lower_bound = 0;
+        let mut upper_bound = self.record_count;
+        while lower_bound < upper_bound {
+            let mid = lower_bound + (upper_bound - lower_bound) / 2;
+            file_handle.seek(SeekFrom::Start(HEADER_SIZE + mid * record_size as u64))?;
+            file_handle.read_exact(&mut record)?;
+
+            match record[..hash_size].cmp(seeked_hash) {
+                Ordering::Equal => {
+                    let occurrences = u32::from_le_bytes(record[hash_size..].try_into().unwrap());
+                    return Ok(Some(u64::from(occurrences)));
+                }
+                Ordering::Less => lower_bound = mid + 1,
+                Ordering::Greater => upper_bound = mid,
+            }
+        }
+        Ok(None)
+    }
+
+    fn get_backend_name(&self) -> &'static str {
+        "compiled"
+    }
+}
+
+/// This class reads all records of a compiled password database in the order of their hashes.
+///
+/// The iterator stops at the first record which could not be read, the error can be taken with
+/// `take_error`.
+pub struct CompiledRecordReader {
+    reader: BufReader<File>,
+    hash_type: HashType,
+    remaining_records: u64,
+    error: Option<Error>,
+}
+
+impl CompiledRecordReader {
+    /// Open the compiled password database for reading all of its records. The header is
+    /// validated like it is done by [CompiledDatabase](struct.CompiledDatabase.html).
+    pub fn from_file(path_to_file: &Path) -> Result<CompiledRecordReader, CreateInstanceError> {
+        let database = CompiledDatabase::from_file(path_to_file)?;
+        let mut file_handle = match File::open(path_to_file) {
+            Ok(handle) => handle,
+            Err(error) => return Err(CreateInstanceError::Io(error)),
+        };
+        if let Err(error) = file_handle.seek(SeekFrom::Start(HEADER_SIZE)) {
+            return Err(CreateInstanceError::Io(error));
+        }
+
+        Ok(CompiledRecordReader {
+            reader: BufReader::with_capacity(1024 * 1024, file_handle),
+            hash_type: database.get_hash_type(),
+            remaining_records: database.get_record_count(),
+            error: None,
+        })
+    }
+
+    /// Take the error which stopped the reader early. If all records were read, `None` is
+    /// returned.
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+}
+
+impl Iterator for CompiledRecordReader {
+    type Item = PasswordHashEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining_records == 0 || self.error.is_some() {
+            return None;
+        }
+
+        let record_size = get_record_size(self.hash_type);
+        let hash_size = record_size - OCCURRENCES_SIZE;
+        let mut record = vec![0; record_size];
+        if let Err(error) = self.reader.read_exact(&mut record) {
+            self.error = Some(error);
+            return None;
+        }
+        self.remaining_records -= 1;
+
+        let hash = encode_hex(&record[..hash_size]).to_uppercase();
+        match PasswordHashEntry::from_hash_with_type(&hash, self.hash_type) {
+            Ok(mut entry) => {
+                entry.occurrences =
+                    u64::from(u32::from_le_bytes(record[hash_size..].try_into().unwrap()));
+                Some(entry)
+            }
+            Err(error) => {
+                self.error = Some(Error::other(error.to_string()));
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::create_temp_path;
+    use std::str::FromStr;
+
+    fn compile_sample_database(file_name: &str, lines: &[&str]) -> std::path::PathBuf {
+        let database_path = create_temp_path(file_name);
+        let mut writer = CompiledDatabaseWriter::create(&database_path, HashType::Sha1).unwrap();
+        for line in lines {
+            writer
+                .write_entry(&PasswordHashEntry::from_str(line).unwrap())
+                .unwrap();
+        }
+        writer.finish(&"ab".repeat(32)).unwrap();
+        database_path
+    }
+
+    #[test]
+    fn looking_up_hashes_in_a_compiled_database_works() {
+        let database_path = compile_sample_database(
+            "compiled-lookup.bin",
+            &[
+                "0000000A1D4B746FAA3FD526FF6D5BC8052FDB38:16",
+                "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3730471",
+                "FDC625010C4BEB998E590924DF39B7E59298612D:2",
+            ],
+        );
+
+        let database = CompiledDatabase::from_file(&database_path).unwrap();
+        assert_eq!(3, database.get_record_count());
+        assert_eq!(HashType::Sha1, database.get_hash_type());
+        assert_eq!("ab".repeat(32), database.get_source_checksum());
+
+        let lookup = |password| {
+            database
+                .occurrences(&HashDigest::from_password(password, HashType::Sha1))
+                .unwrap()
+        };
+        assert_eq!(Some(3730471), lookup("password"));
+        assert_eq!(Some(2), lookup("sample_password"));
+        assert_eq!(None, lookup("unknown"));
+        let ntlm_digest = HashDigest::from_password("password", HashType::Ntlm);
+        assert_eq!(true, database.occurrences(&ntlm_digest).is_err());
+
+        let _ = std::fs::remove_file(database_path);
+    }
+
+    #[test]
+    fn reading_all_records_of_a_compiled_database_works() {
+        let lines = [
+            "0000000A1D4B746FAA3FD526FF6D5BC8052FDB38:16",
+            "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3730471",
+        ];
+        let database_path = compile_sample_database("compiled-records.bin", &lines);
+        assert_eq!(
+            0,
+            CompiledDatabase::from_file(&database_path)
+                .unwrap()
+                .get_version()
+        );
+
+        let mut reader = CompiledRecordReader::from_file(&database_path).unwrap();
+        let read_lines: Vec<String> = reader
+            .by_ref()
+            .map(|entry| entry.get_line_to_write())
+            .collect();
+        assert_eq!(
+            vec![format!("{}\n", lines[0]), format!("{}\n", lines[1])],
+            read_lines
+        );
+        assert_eq!(true, reader.take_error().is_none());
+
+        let mut writer = CompiledDatabaseWriter::create(&database_path, HashType::Sha1).unwrap();
+        writer.set_version(3);
+        writer.finish("").unwrap();
+        assert_eq!(
+            3,
+            CompiledDatabase::from_file(&database_path)
+                .unwrap()
+                .get_version()
+        );
+
+        let _ = std::fs::remove_file(database_path);
+    }
+
+    #[test]
+    fn an_unfinished_writer_keeps_the_existing_database() {
+        let database_path = compile_sample_database(
+            "compiled-unfinished.bin",
+            &["0000000A1D4B746FAA3FD526FF6D5BC8052FDB38:16"],
+        );
+        let mut temporary_name = database_path.as_os_str().to_owned();
+        temporary_name.push(".tmp");
+        let temporary_path = PathBuf::from(temporary_name);
+        assert_eq!(false, temporary_path.exists());
+
+        let mut writer = CompiledDatabaseWriter::create(&database_path, HashType::Sha1).unwrap();
+        writer
+            .write_entry(
+                &PasswordHashEntry::from_str("0000000CAEF405439D57847A8657218C618160B2:15")
+                    .unwrap(),
+            )
+            .unwrap();
+        assert_eq!(true, temporary_path.exists());
+        drop(writer);
+
+        assert_eq!(false, temporary_path.exists());
+        let database = CompiledDatabase::from_file(&database_path).unwrap();
+        assert_eq!(1, database.get_record_count());
+        let digest =
+            HashDigest::from_hex("0000000A1D4B746FAA3FD526FF6D5BC8052FDB38", HashType::Sha1)
+                .unwrap();
+        assert_eq!(Some(16), database.occurrences(&digest).unwrap());
+    }
+
+    #[test]
+    fn writing_unsorted_entries_is_handled_correctly() {
+        let database_path = create_temp_path("compiled-unsorted.bin");
+        let mut writer = CompiledDatabaseWriter::create(&database_path, HashType::Sha1).unwrap();
+        let first = PasswordHashEntry::from_password("sample_password");
+        let second = PasswordHashEntry::from_password("password");
+
+        assert_eq!(true, writer.write_entry(&first).is_ok());
+        let result = writer.write_entry(&second);
+        assert_eq!(true, matches!(result, Err(CompileError::UnsortedEntries)));
+
+        let _ = std::fs::remove_file(database_path);
+    }
+
+    #[test]
+    fn opening_a_compiled_database_with_an_unknown_hash_type_fails() {
+        let database_path = compile_sample_database(
+            "compiled-unknown-hash-type.bin",
+            &["5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3730471"],
+        );
+        let mut file_handle = OpenOptions::new().write(true).open(&database_path).unwrap();
+        file_handle.seek(SeekFrom::Start(10)).unwrap();
+        file_handle.write_all(&[7]).unwrap();
+
+        let maybe_instance = CompiledDatabase::from_file(&database_path);
+        assert_eq!(
+            true,
+            matches!(
+                maybe_instance,
+                Err(CreateInstanceError::Format(
+                    FormatErrorKind::UnknownHashType
+                ))
+            )
+        );
+
+        let _ = std::fs::remove_file(database_path);
+    }
+
+    #[test]
+    fn opening_a_truncated_compiled_database_fails() {
+        let database_path = compile_sample_database(
+            "compiled-truncated.bin",
+            &["5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3730471"],
+        );
+        let file_handle = OpenOptions::new().write(true).open(&database_path).unwrap();
+        file_handle.set_len(HEADER_SIZE + 10).unwrap();
+
+        let maybe_instance = CompiledDatabase::from_file(&database_path);
+        assert_eq!(true, maybe_instance.is_err());
+        assert_eq!(
+            true,
+            maybe_instance
+                .err()
+                .unwrap()
+                .to_string()
+                .contains("does not match its header")
+        );
+
+        // a record count whose size does not fit into 64 bits must not overflow
+        let mut file_handle = OpenOptions::new().write(true).open(&database_path).unwrap();
+        file_handle.seek(SeekFrom::Start(16)).unwrap();
+        file_handle.write_all(&u64::MAX.to_le_bytes()).unwrap();
+        let maybe_instance = CompiledDatabase::from_file(&database_path);
+        assert_eq!(true, maybe_instance.is_err());
+
+        let _ = std::fs::remove_file(database_path);
+    }
+}
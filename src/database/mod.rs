@@ -0,0 +1,136 @@
+use crate::haveibeenpwned::{CreateInstanceError, FormatErrorKind};
+use crate::range::RangeError;
+use crate::{HashDigest, HashType};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::Error;
+use std::path::PathBuf;
+
+/// The possible errors which can occur while looking up a password hash in a database.
+#[derive(Debug)]
+pub enum LookupError {
+    /// There was a generic IO error.
+    Io(Error),
+    /// The data of the database does not have the expected format.
+    Format(FormatErrorKind),
+    /// The database contains hashes of another type than the one which was looked up.
+    HashTypeMismatch {
+        database: HashType,
+        requested: HashType,
+    },
+    /// The range API could not be queried or sent an invalid response.
+    Remote(RangeError),
+    /// The file which should contain the hash is listed in the manifest of the database, but it
+    /// does not exist.
+    MissingFile(PathBuf),
+    /// The size of the file which should contain the hash does not match the manifest of the
+    /// database, e.g. since it was truncated.
+    SizeMismatch(PathBuf),
+}
+
+impl Display for LookupError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            LookupError::Io(ref err) => write!(f, "IO error: {}", err),
+            LookupError::Format(ref err_kind) => {
+                write!(f, "Format error: {}", err_kind.to_string())
+            }
+            LookupError::HashTypeMismatch {
+                database,
+                requested,
+            } => write!(
+                f,
+                "cannot look up a {} hash in a database with {} hashes",
+                requested, database
+            ),
+            LookupError::Remote(ref err) => write!(f, "Range API error: {}", err),
+            LookupError::MissingFile(ref path) => write!(
+                f,
+                "the file {} is listed in the manifest of the database, but it is missing",
+                path.display()
+            ),
+            LookupError::SizeMismatch(ref path) => write!(
+                f,
+                "the size of the file {} does not match the manifest of the database",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl From<CreateInstanceError> for LookupError {
+    fn from(error: CreateInstanceError) -> Self {
+        match error {
+            CreateInstanceError::Io(err) => LookupError::Io(err),
+            CreateInstanceError::Format(err_kind) => LookupError::Format(err_kind),
+        }
+    }
+}
+
+impl From<RangeError> for LookupError {
+    fn from(error: RangeError) -> Self {
+        LookupError::Remote(error)
+    }
+}
+
+impl From<Error> for LookupError {
+    fn from(error: Error) -> Self {
+        LookupError::Io(error)
+    }
+}
+
+/// The outcome of a successful lookup of a password hash in a database.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LookupOutcome {
+    /// The hash is part of the database and occurred the contained number of times.
+    Found(u64),
+    /// The hash is not part of the database.
+    NotFound,
+}
+
+/// A password database in which the number of occurrences of a password hash can be looked up.
+///
+/// All backends (the original ordered file, the optimized prefix folder, the compiled database
+/// and the in-memory reader) implement this trait, so they can be used interchangeably.
+///
+/// # Example
+/// ```
+/// use pwned_rs::database::PasswordDatabase;
+/// use pwned_rs::ordered::DivideAndConquerLookup;
+/// use pwned_rs::{HashDigest, HashType};
+/// use std::path::Path;
+///
+/// fn is_pwned(database: &dyn PasswordDatabase, password: &str) -> bool {
+///     let digest = HashDigest::from_password(password, HashType::Sha1);
+///     matches!(database.occurrences(&digest), Ok(Some(_)))
+/// }
+///
+/// if let Ok(database) = DivideAndConquerLookup::from_file(Path::new("/path/to/the/hash/file.txt")) {
+///     println!("The password was found: {}", is_pwned(&database, "password"));
+/// }
+/// ```
+pub trait PasswordDatabase {
+    /// Get how often the supplied password hash occurred in password breaches. If the hash is not
+    /// part of the database, `Ok(None)` is returned. If the database could not be searched, an
+    /// error is returned instead.
+    fn occurrences(&self, hash: &HashDigest) -> Result<Option<u64>, LookupError>;
+
+    /// Get a short name of the backend (e.g. `ordered-file`), which is used for reporting which
+    /// kind of database answered a lookup.
+    fn get_backend_name(&self) -> &'static str;
+
+    /// Get the IDs of the sources the supplied password hash was found in, if the database was
+    /// merged from several tagged sources (see [SourceMerge](../merge/struct.SourceMerge.html)).
+    /// Databases without tags (and hashes which are not part of the database) return an empty list.
+    fn sources(&self, _hash: &HashDigest) -> Result<Vec<String>, LookupError> {
+        Ok(Vec::new())
+    }
+
+    /// Look up the supplied password hash like [occurrences](#tymethod.occurrences), but report
+    /// the result as a [LookupOutcome](enum.LookupOutcome.html).
+    fn lookup(&self, hash: &HashDigest) -> Result<LookupOutcome, LookupError> {
+        match self.occurrences(hash)? {
+            Some(count) => Ok(LookupOutcome::Found(count)),
+            None => Ok(LookupOutcome::NotFound),
+        }
+    }
+}
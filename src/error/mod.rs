@@ -0,0 +1,143 @@
+use crate::compiled::CompileError;
+use crate::database::LookupError;
+use crate::export::{ExportError, ExportFormat};
+use crate::filter::FilterError;
+use crate::haveibeenpwned::CreateInstanceError;
+use crate::manifest::VerificationError;
+use crate::patch::PatchError;
+use crate::range::RangeError;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::Error;
+
+/// The errors which can occur while processing the password databases. The subcommands return them
+/// instead of terminating the process, so the caller decides how to deal with them.
+#[derive(Debug)]
+pub enum PwnedError {
+    /// A required argument is missing or its value is not valid.
+    InvalidArgument(String),
+    /// There was a generic IO error. The description tells what was done when it happened.
+    Io(String, Error),
+    /// A line of a password file could not be parsed.
+    Format {
+        /// The number of the line (starting with 1).
+        line_number: u64,
+        /// The position of the first byte of the line in the (decompressed) file.
+        byte_offset: u64,
+        /// The reason why the line could not be parsed.
+        reason: String,
+    },
+    /// The hashes of a password file are not in ascending order, but the operation requires it.
+    UnsortedInput {
+        /// The number of the first line (starting with 1) which is out of order.
+        line_number: u64,
+        /// The position of the first byte of the line in the (decompressed) file.
+        byte_offset: u64,
+    },
+    /// A password database or filter could not be opened. The description tells which one.
+    Database(String, CreateInstanceError),
+    /// A password hash could not be looked up. This is never used for hashes which are just not
+    /// part of the database.
+    Lookup(LookupError),
+    /// The optimized database is incomplete or was modified after it was created.
+    CorruptDatabase(Vec<VerificationError>),
+    /// A compiled database could not be written.
+    Compile(CompileError),
+    /// A password filter could not be built or written.
+    Filter(FilterError),
+    /// The export of a password manager could not be read.
+    Export(ExportFormat, ExportError),
+    /// A patch could not be created, read or applied.
+    Patch(PatchError),
+    /// The range API could not be queried or sent an invalid response.
+    Range(RangeError),
+}
+
+impl Display for PwnedError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            PwnedError::InvalidArgument(ref description) => write!(f, "{}", description),
+            PwnedError::Io(ref description, ref err) => {
+                write!(f, "{}. The error was: {}", description, err)
+            }
+            PwnedError::Format {
+                line_number,
+                byte_offset,
+                ref reason,
+            } => write!(
+                f,
+                "Could not parse line {} (at byte {}) of the password file: {}",
+                line_number, byte_offset, reason
+            ),
+            PwnedError::UnsortedInput {
+                line_number,
+                byte_offset,
+            } => write!(
+                f,
+                "The password file is not ordered by hash, line {} (at byte {}) is out of order",
+                line_number, byte_offset
+            ),
+            PwnedError::Database(ref description, ref err) => {
+                write!(f, "{}. The error was: {}", description, err)
+            }
+            PwnedError::Lookup(ref err) => write!(
+                f,
+                "Could not look up the password in the database. The error was: {}",
+                err
+            ),
+            PwnedError::CorruptDatabase(ref errors) => write!(
+                f,
+                "The database is corrupted, {} problems were found. Please run the verify subcommand or optimize the password file again",
+                errors.len()
+            ),
+            PwnedError::Compile(ref err) => write!(
+                f,
+                "Could not write the compiled database. The error was: {}",
+                err
+            ),
+            PwnedError::Filter(ref err) => {
+                write!(f, "Could not write the filter. The error was: {}", err)
+            }
+            PwnedError::Export(format, ref err) => write!(
+                f,
+                "Could not read the {} export of the password manager. The error was: {}",
+                format, err
+            ),
+            PwnedError::Patch(ref err) => {
+                write!(f, "Could not apply the patch. The error was: {}", err)
+            }
+            PwnedError::Range(ref err) => {
+                write!(f, "Could not query the range API. The error was: {}", err)
+            }
+        }
+    }
+}
+
+impl From<LookupError> for PwnedError {
+    fn from(error: LookupError) -> Self {
+        PwnedError::Lookup(error)
+    }
+}
+
+impl From<CompileError> for PwnedError {
+    fn from(error: CompileError) -> Self {
+        PwnedError::Compile(error)
+    }
+}
+
+impl From<FilterError> for PwnedError {
+    fn from(error: FilterError) -> Self {
+        PwnedError::Filter(error)
+    }
+}
+
+impl From<PatchError> for PwnedError {
+    fn from(error: PatchError) -> Self {
+        PwnedError::Patch(error)
+    }
+}
+
+impl From<RangeError> for PwnedError {
+    fn from(error: RangeError) -> Self {
+        PwnedError::Range(error)
+    }
+}
@@ -0,0 +1,420 @@
+use crate::{HashType, PasswordHashEntry};
+use serde_json::Value;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::{read_to_string, File};
+use std::io::{Error, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+/// The names of the CSV columns (in lower case) which contain the fields of an entry. They cover
+/// the exports of Bitwarden, KeePass, KeePassXC, 1Password, Chromium and Firefox.
+const CSV_TITLE_COLUMNS: &[&str] = &["title", "name", "account"];
+const CSV_URL_COLUMNS: &[&str] = &["url", "website", "web site", "login_uri", "uri"];
+const CSV_USERNAME_COLUMNS: &[&str] = &["username", "user name", "login name", "login_username"];
+const CSV_PASSWORD_COLUMNS: &[&str] = &["password", "login_password"];
+
+/// The type of a login item in a Bitwarden export.
+const BITWARDEN_LOGIN_TYPE: u64 = 1;
+
+/// The formats of the password manager exports which can be read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    /// The unencrypted JSON export of Bitwarden.
+    BitwardenJson,
+    /// The XML export of KeePass (1.x and 2.x) and KeePassXC.
+    KeePassXml,
+    /// A CSV export with a header line (Bitwarden, KeePass, 1Password, Chromium or Firefox).
+    Csv,
+}
+
+impl ExportFormat {
+    /// Guess the format of an export from the extension of its file name.
+    pub fn from_path(path: &Path) -> Option<ExportFormat> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "json" => Some(ExportFormat::BitwardenJson),
+            "xml" => Some(ExportFormat::KeePassXml),
+            "csv" => Some(ExportFormat::Csv),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "bitwarden" => Ok(ExportFormat::BitwardenJson),
+            "keepass-xml" => Ok(ExportFormat::KeePassXml),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(format!("The export format {} is not supported.", value)),
+        }
+    }
+}
+
+impl Display for ExportFormat {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            ExportFormat::BitwardenJson => write!(f, "bitwarden"),
+            ExportFormat::KeePassXml => write!(f, "keepass-xml"),
+            ExportFormat::Csv => write!(f, "csv"),
+        }
+    }
+}
+
+/// The errors which can occur while reading an export. None of them contains the content of the
+/// export, so they can be logged without revealing a password.
+#[derive(Debug)]
+pub enum ExportError {
+    /// There was a generic IO error.
+    Io(Error),
+    /// The CSV file could not be parsed.
+    Csv(csv::Error),
+    /// The JSON file could not be parsed.
+    Json(serde_json::Error),
+    /// The XML file could not be parsed.
+    Xml(roxmltree::Error),
+    /// The header of the CSV file does not name a password column.
+    MissingPasswordColumn,
+    /// The file could be parsed, but it does not have the structure of the selected format.
+    UnexpectedStructure(&'static str),
+}
+
+impl Display for ExportError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            ExportError::Io(ref err) => write!(f, "IO error: {}", err),
+            ExportError::Csv(ref err) => write!(f, "invalid CSV: {}", err),
+            ExportError::Json(ref err) => write!(f, "invalid JSON: {}", err),
+            ExportError::Xml(ref err) => write!(f, "invalid XML: {}", err),
+            ExportError::MissingPasswordColumn => {
+                write!(
+                    f,
+                    "the header of the CSV file does not contain a password column"
+                )
+            }
+            ExportError::UnexpectedStructure(description) => write!(f, "{}", description),
+        }
+    }
+}
+
+/// A single entry of a password manager export. The password is hashed as soon as it was read,
+/// so the plaintext is never stored in it.
+pub struct ExportedEntry {
+    title: String,
+    url: String,
+    username: String,
+    password_hash: PasswordHashEntry,
+}
+
+impl ExportedEntry {
+    fn new(
+        title: &str,
+        url: &str,
+        username: &str,
+        password: &str,
+        hash_type: HashType,
+    ) -> ExportedEntry {
+        ExportedEntry {
+            title: title.trim().to_string(),
+            url: url.trim().to_string(),
+            username: username.trim().to_string(),
+            password_hash: PasswordHashEntry::from_password_with_type(password, hash_type),
+        }
+    }
+
+    pub fn get_title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn get_url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn get_username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn get_password_hash(&self) -> &PasswordHashEntry {
+        &self.password_hash
+    }
+
+    /// Get a description of the entry which tells the user which entry is meant, built from the
+    /// title, the username and the URL (as far as they are known).
+    pub fn get_label(&self) -> String {
+        let name = if self.title.is_empty() {
+            &self.url
+        } else {
+            &self.title
+        };
+        let mut label = name.to_string();
+        if !self.username.is_empty() {
+            label = format!("{} ({})", label, self.username);
+        }
+        if !self.url.is_empty() && name != &self.url {
+            label = format!("{} at {}", label, self.url);
+        }
+        label
+    }
+}
+
+/// Read all entries with a password from the supplied export.
+pub fn read_export(
+    path: &Path,
+    format: ExportFormat,
+    hash_type: HashType,
+) -> Result<Vec<ExportedEntry>, ExportError> {
+    match format {
+        ExportFormat::Csv => match File::open(path) {
+            Ok(file_handle) => parse_csv_export(file_handle, hash_type),
+            Err(error) => Err(ExportError::Io(error)),
+        },
+        ExportFormat::BitwardenJson => match read_to_string(path) {
+            Ok(content) => parse_bitwarden_export(&content, hash_type),
+            Err(error) => Err(ExportError::Io(error)),
+        },
+        ExportFormat::KeePassXml => match read_to_string(path) {
+            Ok(content) => parse_keepass_export(&content, hash_type),
+            Err(error) => Err(ExportError::Io(error)),
+        },
+    }
+}
+
+/// Find the index of the first column of the header which has one of the supplied names. Some
+/// password managers start the file with a byte order mark, which is ignored.
+fn find_column(header: &csv::StringRecord, names: &[&str]) -> Option<usize> {
+    header.iter().position(|column| {
+        let column = column.trim_start_matches('\u{feff}').trim().to_lowercase();
+        names.contains(&column.as_str())
+    })
+}
+
+/// Parse a CSV export. The columns are identified by the names in the header line, so the
+/// exports of the different password managers can be read in the same way.
+pub fn parse_csv_export<R: Read>(
+    reader: R,
+    hash_type: HashType,
+) -> Result<Vec<ExportedEntry>, ExportError> {
+    let mut csv_reader = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+    let header = csv_reader.headers().map_err(ExportError::Csv)?.clone();
+    let password_column = match find_column(&header, CSV_PASSWORD_COLUMNS) {
+        Some(index) => index,
+        None => return Err(ExportError::MissingPasswordColumn),
+    };
+    let title_column = find_column(&header, CSV_TITLE_COLUMNS);
+    let url_column = find_column(&header, CSV_URL_COLUMNS);
+    let username_column = find_column(&header, CSV_USERNAME_COLUMNS);
+
+    let mut entries = Vec::new();
+    for record in csv_reader.records() {
+        let record = record.map_err(ExportError::Csv)?;
+        let get_field = |column: Option<usize>| column.and_then(|index| record.get(index));
+        let password = get_field(Some(password_column)).unwrap_or_default();
+        if password.is_empty() {
+            continue;
+        }
+        entries.push(ExportedEntry::new(
+            get_field(title_column).unwrap_or_default(),
+            get_field(url_column).unwrap_or_default(),
+            get_field(username_column).unwrap_or_default(),
+            password,
+            hash_type,
+        ));
+    }
+    Ok(entries)
+}
+
+/// Parse the unencrypted JSON export of Bitwarden. Only the login items with a password are
+/// returned.
+pub fn parse_bitwarden_export(
+    content: &str,
+    hash_type: HashType,
+) -> Result<Vec<ExportedEntry>, ExportError> {
+    // the values are read without a schema, so a type error can never quote a password
+    let export: Value = serde_json::from_str(content).map_err(ExportError::Json)?;
+    if export.get("encrypted").and_then(Value::as_bool) == Some(true) {
+        return Err(ExportError::UnexpectedStructure(
+            "the Bitwarden export is encrypted, please export it unencrypted",
+        ));
+    }
+    let items = match export.get("items").and_then(Value::as_array) {
+        Some(items) => items,
+        None => {
+            return Err(ExportError::UnexpectedStructure(
+                "the Bitwarden export does not contain a list of items",
+            ))
+        }
+    };
+
+    let mut entries = Vec::new();
+    for item in items {
+        if item.get("type").and_then(Value::as_u64) != Some(BITWARDEN_LOGIN_TYPE) {
+            continue;
+        }
+        let login = match item.get("login") {
+            Some(login) => login,
+            None => continue,
+        };
+        let password = login
+            .get("password")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        if password.is_empty() {
+            continue;
+        }
+        let url = login
+            .get("uris")
+            .and_then(Value::as_array)
+            .and_then(|uris| uris.first())
+            .and_then(|uri| uri.get("uri"))
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        entries.push(ExportedEntry::new(
+            item.get("name").and_then(Value::as_str).unwrap_or_default(),
+            url,
+            login
+                .get("username")
+                .and_then(Value::as_str)
+                .unwrap_or_default(),
+            password,
+            hash_type,
+        ));
+    }
+    Ok(entries)
+}
+
+/// Get the text of the first child element with the supplied name.
+fn get_child_text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> &'a str {
+    node.children()
+        .find(|child| child.has_tag_name(name))
+        .and_then(|child| child.text())
+        .unwrap_or_default()
+}
+
+/// Parse the XML export of KeePass 2.x / KeePassXC (`Entry` elements with `String` key-value
+/// pairs) or KeePass 1.x (`pwentry` elements). Old versions of an entry are skipped.
+pub fn parse_keepass_export(
+    content: &str,
+    hash_type: HashType,
+) -> Result<Vec<ExportedEntry>, ExportError> {
+    let document = roxmltree::Document::parse(content).map_err(ExportError::Xml)?;
+
+    let mut entries = Vec::new();
+    for node in document.descendants() {
+        let (title, url, username, password) = if node.has_tag_name("Entry") {
+            if node
+                .parent_element()
+                .is_some_and(|parent| parent.has_tag_name("History"))
+            {
+                continue;
+            }
+            let (mut title, mut url, mut username, mut password) = ("", "", "", "");
+            for field in node.children().filter(|child| child.has_tag_name("String")) {
+                let value = get_child_text(field, "Value");
+                match get_child_text(field, "Key") {
+                    "Title" => title = value,
+                    "URL" => url = value,
+                    "UserName" => username = value,
+                    "Password" => password = value,
+                    _ => {}
+                }
+            }
+            (title, url, username, password)
+        } else if node.has_tag_name("pwentry") {
+            (
+                get_child_text(node, "title"),
+                get_child_text(node, "url"),
+                get_child_text(node, "username"),
+                get_child_text(node, "password"),
+            )
+        } else {
+            continue;
+        };
+
+        if !password.is_empty() {
+            entries.push(ExportedEntry::new(
+                title, url, username, password, hash_type,
+            ));
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing_csv_exports_of_different_password_managers_works() {
+        let chromium_export = "name,url,username,password,note\n\
+                               Example,https://example.com,alice,password,\n\
+                               Empty,https://empty.com,bob,,\n";
+        let entries = parse_csv_export(chromium_export.as_bytes(), HashType::Sha1).unwrap();
+        assert_eq!(1, entries.len());
+        assert_eq!(
+            "Example (alice) at https://example.com",
+            entries[0].get_label()
+        );
+        assert_eq!(
+            "5baa61e4c9b93f3f0682250b6cf8331b7ee68fd8",
+            entries[0].get_password_hash().get_hash()
+        );
+
+        let keepass_export = "\"Account\",\"Login Name\",\"Password\",\"Web Site\",\"Comments\"\n\
+                              \"Mail\",\"carol\",\"pass,\"\"word\",\"\",\"multi\nline\"\n";
+        let entries = parse_csv_export(keepass_export.as_bytes(), HashType::Ntlm).unwrap();
+        assert_eq!(1, entries.len());
+        assert_eq!("Mail (carol)", entries[0].get_label());
+        assert_eq!(
+            PasswordHashEntry::from_password_with_type("pass,\"word", HashType::Ntlm).get_hash(),
+            entries[0].get_password_hash().get_hash()
+        );
+
+        assert_eq!(
+            true,
+            parse_csv_export("url,username\n".as_bytes(), HashType::Sha1).is_err()
+        );
+    }
+
+    #[test]
+    fn parsing_a_bitwarden_export_works() {
+        let export = r#"{"encrypted":false,"items":[
+            {"type":1,"name":"Example","login":{"username":"alice","password":"password","uris":[{"uri":"https://example.com"}]}},
+            {"type":2,"name":"A secure note","notes":"password"},
+            {"type":1,"name":"No password","login":{"username":"bob","password":null}}
+        ]}"#;
+
+        let entries = parse_bitwarden_export(export, HashType::Sha1).unwrap();
+        assert_eq!(1, entries.len());
+        assert_eq!("Example", entries[0].get_title());
+        assert_eq!("alice", entries[0].get_username());
+        assert_eq!("https://example.com", entries[0].get_url());
+        assert_eq!(
+            true,
+            parse_bitwarden_export("{\"encrypted\":true}", HashType::Sha1).is_err()
+        );
+    }
+
+    #[test]
+    fn parsing_a_keepass_export_skips_the_history() {
+        let export = "<KeePassFile><Root><Group><Entry>\
+            <String><Key>Title</Key><Value>Example</Value></String>\
+            <String><Key>UserName</Key><Value>alice</Value></String>\
+            <String><Key>Password</Key><Value>password</Value></String>\
+            <History><Entry><String><Key>Password</Key><Value>old</Value></String></Entry></History>\
+            </Entry></Group></Root></KeePassFile>";
+
+        let entries = parse_keepass_export(export, HashType::Sha1).unwrap();
+        assert_eq!(1, entries.len());
+        assert_eq!("Example (alice)", entries[0].get_label());
+
+        let legacy_export = "<pwlist><pwentry><title>Mail</title><username>bob</username>\
+            <url>https://mail.example.com</url><password>password</password></pwentry></pwlist>";
+        let entries = parse_keepass_export(legacy_export, HashType::Sha1).unwrap();
+        assert_eq!(
+            "Mail (bob) at https://mail.example.com",
+            entries[0].get_label()
+        );
+    }
+}
@@ -0,0 +1,393 @@
+use crate::compiled::{hash_type_from_byte, hash_type_to_byte};
+use crate::database::LookupError;
+use crate::haveibeenpwned::{CreateInstanceError, FormatErrorKind};
+use crate::{HashDigest, HashType, PasswordHashEntry};
+use memmap2::Mmap;
+use std::convert::TryInto;
+use std::f64::consts::LN_2;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Error, Write};
+use std::path::Path;
+
+/// The magic bytes every password filter starts with.
+const MAGIC_BYTES: &[u8; 8] = b"PWNEDBF\0";
+
+/// The version of the format which is written by the [BloomFilterBuilder](struct.BloomFilterBuilder.html).
+const FORMAT_VERSION: u16 = 1;
+
+/// The size of the header in front of the bits of the filter.
+const HEADER_SIZE: usize = 64;
+
+/// The possible errors which can occur while building a password filter.
+#[derive(Debug)]
+pub enum FilterError {
+    /// There was a generic IO error.
+    Io(Error),
+    /// The hash of an entry is not a valid hash of the filter's hash type.
+    InvalidHash,
+}
+
+impl Display for FilterError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            FilterError::Io(ref err) => write!(f, "IO error: {}", err),
+            FilterError::InvalidHash => write!(
+                f,
+                "the hash of an entry does not match the hash type of the filter"
+            ),
+        }
+    }
+}
+
+/// The answer of a password filter for a password hash.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterLookupResult {
+    /// The password hash is not part of the filter, so the password was not found in any breach.
+    DefinitelyNotPwned,
+    /// The password hash is most likely part of the filter. With the false positive rate of the
+    /// filter, the password was reported although it was not found in any breach.
+    ProbablyPwned,
+}
+
+/// Get the number of bits and hash functions for a filter with the supplied number of entries and
+/// false positive rate.
+fn get_filter_dimensions(expected_entries: u64, false_positive_rate: f64) -> (u64, u8) {
+    let entries = expected_entries.max(1) as f64;
+    let number_of_bits = (-entries * false_positive_rate.ln() / (LN_2 * LN_2)).ceil();
+    let number_of_hash_functions = (number_of_bits / entries * LN_2).round().clamp(1.0, 32.0);
+    (
+        number_of_bits.max(8.0) as u64,
+        number_of_hash_functions as u8,
+    )
+}
+
+/// Get the positions of the bits which represent the supplied hash in a filter.
+///
+/// The hashes of the password databases are already uniformly distributed, so the first 16 bytes
+/// of the hash are used as two independent hash values which are combined by double hashing.
+fn get_bit_positions(
+    hash_bytes: &[u8],
+    number_of_bits: u64,
+    number_of_hash_functions: u8,
+) -> impl Iterator<Item = u64> {
+    let first_hash = u64::from_le_bytes(hash_bytes[0..8].try_into().unwrap());
+    let second_hash = u64::from_le_bytes(hash_bytes[8..16].try_into().unwrap());
+    (0..u64::from(number_of_hash_functions))
+        .map(move |index| first_hash.wrapping_add(index.wrapping_mul(second_hash)) % number_of_bits)
+}
+
+/// Get the raw bytes of the hash of the entry if it matches the hash type of the filter.
+fn get_filter_hash(entry: &PasswordHashEntry, hash_type: HashType) -> Option<Vec<u8>> {
+    if entry.get_hash_type() != hash_type {
+        return None;
+    }
+    entry.get_hash_bytes()
+}
+
+/// This class builds a Bloom filter of password hashes in memory and writes it into a file.
+///
+/// A filter file consists of a header of 64 bytes (magic bytes, format version, hash type, number
+/// of hash functions, number of bits, number of entries, minimal occurrence count and the false
+/// positive rate) followed by the bits of the filter.
+pub struct BloomFilterBuilder {
+    hash_type: HashType,
+    number_of_bits: u64,
+    number_of_hash_functions: u8,
+    false_positive_rate: f64,
+    minimal_occurrences: u64,
+    entry_count: u64,
+    bits: Vec<u8>,
+}
+
+impl BloomFilterBuilder {
+    /// Create an empty filter which is large enough for the expected number of entries to reach
+    /// the supplied false positive rate. Entries which occurred less often than the minimal
+    /// occurrence count are not added to the filter.
+    pub fn new(
+        hash_type: HashType,
+        expected_entries: u64,
+        false_positive_rate: f64,
+        minimal_occurrences: u64,
+    ) -> BloomFilterBuilder {
+        let (number_of_bits, number_of_hash_functions) =
+            get_filter_dimensions(expected_entries, false_positive_rate);
+        BloomFilterBuilder {
+            hash_type,
+            number_of_bits,
+            number_of_hash_functions,
+            false_positive_rate,
+            minimal_occurrences,
+            entry_count: 0,
+            bits: vec![0; number_of_bits.div_ceil(8) as usize],
+        }
+    }
+
+    /// Get the size of the filter in bytes (without the header).
+    pub fn get_size_in_bytes(&self) -> u64 {
+        self.bits.len() as u64
+    }
+
+    /// Get the number of entries which were added to the filter.
+    pub fn get_entry_count(&self) -> u64 {
+        self.entry_count
+    }
+
+    /// Add the supplied entry to the filter if it occurred at least as often as the minimal
+    /// occurrence count. Returns if the entry was added.
+    pub fn insert(&mut self, entry: &PasswordHashEntry) -> Result<bool, FilterError> {
+        let hash_bytes = match get_filter_hash(entry, self.hash_type) {
+            Some(bytes) => bytes,
+            None => return Err(FilterError::InvalidHash),
+        };
+        if entry.get_occurrences() < self.minimal_occurrences {
+            return Ok(false);
+        }
+
+        for position in get_bit_positions(
+            &hash_bytes,
+            self.number_of_bits,
+            self.number_of_hash_functions,
+        ) {
+            self.bits[(position / 8) as usize] |= 1 << (position % 8);
+        }
+        self.entry_count += 1;
+        Ok(true)
+    }
+
+    /// Write the filter into the supplied file. An existing file will be replaced.
+    pub fn write_to_file(&self, path_to_file: &Path) -> Result<(), FilterError> {
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(MAGIC_BYTES);
+        header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        header.push(hash_type_to_byte(self.hash_type));
+        header.push(self.number_of_hash_functions);
+        header.resize(16, 0);
+        header.extend_from_slice(&self.number_of_bits.to_le_bytes());
+        header.extend_from_slice(&self.entry_count.to_le_bytes());
+        header.extend_from_slice(&self.minimal_occurrences.to_le_bytes());
+        header.extend_from_slice(&self.false_positive_rate.to_le_bytes());
+        header.resize(HEADER_SIZE, 0);
+
+        let write_result = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path_to_file)
+            .and_then(|file_handle| {
+                let mut output_file = BufWriter::with_capacity(1024 * 1024 * 8, file_handle);
+                output_file.write_all(&header)?;
+                output_file.write_all(&self.bits)?;
+                output_file.flush()
+            });
+        match write_result {
+            Ok(_) => Ok(()),
+            Err(error) => Err(FilterError::Io(error)),
+        }
+    }
+}
+
+/// This class answers whether a password hash is part of a Bloom filter which was written by the
+/// [BloomFilterBuilder](struct.BloomFilterBuilder.html). The filter file is mapped into memory, so
+/// a lookup just reads the few pages which contain the bits of the hash.
+pub struct BloomFilter {
+    mapped_file: Mmap,
+    hash_type: HashType,
+    number_of_bits: u64,
+    number_of_hash_functions: u8,
+    entry_count: u64,
+    minimal_occurrences: u64,
+    false_positive_rate: f64,
+}
+
+impl BloomFilter {
+    /// Open a password filter and validate its header.
+    ///
+    /// # Example
+    /// ```
+    /// use pwned_rs::filter::BloomFilter;
+    /// use std::path::Path;
+    ///
+    /// match BloomFilter::from_file(Path::new("/path/to/the/filter.bin")) {
+    ///     Ok(instance) => println!("The filter contains {} hashes", instance.get_entry_count()),
+    ///     Err(error) => println!("Could not get an instance, the error was: {}", error)
+    /// }
+    /// ```
+    pub fn from_file(path_to_file: &Path) -> Result<BloomFilter, CreateInstanceError> {
+        let file_handle = match File::open(path_to_file) {
+            Ok(handle) => handle,
+            Err(error) => return Err(CreateInstanceError::Io(error)),
+        };
+
+        // the mapping is only valid as long as no one else modifies or truncates the file
+        let mapped_file = match unsafe { Mmap::map(&file_handle) } {
+            Ok(mapped_file) => mapped_file,
+            Err(error) => return Err(CreateInstanceError::Io(error)),
+        };
+
+        // read and validate the header of the file
+        if mapped_file.len() < HEADER_SIZE || mapped_file[..8] != *MAGIC_BYTES {
+            return Err(CreateInstanceError::Format(FormatErrorKind::NotAFilter));
+        }
+        let header = &mapped_file[..HEADER_SIZE];
+        if u16::from_le_bytes([header[8], header[9]]) != FORMAT_VERSION {
+            return Err(CreateInstanceError::Format(
+                FormatErrorKind::UnsupportedVersion,
+            ));
+        }
+        let hash_type = match hash_type_from_byte(header[10]) {
+            Some(hash_type) => hash_type,
+            None => {
+                return Err(CreateInstanceError::Format(
+                    FormatErrorKind::UnknownHashType,
+                ))
+            }
+        };
+        let number_of_hash_functions = header[11];
+        let number_of_bits = u64::from_le_bytes(header[16..24].try_into().unwrap());
+        let entry_count = u64::from_le_bytes(header[24..32].try_into().unwrap());
+        let minimal_occurrences = u64::from_le_bytes(header[32..40].try_into().unwrap());
+        let false_positive_rate = f64::from_le_bytes(header[40..48].try_into().unwrap());
+
+        // the file has to contain exactly the number of bits stated in the header
+        if number_of_bits == 0
+            || number_of_hash_functions == 0
+            || mapped_file.len() as u64 != HEADER_SIZE as u64 + number_of_bits.div_ceil(8)
+        {
+            return Err(CreateInstanceError::Format(
+                FormatErrorKind::TruncatedDatabase,
+            ));
+        }
+
+        Ok(BloomFilter {
+            mapped_file,
+            hash_type,
+            number_of_bits,
+            number_of_hash_functions,
+            entry_count,
+            minimal_occurrences,
+            false_positive_rate,
+        })
+    }
+
+    /// Get the type of the hashes stored in the filter.
+    pub fn get_hash_type(&self) -> HashType {
+        self.hash_type
+    }
+
+    /// Get the number of password hashes which were added to the filter.
+    pub fn get_entry_count(&self) -> u64 {
+        self.entry_count
+    }
+
+    /// Get the minimal number of occurrences a password hash needed to be added to the filter.
+    pub fn get_minimal_occurrences(&self) -> u64 {
+        self.minimal_occurrences
+    }
+
+    /// Get the false positive rate the filter was built for.
+    pub fn get_false_positive_rate(&self) -> f64 {
+        self.false_positive_rate
+    }
+
+    /// Check if the supplied password hash is part of the filter. A hash of another type than the
+    /// one of the filter cannot be checked, so an error is returned instead of a negative result.
+    pub fn lookup(&self, hash: &HashDigest) -> Result<FilterLookupResult, LookupError> {
+        if hash.get_hash_type() != self.hash_type {
+            return Err(LookupError::HashTypeMismatch {
+                database: self.hash_type,
+                requested: hash.get_hash_type(),
+            });
+        }
+
+        let bits = &self.mapped_file[HEADER_SIZE..];
+        let all_bits_set = get_bit_positions(
+            hash.as_bytes(),
+            self.number_of_bits,
+            self.number_of_hash_functions,
+        )
+        .all(|position| bits[(position / 8) as usize] & (1 << (position % 8)) != 0);
+        if all_bits_set {
+            Ok(FilterLookupResult::ProbablyPwned)
+        } else {
+            Ok(FilterLookupResult::DefinitelyNotPwned)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::create_temp_path;
+
+    #[test]
+    fn the_filter_dimensions_match_the_false_positive_rate() {
+        assert_eq!((9586, 7), get_filter_dimensions(1000, 0.01));
+        assert_eq!((14378, 10), get_filter_dimensions(1000, 0.001));
+    }
+
+    #[test]
+    fn looking_up_passwords_in_a_filter_works() {
+        let filter_path = create_temp_path("filter-test.bin");
+        let mut builder = BloomFilterBuilder::new(HashType::Sha1, 1000, 0.001, 2);
+        for index in 0..1000 {
+            let mut entry = PasswordHashEntry::from_password(&format!("password{}", index));
+            entry.occurrences = 2;
+            assert_eq!(true, builder.insert(&entry).unwrap());
+        }
+        let mut rare_entry = PasswordHashEntry::from_password("rare password");
+        rare_entry.occurrences = 1;
+        assert_eq!(false, builder.insert(&rare_entry).unwrap());
+        assert_eq!(
+            true,
+            builder
+                .insert(&PasswordHashEntry::from_password_with_type(
+                    "password",
+                    HashType::Ntlm
+                ))
+                .is_err()
+        );
+        builder.write_to_file(&filter_path).unwrap();
+
+        let filter = BloomFilter::from_file(&filter_path).unwrap();
+        assert_eq!(1000, filter.get_entry_count());
+        assert_eq!(2, filter.get_minimal_occurrences());
+        for index in 0..1000 {
+            let digest = HashDigest::from_password(&format!("password{}", index), HashType::Sha1);
+            assert_eq!(
+                FilterLookupResult::ProbablyPwned,
+                filter.lookup(&digest).unwrap()
+            );
+        }
+        let false_positives = (0..10000)
+            .map(|index| HashDigest::from_password(&format!("unknown{}", index), HashType::Sha1))
+            .filter(|digest| filter.lookup(digest).unwrap() == FilterLookupResult::ProbablyPwned)
+            .count();
+        assert_eq!(true, false_positives < 50);
+
+        // a hash of another type is never reported as not pwned
+        let ntlm_digest = HashDigest::from_password("password0", HashType::Ntlm);
+        assert_eq!(true, filter.lookup(&ntlm_digest).is_err());
+
+        let _ = std::fs::remove_file(filter_path);
+    }
+
+    #[test]
+    fn opening_a_truncated_filter_fails() {
+        let filter_path = create_temp_path("filter-truncated.bin");
+        BloomFilterBuilder::new(HashType::Ntlm, 100, 0.01, 0)
+            .write_to_file(&filter_path)
+            .unwrap();
+        let file_size = std::fs::metadata(&filter_path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&filter_path)
+            .unwrap()
+            .set_len(file_size - 1)
+            .unwrap();
+
+        assert_eq!(true, BloomFilter::from_file(&filter_path).is_err());
+
+        let _ = std::fs::remove_file(filter_path);
+    }
+}
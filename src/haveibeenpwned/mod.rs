@@ -1,10 +1,18 @@
-use crate::PasswordHashEntry;
-use log::{debug, error};
+use crate::database::{LookupError, PasswordDatabase};
+use crate::error::PwnedError;
+use crate::{compare_hashes, HashDigest, HashType, PasswordHashEntry};
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use flate2::bufread::MultiGzDecoder;
+use log::debug;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::fs::{File, OpenOptions};
-use std::io::{BufRead, BufReader, Error, Read};
+use std::io::{stdin, BufRead, BufReader, Error, ErrorKind, Read, Result as IoResult};
 use std::path::Path;
+use std::sync::{Arc, Mutex};
+use zstd::stream::read::Decoder as ZstdDecoder;
 
 /// The possible errors which can occur on instantiation of the [HaveIBeenPwnedParser](struct.HaveIBeenPwnedParser.html) class.
 #[derive(Debug)]
@@ -22,15 +30,39 @@ pub enum FormatErrorKind {
     NotATextFile,
     /// It seems that the format of at least one of the lines in the file is invalid.
     LineFormatNotCorrect,
+    /// It seems that the file is not a compiled (binary) password database.
+    NotACompiledDatabase,
+    /// The version of the compiled password database is not supported.
+    UnsupportedVersion,
+    /// The size of the compiled password database does not match the number of records in its header.
+    TruncatedDatabase,
+    /// The manifest of the optimized password database could not be parsed.
+    InvalidManifest,
+    /// It seems that the file is not a password filter.
+    NotAFilter,
+    /// The header of the compiled password database or filter names an unknown type of hashes.
+    UnknownHashType,
 }
 
 impl FormatErrorKind {
-    fn to_string(&self) -> &str {
+    pub(crate) fn to_string(&self) -> &str {
         match *self {
             FormatErrorKind::NotATextFile => "not a text file which can be parsed",
             FormatErrorKind::LineFormatNotCorrect => {
                 "format of lines does not match the required format"
             }
+            FormatErrorKind::NotACompiledDatabase => "not a compiled password database",
+            FormatErrorKind::UnsupportedVersion => {
+                "the version of the compiled password database is not supported"
+            }
+            FormatErrorKind::TruncatedDatabase => {
+                "the size of the compiled password database does not match its header"
+            }
+            FormatErrorKind::InvalidManifest => {
+                "the manifest of the optimized password database is invalid"
+            }
+            FormatErrorKind::NotAFilter => "not a password filter",
+            FormatErrorKind::UnknownHashType => "the type of the stored hashes is not known",
         }
     }
 }
@@ -46,15 +78,133 @@ impl Display for CreateInstanceError {
     }
 }
 
+/// Determine the type of the hashes based on the first line which can be read from the reader.
+///
+/// The reader is not advanced, so the first line can still be read afterwards. If the reader does
+/// not contain any data, the default hash type (SHA-1) is assumed.
+fn detect_hash_type_from_reader(reader: &mut dyn BufRead) -> Result<HashType, CreateInstanceError> {
+    let buffered_data = match reader.fill_buf() {
+        Ok(data) => data,
+        Err(error) => return Err(CreateInstanceError::Io(error)),
+    };
+    if buffered_data.is_empty() {
+        return Ok(HashType::Sha1);
+    }
+
+    // the hash is everything in front of the separator of the first line
+    let hash_length = match buffered_data.iter().position(|c| *c == b':') {
+        Some(position) => position,
+        None => {
+            return Err(CreateInstanceError::Format(
+                FormatErrorKind::LineFormatNotCorrect,
+            ))
+        }
+    };
+    match HashType::from_hash_length(hash_length) {
+        Some(hash_type) => Ok(hash_type),
+        None => Err(CreateInstanceError::Format(
+            FormatErrorKind::LineFormatNotCorrect,
+        )),
+    }
+}
+
+/// Determine the type of the hashes stored in the supplied password file by looking at its first line.
+///
+/// # Example
+/// ```
+/// use pwned_rs::haveibeenpwned::detect_hash_type;
+/// use std::path::Path;
+///
+/// match detect_hash_type(Path::new("/path/to/the/hash/file.txt")) {
+///     Ok(hash_type) => println!("The file contains {} hashes", hash_type),
+///     Err(error) => println!("Could not detect the hash type, the error was: {}", error)
+/// }
+/// ```
+pub fn detect_hash_type(path_to_file: &Path) -> Result<HashType, CreateInstanceError> {
+    match File::open(path_to_file) {
+        Ok(file_handle) => detect_hash_type_from_reader(&mut BufReader::new(file_handle)),
+        Err(error) => Err(CreateInstanceError::Io(error)),
+    }
+}
+
+/// The magic bytes at the beginning of gzip- and zstd-compressed files.
+const GZIP_MAGIC_BYTES: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC_BYTES: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// The path which selects the standard input instead of a password file.
+pub const STDIN_PATH: &str = "-";
+
+/// The size of the buffer for the (decompressed) password hashes.
+const MAXIMAL_BUFFER_SIZE: u64 = 1024 * 1024 * 128;
+
+/// The smallest buffer which is used, even if the password file is smaller.
+const MINIMAL_BUFFER_SIZE: u64 = 1024 * 64;
+
+/// The compression formats in which the password file can be supplied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompressionFormat {
+    Plain,
+    Gzip,
+    Zstd,
+}
+
+/// Determine the compression format of the data by looking at its first bytes.
+fn detect_compression_format(first_bytes: &[u8]) -> CompressionFormat {
+    if first_bytes.starts_with(&GZIP_MAGIC_BYTES) {
+        CompressionFormat::Gzip
+    } else if first_bytes.starts_with(&ZSTD_MAGIC_BYTES) {
+        CompressionFormat::Zstd
+    } else {
+        CompressionFormat::Plain
+    }
+}
+
+/// The number of (possibly compressed) bytes which were read from the source and their checksum.
+struct SourceStatistics {
+    consumed_bytes: u64,
+    hasher: Sha256,
+}
+
+/// A reader which calculates the SHA-256 checksum of all bytes which were read through it and
+/// counts them. The statistics are shared, since the reader is owned by the decompressor.
+struct ChecksumReader<R: Read> {
+    inner: R,
+    statistics: Arc<Mutex<SourceStatistics>>,
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let read_bytes = self.inner.read(buf)?;
+        if let Ok(mut statistics) = self.statistics.lock() {
+            statistics.hasher.input(&buf[..read_bytes]);
+            statistics.consumed_bytes += read_bytes as u64;
+        }
+        Ok(read_bytes)
+    }
+}
+
 /// This class can be used to parse the password files provided by https://haveibeenpwned.com.
 pub struct DatabaseIterator {
-    file_size: u64,
-    password_file: Option<BufReader<File>>,
+    file_size: Option<u64>,
+    hash_type: HashType,
+    end_reached: bool,
+    line_number: u64,
+    byte_offset: u64,
+    read_bytes: u64,
+    error: Option<PwnedError>,
+    statistics: Arc<Mutex<SourceStatistics>>,
+    password_file: Option<BufReader<Box<dyn Read + Send>>>,
+    check_order: bool,
+    previous_hash: String,
 }
 
 impl DatabaseIterator {
     /// Get a new instance of the file parsed based on the provided file path.
     ///
+    /// The file can be a plain text file or a gzip- or zstd-compressed one, the format is detected
+    /// by the first bytes of the file. If the path is `-`, the password hashes are read from the
+    /// standard input.
+    ///
     /// # Errors
     ///
     /// This function will return an error in the following situations, but is not
@@ -69,12 +219,16 @@ impl DatabaseIterator {
     /// ```
     /// use pwned_rs::haveibeenpwned::DatabaseIterator;
     ///
-    /// match DatabaseIterator::from_file("/path/to/the/hash/file.txt") {
+    /// match DatabaseIterator::from_file("/path/to/the/hash/file.txt.gz") {
     ///     Ok(instance) => println!("Got an instance of the file parser!"),
     ///     Err(error) => println!("Could not get an instance, the error was: {}", error)
     /// }
     /// ```
     pub fn from_file(path_to_file: &str) -> Result<DatabaseIterator, CreateInstanceError> {
+        if path_to_file == STDIN_PATH {
+            return DatabaseIterator::from_reader(Box::new(stdin()), None);
+        }
+
         // be sure that the file exists, if not we should return a proper error which the caller can deal with
         let file_meta_data = match std::fs::metadata(path_to_file) {
             Ok(data) => data,
@@ -82,24 +236,178 @@ impl DatabaseIterator {
         };
 
         // try to figure our how many entries are stored in the file
-        let file_reader = match OpenOptions::new()
+        match OpenOptions::new()
             .append(false)
             .create(false)
             .read(true)
-            .open(&path_to_file)
+            .open(path_to_file)
         {
-            Ok(file_handle) => BufReader::with_capacity(1024 * 1024 * 128, file_handle),
+            Ok(file_handle) => {
+                debug!("Reading the password hashes from {}", path_to_file);
+                DatabaseIterator::from_reader(Box::new(file_handle), Some(file_meta_data.len()))
+            }
+            Err(error) => Err(CreateInstanceError::Io(error)),
+        }
+    }
+
+    /// Get a new instance of the parser which reads the (possibly compressed) password hashes from
+    /// the supplied reader. The size of the data is used for reporting the progress, if it is known.
+    pub fn from_reader(
+        reader: Box<dyn Read + Send>,
+        file_size: Option<u64>,
+    ) -> Result<DatabaseIterator, CreateInstanceError> {
+        let statistics = Arc::new(Mutex::new(SourceStatistics {
+            consumed_bytes: 0,
+            hasher: Sha256::new(),
+        }));
+        let mut source_reader = BufReader::new(ChecksumReader {
+            inner: reader,
+            statistics: Arc::clone(&statistics),
+        });
+
+        // decompress the data if it starts with the magic bytes of a supported format
+        let compression_format = match source_reader.fill_buf() {
+            Ok(first_bytes) => detect_compression_format(first_bytes),
             Err(error) => return Err(CreateInstanceError::Io(error)),
         };
+        debug!(
+            "The password hashes are stored as {:?} data",
+            compression_format
+        );
+        let decompressed_reader: Box<dyn Read + Send> = match compression_format {
+            CompressionFormat::Plain => Box::new(source_reader),
+            CompressionFormat::Gzip => Box::new(MultiGzDecoder::new(source_reader)),
+            CompressionFormat::Zstd => match ZstdDecoder::with_buffer(source_reader) {
+                Ok(decoder) => Box::new(decoder),
+                Err(error) => return Err(CreateInstanceError::Io(error)),
+            },
+        };
+        // small files (like the ones of an optimized database) do not need the whole buffer
+        let buffer_size = file_size
+            .map(|size| size.clamp(MINIMAL_BUFFER_SIZE, MAXIMAL_BUFFER_SIZE))
+            .unwrap_or(MAXIMAL_BUFFER_SIZE);
+        let mut file_reader = BufReader::with_capacity(buffer_size as usize, decompressed_reader);
+
+        // the type of the hashes is determined by the first line of the file
+        let hash_type = detect_hash_type_from_reader(&mut file_reader)?;
+        debug!("Detected {} hashes in the password file", hash_type);
 
         // return the successfully created instance of the parser
         Ok(DatabaseIterator {
             password_file: Some(file_reader),
-            file_size: file_meta_data.len(),
+            hash_type,
+            file_size,
+            end_reached: false,
+            line_number: 0,
+            byte_offset: 0,
+            read_bytes: 0,
+            error: None,
+            statistics,
+            check_order: true,
+            previous_hash: String::new(),
         })
     }
 
-    /// Get the size of the original password file.
+    /// Get the type of the hashes stored in the password file (detected from its first line).
+    pub fn get_hash_type(&self) -> HashType {
+        self.hash_type
+    }
+
+    /// Get the hexadecimal SHA-256 checksum of all bytes of the password file which were read so far.
+    /// For a compressed file, the checksum is calculated over the compressed data.
+    ///
+    /// The checksum of the whole file is available after the iterator returned its last entry.
+    pub fn get_checksum(&self) -> Option<String> {
+        match self.statistics.lock() {
+            Ok(statistics) => {
+                let mut hasher = statistics.hasher;
+                Some(hasher.result_str())
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// Get the number of bytes which were read from the password file so far. For a compressed
+    /// file, this is the number of compressed bytes, so it can be compared with the file size.
+    pub fn get_consumed_bytes(&self) -> u64 {
+        match self.statistics.lock() {
+            Ok(statistics) => statistics.consumed_bytes,
+            Err(_) => 0,
+        }
+    }
+
+    /// Check if the whole password file was read. This is not the case if the iterator stopped
+    /// early because of a line which could not be parsed.
+    pub fn is_end_reached(&self) -> bool {
+        self.end_reached
+    }
+
+    /// Get the number of the line (starting with 1) of the entry which was returned last.
+    pub fn get_line_number(&self) -> u64 {
+        self.line_number
+    }
+
+    /// Get the position of the first byte of the entry which was returned last in the
+    /// (decompressed) password file.
+    pub fn get_byte_offset(&self) -> u64 {
+        self.byte_offset
+    }
+
+    /// Take the error which stopped the iterator early. If the iterator stopped because the end of
+    /// the password file was reached, `None` is returned.
+    ///
+    /// # Example
+    /// ```
+    /// use pwned_rs::haveibeenpwned::DatabaseIterator;
+    ///
+    /// if let Ok(mut parser) = DatabaseIterator::from_file("/path/to/the/hash/file.txt") {
+    ///     let entries = parser.by_ref().count();
+    ///     match parser.take_error() {
+    ///         Some(error) => println!("Stopped after {} entries: {}", entries, error),
+    ///         None => println!("Read all {} entries", entries),
+    ///     }
+    /// }
+    /// ```
+    pub fn take_error(&mut self) -> Option<PwnedError> {
+        self.error.take()
+    }
+
+    /// Select if the iterator checks that the entries are ordered by hash, which is the default.
+    /// If an entry is lower than the previous one, the iterator stops with an
+    /// [UnsortedInput](../error/enum.PwnedError.html) error. The check has to be disabled for
+    /// files which are not required to be ordered (e.g. the ones ordered by prevalence).
+    pub fn set_order_check(&mut self, enabled: bool) {
+        self.check_order = enabled;
+    }
+
+    /// Read the next lines of the password file as raw bytes without parsing them.
+    ///
+    /// The returned chunk contains at least `chunk_size` bytes (if the file is long enough) and is
+    /// extended up to the end of the line, so it always ends at a line boundary. If the end of the
+    /// file was reached, `None` is returned.
+    pub fn read_chunk(&mut self, chunk_size: usize) -> Result<Option<Vec<u8>>, Error> {
+        let password_file_reader = match &mut self.password_file {
+            Some(reader) => reader,
+            None => return Ok(None),
+        };
+
+        let mut chunk = Vec::with_capacity(chunk_size + 128);
+        password_file_reader
+            .by_ref()
+            .take(chunk_size as u64)
+            .read_to_end(&mut chunk)?;
+        if chunk.is_empty() {
+            self.end_reached = true;
+            return Ok(None);
+        }
+        if chunk.last() != Some(&b'\n') {
+            password_file_reader.read_until(b'\n', &mut chunk)?;
+        }
+        Ok(Some(chunk))
+    }
+
+    /// Get the size of the original password file. The size is not known if the password hashes
+    /// are read from the standard input.
     ///
     /// # Example
     /// ```
@@ -117,10 +425,7 @@ impl DatabaseIterator {
     /// }
     /// ```
     pub fn get_file_size(&self) -> Option<u64> {
-        if self.password_file.is_some() {
-            return Some(self.file_size);
-        }
-        None
+        self.file_size
     }
 }
 
@@ -134,49 +439,88 @@ impl Iterator for DatabaseIterator {
             None => return None,
         };
 
+        // the iterator stops at the first error, so do not read any further if one occurred
+        if self.error.is_some() {
+            return None;
+        }
+
         // get the next line from the file
         let mut entry_line = String::new();
         let line_length = match password_file_reader.read_line(&mut entry_line) {
             Ok(length) => length,
-            Err(_) => return None,
-        };
-
-        //
-        let mut entry_splitted = entry_line.trim().split(':');
-
-        //
-        let password_hash = match entry_splitted.next() {
-            Some(key_text) => key_text.to_string(),
-            None => {
-                error!("Could not get the password hash part of the entry!");
+            Err(error) if error.kind() == ErrorKind::InvalidData => {
+                self.error = Some(PwnedError::Format {
+                    line_number: self.line_number + 1,
+                    byte_offset: self.read_bytes,
+                    reason: "the line is not valid UTF-8".to_string(),
+                });
+                return None;
+            }
+            Err(error) => {
+                self.error = Some(PwnedError::Io(
+                    "Could not read from the password file".to_string(),
+                    error,
+                ));
                 return None;
             }
         };
 
-        // try to get the number of occurrences of the password hash
-        let occurrences = match entry_splitted.next() {
-            Some(value_text) => match value_text.parse::<u64>() {
-                Ok(value_as_int) => value_as_int,
-                Err(_) => {
-                    error!("Could not parse the number of occurrences of the password. Maybe \"{}\" not a number.", value_text);
+        // if nothing was read, the end of the file was reached
+        if line_length == 0 {
+            self.end_reached = true;
+            return None;
+        }
+        self.line_number += 1;
+        self.byte_offset = self.read_bytes;
+        self.read_bytes += line_length as u64;
+
+        // parse the line and ensure that all hashes in the file have the same type
+        let mut password_hash_entry =
+            match PasswordHashEntry::from_line_with_type(entry_line.trim(), self.hash_type) {
+                Ok(entry) => entry,
+                Err(error) => {
+                    self.error = Some(PwnedError::Format {
+                        line_number: self.line_number,
+                        byte_offset: self.byte_offset,
+                        reason: error.to_string(),
+                    });
                     return None;
                 }
-            },
-            None => {
-                error!("Could not get the occurrence count.");
+            };
+        if password_hash_entry.get_hash_type() != self.hash_type {
+            self.error = Some(PwnedError::Format {
+                line_number: self.line_number,
+                byte_offset: self.byte_offset,
+                reason: format!(
+                    "found a {} hash in a file with {} hashes",
+                    password_hash_entry.get_hash_type(),
+                    self.hash_type
+                ),
+            });
+            return None;
+        }
+
+        // an unordered file would silently break every lookup which relies on the order
+        if self.check_order {
+            if compare_hashes(&password_hash_entry.hash, &self.previous_hash) == Ordering::Less {
+                self.error = Some(PwnedError::UnsortedInput {
+                    line_number: self.line_number,
+                    byte_offset: self.byte_offset,
+                });
                 return None;
             }
-        };
+            self.previous_hash.clear();
+            self.previous_hash.push_str(&password_hash_entry.hash);
+        }
 
         // return the parsed password entry
-        Some(PasswordHashEntry {
-            hash: password_hash,
-            occurrences,
-            entry_size: line_length as u64,
-        })
+        password_hash_entry.entry_size = line_length as u64;
+        Some(password_hash_entry)
     }
 }
 
+/// This class reads a whole password file (usually a single file of an optimized database) into
+/// memory and looks up the password hashes in a hash map.
 pub struct DatabaseReader {
     password_hashes: HashMap<String, u64>,
 }
@@ -194,7 +538,7 @@ impl DatabaseReader {
             .append(false)
             .create(false)
             .read(true)
-            .open(&path_to_file)
+            .open(path_to_file)
         {
             Ok(file_handle) => BufReader::new(file_handle),
             Err(error) => return Err(CreateInstanceError::Io(error)),
@@ -218,6 +562,9 @@ impl DatabaseReader {
 
         // loop through all single password lines
         for current_hash in password_hashes.split('\n') {
+            // the lines can end with CRLF or LF, even mixed within the same file
+            let current_hash = current_hash.trim();
+
             // skip all empty lines to prevent that they are indicating corrupted files
             if current_hash.is_empty() {
                 continue;
@@ -228,7 +575,7 @@ impl DatabaseReader {
 
             //
             let key = match splitted_line.next() {
-                Some(value) => value.to_string(),
+                Some(value) => value.to_uppercase(),
                 None => {
                     return Err(CreateInstanceError::Format(
                         FormatErrorKind::LineFormatNotCorrect,
@@ -263,17 +610,33 @@ impl DatabaseReader {
         })
     }
 
+    /// Get the number of times the supplied password hash (in hexadecimal notation) was found in
+    /// password breaches. `None` is returned if the hash is unknown or not a valid hash.
+    #[deprecated(note = "use `PasswordDatabase::occurrences` with a `HashDigest` instead")]
     pub fn get_password_count(&self, password: String) -> Option<u64> {
-        match self.password_hashes.get(password.to_uppercase().as_str()) {
-            Some(value) => Some(*value),
-            None => None,
-        }
+        let hash_type = HashType::from_hash_length(password.len())?;
+        let digest = HashDigest::from_hex(&password, hash_type).ok()?;
+        self.occurrences(&digest).ok()?
+    }
+}
+
+impl PasswordDatabase for DatabaseReader {
+    fn occurrences(&self, hash: &HashDigest) -> Result<Option<u64>, LookupError> {
+        Ok(self.password_hashes.get(&hash.to_hex()).copied())
+    }
+
+    fn get_backend_name(&self) -> &'static str {
+        "in-memory"
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::create_sample_file;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::{Cursor, Write};
 
     #[test]
     fn creating_instance_with_invalid_path_fails() {
@@ -284,6 +647,107 @@ mod tests {
         assert_eq!(true, error.to_string().contains("IO error:"));
     }
 
+    #[test]
+    fn detecting_the_hash_type_does_not_consume_the_first_line() {
+        let mut reader = "8846F7EAEE8FB117AD06BDD830B7586C:7\n".as_bytes();
+
+        let hash_type = detect_hash_type_from_reader(&mut reader);
+        assert_eq!(HashType::Ntlm, hash_type.unwrap());
+
+        let mut first_line = String::new();
+        assert_eq!(35, reader.read_line(&mut first_line).unwrap());
+    }
+
+    const SAMPLE_PASSWORD_FILE: &str = "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3\r\n7C4A8D09CA3762AF61E59520943DC26494F8941B:2\r\n";
+
+    fn read_all_entries(compressed_data: Vec<u8>) -> Vec<(String, u64)> {
+        let compressed_size = compressed_data.len() as u64;
+        let mut parser =
+            DatabaseIterator::from_reader(Box::new(Cursor::new(compressed_data)), None).unwrap();
+        let entries: Vec<(String, u64)> = parser
+            .by_ref()
+            .map(|entry| (entry.get_hash().to_string(), entry.get_occurrences()))
+            .collect();
+        assert_eq!(true, parser.is_end_reached());
+        assert_eq!(compressed_size, parser.get_consumed_bytes());
+        entries
+    }
+
+    #[test]
+    fn reading_compressed_password_files_works() {
+        let mut gzip_encoder = GzEncoder::new(Vec::new(), Compression::default());
+        gzip_encoder
+            .write_all(SAMPLE_PASSWORD_FILE.as_bytes())
+            .unwrap();
+        let gzip_entries = read_all_entries(gzip_encoder.finish().unwrap());
+        assert_eq!(2, gzip_entries.len());
+        assert_eq!(2, gzip_entries[1].1);
+
+        let zstd_data = zstd::encode_all(SAMPLE_PASSWORD_FILE.as_bytes(), 0).unwrap();
+        let zstd_entries = read_all_entries(zstd_data);
+        assert_eq!(gzip_entries, zstd_entries);
+
+        let plain_entries = read_all_entries(SAMPLE_PASSWORD_FILE.as_bytes().to_vec());
+        assert_eq!(gzip_entries, plain_entries);
+    }
+
+    #[test]
+    fn an_invalid_line_stops_the_iterator_with_its_position() {
+        let password_file = format!("{}INVALID:1\r\n", SAMPLE_PASSWORD_FILE);
+        let mut parser =
+            DatabaseIterator::from_reader(Box::new(Cursor::new(password_file)), None).unwrap();
+
+        assert_eq!(2, parser.by_ref().count());
+        assert_eq!(false, parser.is_end_reached());
+        match parser.take_error() {
+            Some(PwnedError::Format {
+                line_number,
+                byte_offset,
+                ..
+            }) => {
+                assert_eq!(3, line_number);
+                assert_eq!(88, byte_offset);
+            }
+            _ => panic!("The invalid line was not reported as a format error"),
+        }
+        assert_eq!(true, parser.take_error().is_none());
+    }
+
+    #[test]
+    fn an_unordered_line_stops_the_iterator_unless_the_check_is_disabled() {
+        let password_file = "5baa61e4c9b93f3f0682250b6cf8331b7ee68fd8:3\n\
+                             5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:1\n\
+                             000000005AD76BD555C1D6D771DE417A4B87E4B4:4\n";
+        let mut parser =
+            DatabaseIterator::from_reader(Box::new(Cursor::new(password_file)), None).unwrap();
+
+        assert_eq!(2, parser.by_ref().count());
+        match parser.take_error() {
+            Some(PwnedError::UnsortedInput {
+                line_number,
+                byte_offset,
+            }) => {
+                assert_eq!(3, line_number);
+                assert_eq!(86, byte_offset);
+            }
+            _ => panic!("The unordered line was not reported"),
+        }
+
+        let mut parser =
+            DatabaseIterator::from_reader(Box::new(Cursor::new(password_file)), None).unwrap();
+        parser.set_order_check(false);
+        assert_eq!(3, parser.by_ref().count());
+        assert_eq!(true, parser.take_error().is_none());
+    }
+
+    #[test]
+    fn detecting_the_hash_type_of_an_invalid_line_fails() {
+        let mut reader = "8846F7EAEE8FB117AD:7\n".as_bytes();
+
+        let hash_type = detect_hash_type_from_reader(&mut reader);
+        assert_eq!(true, hash_type.is_err());
+    }
+
     #[test]
     fn ensure_get_password_count_is_case_insensitive() {
         let mut fake_reader = DatabaseReader {
@@ -291,17 +755,78 @@ mod tests {
         };
         fake_reader.password_hashes.insert(
             "0000000A1D4B746FAA3FD526FF6D5BC8052FDB38".to_string(),
-            1 as u64,
+            1_u64,
         );
 
-        let lower_case_input =
-            fake_reader.get_password_count("0000000a1d4b746faa3fd526ff6d5bc8052fdb38".to_string());
+        let lower_case_digest =
+            HashDigest::from_hex("0000000a1d4b746faa3fd526ff6d5bc8052fdb38", HashType::Sha1);
+        let lower_case_input = fake_reader
+            .occurrences(&lower_case_digest.unwrap())
+            .unwrap();
         assert_eq!(true, lower_case_input.is_some());
         assert_eq!(1, lower_case_input.unwrap());
 
-        let upper_case_input =
-            fake_reader.get_password_count("0000000A1D4B746FAA3FD526FF6D5BC8052FDB38".to_string());
+        let upper_case_digest =
+            HashDigest::from_hex("0000000A1D4B746FAA3FD526FF6D5BC8052FDB38", HashType::Sha1);
+        let upper_case_input = fake_reader
+            .occurrences(&upper_case_digest.unwrap())
+            .unwrap();
         assert_eq!(true, upper_case_input.is_some());
         assert_eq!(1, upper_case_input.unwrap());
     }
+
+    #[test]
+    #[allow(deprecated)]
+    fn get_password_count_delegates_to_occurrences() {
+        let mut fake_reader = DatabaseReader {
+            password_hashes: HashMap::new(),
+        };
+        fake_reader.password_hashes.insert(
+            "0000000A1D4B746FAA3FD526FF6D5BC8052FDB38".to_string(),
+            3_u64,
+        );
+
+        assert_eq!(
+            Some(3),
+            fake_reader.get_password_count("0000000a1d4b746faa3fd526ff6d5bc8052fdb38".to_string())
+        );
+        assert_eq!(
+            None,
+            fake_reader.get_password_count("1111111A1D4B746FAA3FD526FF6D5BC8052FDB38".to_string())
+        );
+        assert_eq!(
+            None,
+            fake_reader.get_password_count("not a hash".to_string())
+        );
+    }
+
+    #[test]
+    fn reading_a_file_with_mixed_line_endings_works() {
+        let file_path = create_sample_file(
+            "reader-mixed-line-endings.txt",
+            "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3\r\n\
+             7C4A8D09CA3762AF61E59520943DC26494F8941B:2\n\
+             FDC625010C4BEB998E590924DF39B7E59298612D:1\r\n",
+        );
+
+        let reader = DatabaseReader::from_file(&file_path).unwrap();
+        for (password, count) in &[("password", 3), ("123456", 2), ("sample_password", 1)] {
+            let digest = HashDigest::from_password(password, HashType::Sha1);
+            assert_eq!(Some(*count), reader.occurrences(&digest).unwrap());
+        }
+
+        let _ = std::fs::remove_file(file_path);
+    }
+
+    #[test]
+    fn reading_a_truncated_file_fails() {
+        let file_path = create_sample_file(
+            "reader-truncated.txt",
+            "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3\r\n7C4A8D09CA3762AF61E5",
+        );
+
+        assert_eq!(true, DatabaseReader::from_file(&file_path).is_err());
+
+        let _ = std::fs::remove_file(file_path);
+    }
 }
@@ -1,17 +1,42 @@
+#![cfg_attr(test, allow(clippy::bool_assert_comparison))]
+
 use crypto::digest::Digest;
 use crypto::sha1::Sha1;
+use md4::{Digest as Md4Digest, Md4};
+use secret::{wipe_value, SecretBytes, SecretString};
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::fmt::Result as FmtResult;
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 
+pub mod compiled;
+pub mod database;
+pub mod error;
+pub mod export;
+pub mod filter;
 pub mod haveibeenpwned;
+pub mod manifest;
+pub mod mapped;
+pub mod merge;
+pub mod optimized;
+pub mod ordered;
+pub mod output;
+pub mod patch;
+pub mod pwdump;
+pub mod range;
+pub mod remote;
+pub mod secret;
+pub mod sort;
 pub mod subcommands;
+#[cfg(test)]
+mod testing;
 
 #[derive(Debug, PartialEq)]
 pub enum HashLineFormatError {
     NoOccurrenceCountFound,
     NotAValidSha1Hash,
+    NotAValidNtlmHash,
     MultipleHashLines,
 }
 
@@ -22,6 +47,10 @@ impl Display for HashLineFormatError {
                 f,
                 "It seems that the supplied hash string is not a valid SHA-1 hash"
             ),
+            HashLineFormatError::NotAValidNtlmHash => write!(
+                f,
+                "It seems that the supplied hash string is not a valid NTLM hash"
+            ),
             HashLineFormatError::MultipleHashLines => write!(
                 f,
                 "It seems that the supplied string contains more than one line"
@@ -34,9 +63,255 @@ impl Display for HashLineFormatError {
     }
 }
 
+/// Compare two hexadecimal hashes like they are ordered in the password files, regardless of the
+/// case of their characters.
+pub(crate) fn compare_hashes(first_hash: &str, second_hash: &str) -> Ordering {
+    let first_hash = first_hash.bytes().map(|c| c.to_ascii_uppercase());
+    let second_hash = second_hash.bytes().map(|c| c.to_ascii_uppercase());
+    first_hash.cmp(second_hash)
+}
+
+/// The characters of the hexadecimal representation of a hash.
+const HEX_CHARACTERS: &[u8; 16] = b"0123456789abcdef";
+
+/// Convert the supplied bytes into a lower case hexadecimal string. This is done for every found
+/// hash of a lookup, so it avoids formatting each byte on its own.
+pub(crate) fn encode_hex(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        encoded.push(HEX_CHARACTERS[(byte >> 4) as usize] as char);
+        encoded.push(HEX_CHARACTERS[(byte & 0x0f) as usize] as char);
+    }
+    encoded
+}
+
+/// Convert the supplied hexadecimal string (upper or lower case) into the bytes it represents.
+pub(crate) fn decode_hex(hex_string: &str) -> Option<Vec<u8>> {
+    // from_str_radix would accept a leading plus sign, so each character is checked on its own
+    if !hex_string.len().is_multiple_of(2)
+        || !hex_string
+            .bytes()
+            .all(|character| character.is_ascii_hexdigit())
+    {
+        return None;
+    }
+    let mut decoded_bytes = Vec::with_capacity(hex_string.len() / 2);
+    for hex_pair in hex_string.as_bytes().chunks(2) {
+        let hex_pair = std::str::from_utf8(hex_pair).ok()?;
+        decoded_bytes.push(u8::from_str_radix(hex_pair, 16).ok()?);
+    }
+    Some(decoded_bytes)
+}
+
+/// The number of hexadecimal characters of the longest supported hash (SHA-1).
+pub const MAXIMAL_HASH_LENGTH: usize = 40;
+
+/// The hash algorithms in which the password databases are provided.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashType {
+    /// The SHA-1 hash of the UTF-8 encoded password (40 hexadecimal characters).
+    Sha1,
+    /// The NTLM hash, which is the MD4 hash of the UTF-16LE encoded password (32 hexadecimal characters).
+    Ntlm,
+}
+
+impl HashType {
+    /// Get the number of hexadecimal characters of a hash of this type.
+    pub fn get_hash_length(self) -> usize {
+        match self {
+            HashType::Sha1 => 40,
+            HashType::Ntlm => 32,
+        }
+    }
+
+    /// Get the hash type based on the number of hexadecimal characters of a hash.
+    ///
+    /// # Example
+    /// ```
+    /// use pwned_rs::HashType;
+    ///
+    /// assert_eq!(Some(HashType::Ntlm), HashType::from_hash_length(32));
+    /// assert_eq!(None, HashType::from_hash_length(12));
+    /// ```
+    pub fn from_hash_length(length: usize) -> Option<HashType> {
+        match length {
+            40 => Some(HashType::Sha1),
+            32 => Some(HashType::Ntlm),
+            _ => None,
+        }
+    }
+
+    /// Hash the supplied password with the algorithm of this type and return the lower case
+    /// hexadecimal representation of the hash.
+    pub fn hash_password(self, password: &str) -> String {
+        encode_hex(&self.compute_digest(password))
+    }
+
+    /// Hash the supplied password and return the raw digest. The password is fed directly into
+    /// the hash function (its UTF-16 encoding for NTLM is kept in a secret buffer) and the state
+    /// of the hash function, which still contains the last block of the password, is wiped.
+    fn compute_digest(self, password: &str) -> Vec<u8> {
+        match self {
+            HashType::Sha1 => {
+                let mut hasher = Sha1::new();
+                hasher.input(password.as_bytes());
+                let mut digest = vec![0; hasher.output_bytes()];
+                hasher.result(&mut digest);
+                unsafe { wipe_value(&mut hasher) };
+                digest
+            }
+            HashType::Ntlm => {
+                // a UTF-16 code unit never needs more bytes than its UTF-8 encoding
+                let mut encoded_password = SecretBytes::with_capacity(2 * password.len());
+                for code_unit in password.encode_utf16() {
+                    encoded_password.extend_from_slice(&code_unit.to_le_bytes());
+                }
+                let mut hasher = Md4::new();
+                Md4Digest::update(&mut hasher, encoded_password.as_bytes());
+                let digest = hasher.finalize_reset().to_vec();
+                unsafe { wipe_value(&mut hasher) };
+                digest
+            }
+        }
+    }
+
+    fn get_format_error(self) -> HashLineFormatError {
+        match self {
+            HashType::Sha1 => HashLineFormatError::NotAValidSha1Hash,
+            HashType::Ntlm => HashLineFormatError::NotAValidNtlmHash,
+        }
+    }
+}
+
+impl Display for HashType {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            HashType::Sha1 => write!(f, "sha1"),
+            HashType::Ntlm => write!(f, "ntlm"),
+        }
+    }
+}
+
+impl FromStr for HashType {
+    type Err = String;
+
+    fn from_str(input_str: &str) -> Result<Self, Self::Err> {
+        match input_str.to_lowercase().as_str() {
+            "sha1" | "sha-1" => Ok(HashType::Sha1),
+            "ntlm" => Ok(HashType::Ntlm),
+            _ => Err(format!("{} is not a known hash type", input_str)),
+        }
+    }
+}
+
+/// The raw digest of a password together with the algorithm which produced it. This is the key
+/// which is used for looking up passwords in a [PasswordDatabase](database/trait.PasswordDatabase.html).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HashDigest {
+    hash_type: HashType,
+    bytes: Vec<u8>,
+}
+
+impl HashDigest {
+    /// Create a digest from an already computed hash in its hexadecimal representation.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the length of the supplied string does not match
+    /// the hash type or if it contains non-hexadecimal characters.
+    ///
+    /// # Example
+    /// ```
+    /// use pwned_rs::{HashDigest, HashType};
+    ///
+    /// match HashDigest::from_hex("5baa61e4c9b93f3f0682250b6cf8331b7ee68fd8", HashType::Sha1) {
+    ///     Ok(digest) => println!("Got the digest {}", digest.to_hex()),
+    ///     Err(error) => println!("Could not get a digest, the error was: {}", error)
+    /// }
+    /// ```
+    pub fn from_hex(hash: &str, hash_type: HashType) -> Result<HashDigest, HashLineFormatError> {
+        if hash.len() != hash_type.get_hash_length() {
+            return Err(hash_type.get_format_error());
+        }
+        match decode_hex(hash) {
+            Some(bytes) => Ok(HashDigest { hash_type, bytes }),
+            None => Err(hash_type.get_format_error()),
+        }
+    }
+
+    /// Hash the supplied password with the algorithm of the supplied hash type.
+    ///
+    /// # Example
+    /// ```
+    /// use pwned_rs::{HashDigest, HashType};
+    ///
+    /// let digest = HashDigest::from_password("password", HashType::Ntlm);
+    /// assert_eq!("8846F7EAEE8FB117AD06BDD830B7586C", digest.to_hex());
+    /// ```
+    pub fn from_password(password: &str, hash_type: HashType) -> HashDigest {
+        HashDigest {
+            hash_type,
+            bytes: hash_type.compute_digest(password),
+        }
+    }
+
+    /// Hash the supplied secret with the algorithm of the supplied hash type. The password is not
+    /// copied into any buffer which is not wiped afterwards.
+    ///
+    /// # Example
+    /// ```
+    /// use pwned_rs::secret::SecretString;
+    /// use pwned_rs::{HashDigest, HashType};
+    ///
+    /// let secret = SecretString::from_string(String::from("password"));
+    /// let digest = HashDigest::from_secret(&secret, HashType::Sha1);
+    /// assert_eq!("5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8", digest.to_hex());
+    /// ```
+    pub fn from_secret(secret: &SecretString, hash_type: HashType) -> HashDigest {
+        HashDigest::from_password(secret.as_str(), hash_type)
+    }
+
+    pub fn get_hash_type(&self) -> HashType {
+        self.hash_type
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Get the hexadecimal representation of the digest in upper case characters, like it is used
+    /// in the password files.
+    pub fn to_hex(&self) -> String {
+        encode_hex(&self.bytes).to_uppercase()
+    }
+
+    /// Write the hexadecimal representation of the digest in upper case characters into the
+    /// supplied buffer and return the written part. Unlike `to_hex`, this does not allocate any
+    /// memory, so it can be used for every single lookup.
+    ///
+    /// # Example
+    /// ```
+    /// use pwned_rs::{HashDigest, HashType, MAXIMAL_HASH_LENGTH};
+    ///
+    /// let digest = HashDigest::from_password("password", HashType::Ntlm);
+    /// let mut buffer = [0; MAXIMAL_HASH_LENGTH];
+    /// assert_eq!("8846F7EAEE8FB117AD06BDD830B7586C", digest.write_hex(&mut buffer));
+    /// ```
+    pub fn write_hex<'a>(&self, buffer: &'a mut [u8; MAXIMAL_HASH_LENGTH]) -> &'a str {
+        let bytes = &self.bytes[..self.bytes.len().min(MAXIMAL_HASH_LENGTH / 2)];
+        for (index, byte) in bytes.iter().enumerate() {
+            buffer[2 * index] = HEX_CHARACTERS[(byte >> 4) as usize].to_ascii_uppercase();
+            buffer[2 * index + 1] = HEX_CHARACTERS[(byte & 0x0f) as usize].to_ascii_uppercase();
+        }
+        std::str::from_utf8(&buffer[..2 * bytes.len()]).unwrap_or_default()
+    }
+}
+
 /// This struct is used to represent a single password hash entry.
 pub struct PasswordHashEntry {
     hash: String,
+    hash_type: HashType,
     occurrences: u64,
     entry_size: u64,
 }
@@ -67,34 +342,135 @@ impl PasswordHashEntry {
         self.hash.clone()
     }
 
+    /// Get the raw bytes of the hash (e.g. 20 bytes for a SHA-1 hash).
+    ///
+    /// Returns `None` if the hash contains characters which are not hexadecimal.
+    pub fn get_hash_bytes(&self) -> Option<Vec<u8>> {
+        decode_hex(&self.hash)
+    }
+
+    /// Get the digest of the hash of this entry, so it can be looked up in a password database.
+    pub fn get_digest(&self) -> Option<HashDigest> {
+        HashDigest::from_hex(&self.hash, self.hash_type).ok()
+    }
+
     pub fn get_line_to_write(&self) -> String {
         format!("{}:{}\n", self.hash, self.occurrences)
     }
 
+    pub fn get_hash_type(&self) -> HashType {
+        self.hash_type
+    }
+
+    /// Parse a line of a password file which is expected to contain hashes of the supplied type.
+    ///
+    /// The type of the parsed hash is still determined by its length, so the caller can report a
+    /// hash of the other type. If the length does not fit any hash type, the format error of the
+    /// expected type is returned.
+    pub fn from_line_with_type(
+        line: &str,
+        hash_type: HashType,
+    ) -> Result<PasswordHashEntry, HashLineFormatError> {
+        PasswordHashEntry::from_str(line).map_err(|error| match error {
+            HashLineFormatError::NotAValidSha1Hash => hash_type.get_format_error(),
+            error => error,
+        })
+    }
+
+    /// Create an entry from an already computed SHA-1 hash of a password.
+    ///
+    /// The hash has to consist of exactly 40 hexadecimal characters. Upper and lower case
+    /// characters are both accepted.
+    ///
+    /// # Errors
+    ///
+    /// This function will return [NotAValidSha1Hash](enum.HashLineFormatError.html) if the
+    /// supplied string is not 40 characters long or contains non-hexadecimal characters.
+    ///
+    /// # Example
+    /// ```
+    /// use pwned_rs::PasswordHashEntry;
+    ///
+    /// match PasswordHashEntry::from_hash("5baa61e4c9b93f3f0682250b6cf8331b7ee68fd8") {
+    ///     Ok(instance) => println!("Got an entry with the prefix {}", instance.get_prefix()),
+    ///     Err(error) => println!("Could not get an instance, the error was: {}", error)
+    /// }
+    /// ```
+    pub fn from_hash(hash: &str) -> Result<PasswordHashEntry, HashLineFormatError> {
+        PasswordHashEntry::from_hash_with_type(hash, HashType::Sha1)
+    }
+
+    /// Create an entry from an already computed hash of a password with the supplied hash type.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the length of the supplied string does not match
+    /// the hash type or if it contains non-hexadecimal characters.
+    pub fn from_hash_with_type(
+        hash: &str,
+        hash_type: HashType,
+    ) -> Result<PasswordHashEntry, HashLineFormatError> {
+        // a hash has to consist of the expected number of hexadecimal characters
+        if hash.len() != hash_type.get_hash_length() || !hash.chars().all(|c| c.is_ascii_hexdigit())
+        {
+            return Err(hash_type.get_format_error());
+        }
+
+        // return the created object
+        Ok(PasswordHashEntry {
+            hash: hash.to_string(),
+            hash_type,
+            occurrences: 0,
+            entry_size: 2 + hash.len() as u64,
+        })
+    }
+
     pub fn from_password(password: &str) -> PasswordHashEntry {
+        PasswordHashEntry::from_password_with_type(password, HashType::Sha1)
+    }
+
+    /// Hash the supplied password with the algorithm of the supplied hash type.
+    ///
+    /// # Example
+    /// ```
+    /// use pwned_rs::{HashType, PasswordHashEntry};
+    ///
+    /// let entry = PasswordHashEntry::from_password_with_type("password", HashType::Ntlm);
+    /// assert_eq!("8846f7eaee8fb117ad06bdd830b7586c", entry.get_hash());
+    /// ```
+    pub fn from_password_with_type(password: &str, hash_type: HashType) -> PasswordHashEntry {
         // hash the input password
-        let mut hasher = Sha1::new();
-        hasher.input_str(password);
-        let hashed_password = hasher.result_str();
+        let hashed_password = hash_type.hash_password(password);
 
         // return the created object
         PasswordHashEntry {
-            hash: hashed_password.clone(),
-            occurrences: 0,
             entry_size: 2 + hashed_password.len() as u64,
+            hash: hashed_password,
+            hash_type,
+            occurrences: 0,
         }
     }
 }
 
 impl PartialEq for PasswordHashEntry {
     fn eq(&self, other: &Self) -> bool {
-        self.hash.eq(&other.hash)
+        self.hash.eq_ignore_ascii_case(&other.hash)
     }
 }
 
+impl Eq for PasswordHashEntry {}
+
 impl PartialOrd for PasswordHashEntry {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.hash.partial_cmp(&other.hash)
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PasswordHashEntry {
+    /// Compare the hashes of two entries. Since the password files use upper case hashes and
+    /// hashed passwords are lower case, the comparison has to ignore the case of the characters.
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_hashes(&self.hash, &other.hash)
     }
 }
 
@@ -153,14 +529,16 @@ impl FromStr for PasswordHashEntry {
             None => return Err(HashLineFormatError::NoOccurrenceCountFound),
         };
 
-        // a SHA-1 hash has to be 40 hexadecimal characters
-        if hash.len() != 40 {
-            return Err(HashLineFormatError::NotAValidSha1Hash);
-        }
+        // the type of the hash is determined by its length (40 characters for SHA-1, 32 for NTLM)
+        let hash_type = match HashType::from_hash_length(hash.len()) {
+            Some(hash_type) => hash_type,
+            None => return Err(HashLineFormatError::NotAValidSha1Hash),
+        };
 
         // return the created entry
         Ok(PasswordHashEntry {
             hash,
+            hash_type,
             occurrences,
             entry_size: input_str.len() as u64,
         })
@@ -244,6 +622,78 @@ mod tests {
         assert_eq!("fdc62", instance.get_dynamic_prefix(5).unwrap());
     }
 
+    #[test]
+    fn creating_a_password_hash_entry_from_a_hash_works_for_both_cases() {
+        let lower_case = PasswordHashEntry::from_hash("fdc625010c4beb998e590924df39b7e59298612d");
+        let upper_case = PasswordHashEntry::from_hash("FDC625010C4BEB998E590924DF39B7E59298612D");
+        assert_eq!(false, lower_case.is_err());
+        assert_eq!(false, upper_case.is_err());
+
+        let password_entry = PasswordHashEntry::from_password("sample_password");
+        assert_eq!(true, password_entry == lower_case.unwrap());
+        assert_eq!(true, password_entry == upper_case.unwrap());
+    }
+
+    #[test]
+    fn creating_a_password_hash_entry_from_an_invalid_hash_is_handled_correctly() {
+        let too_short = PasswordHashEntry::from_hash("fdc625010c4beb998e590924df39b7e59298612");
+        assert_eq!(
+            HashLineFormatError::NotAValidSha1Hash,
+            too_short.err().unwrap()
+        );
+
+        let not_hex = PasswordHashEntry::from_hash("xdc625010c4beb998e590924df39b7e59298612d");
+        assert_eq!(
+            HashLineFormatError::NotAValidSha1Hash,
+            not_hex.err().unwrap()
+        );
+    }
+
+    #[test]
+    fn creating_a_password_hash_entry_from_a_ntlm_line_detects_the_hash_type() {
+        let maybe_instance = PasswordHashEntry::from_str("8846F7EAEE8FB117AD06BDD830B7586C:7");
+        assert_eq!(false, maybe_instance.is_err());
+        let instance = maybe_instance.unwrap();
+
+        assert_eq!(HashType::Ntlm, instance.get_hash_type());
+        assert_eq!(7, instance.get_occurrences());
+        assert_eq!(
+            true,
+            instance == PasswordHashEntry::from_password_with_type("password", HashType::Ntlm)
+        );
+
+        let too_short = PasswordHashEntry::from_line_with_type(
+            "8846F7EAEE8FB117AD06BDD830B7586:7",
+            HashType::Ntlm,
+        );
+        assert_eq!(
+            HashLineFormatError::NotAValidNtlmHash,
+            too_short.err().unwrap()
+        );
+    }
+
+    #[test]
+    fn creating_a_ntlm_password_hash_entry_from_a_sha1_hash_is_handled_correctly() {
+        let maybe_instance = PasswordHashEntry::from_hash_with_type(
+            "fdc625010c4beb998e590924df39b7e59298612d",
+            HashType::Ntlm,
+        );
+        assert_eq!(
+            HashLineFormatError::NotAValidNtlmHash,
+            maybe_instance.err().unwrap()
+        );
+    }
+
+    #[test]
+    fn getting_the_raw_bytes_of_a_hash_works() {
+        let instance = PasswordHashEntry::from_password_with_type("password", HashType::Ntlm);
+        let hash_bytes = instance.get_hash_bytes().unwrap();
+
+        assert_eq!(16, hash_bytes.len());
+        assert_eq!(0x88, hash_bytes[0]);
+        assert_eq!(0x6c, hash_bytes[15]);
+    }
+
     #[test]
     fn getting_a_too_long_dynamic_prefix_is_handled_correctly() {
         let instance = PasswordHashEntry::from_password("sample_password");
@@ -268,11 +718,13 @@ mod tests {
     fn ensure_hash_comparison_works_as_intended() {
         let hash_one = PasswordHashEntry {
             hash: "000000".to_string(),
+            hash_type: HashType::Sha1,
             entry_size: 0,
             occurrences: 0,
         };
         let hash_two = PasswordHashEntry {
             hash: "00000F".to_string(),
+            hash_type: HashType::Sha1,
             entry_size: 0,
             occurrences: 0,
         };
@@ -284,4 +736,29 @@ mod tests {
         assert_eq!(false, hash_one > hash_two);
         assert_eq!(false, hash_one >= hash_two);
     }
+
+    #[test]
+    fn ensure_hash_comparison_ignores_the_case_of_the_hashes() {
+        let hashed_password = PasswordHashEntry::from_password("sample_password");
+        let read_entry =
+            PasswordHashEntry::from_str("FDC625010C4BEB998E590924DF39B7E59298612D:2").unwrap();
+        let smaller_entry =
+            PasswordHashEntry::from_str("FDC625010C4BEB998E590924DF39B7E59298612C:2").unwrap();
+
+        assert_eq!(true, hashed_password == read_entry);
+        assert_eq!(true, smaller_entry < hashed_password);
+    }
+
+    #[test]
+    fn digests_are_just_created_from_hexadecimal_characters() {
+        let digest =
+            HashDigest::from_hex("5baa61e4c9b93f3f0682250b6cf8331b7ee68fd8", HashType::Sha1);
+        assert_eq!(true, digest.is_ok());
+        let signed_digest =
+            HashDigest::from_hex("+BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8", HashType::Sha1);
+        assert_eq!(true, signed_digest.is_err());
+        assert_eq!(None, decode_hex("+1"));
+        assert_eq!(None, decode_hex("5G"));
+        assert_eq!(Some(vec![0x5b, 0xaa]), decode_hex("5bAA"));
+    }
 }
@@ -0,0 +1,546 @@
+use crate::haveibeenpwned::{CreateInstanceError, FormatErrorKind};
+use crate::{HashDigest, HashType};
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+
+/// The name of the file in which the manifest of an optimized database is stored.
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// The prefix length which is used if an optimized database does not have a manifest.
+pub const DEFAULT_PREFIX_LENGTH: usize = 3;
+
+/// The shortest prefix length which can be used for splitting the password hashes.
+pub const MINIMAL_PREFIX_LENGTH: usize = 1;
+
+/// The longest prefix length which can be used for splitting the password hashes.
+pub const MAXIMAL_PREFIX_LENGTH: usize = 6;
+
+/// Information about the password file an optimized database was created from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceInformation {
+    file_name: String,
+    size: u64,
+    sha256: String,
+}
+
+impl SourceInformation {
+    pub fn new(file_name: &str, size: u64, sha256: &str) -> SourceInformation {
+        SourceInformation {
+            file_name: file_name.to_string(),
+            size,
+            sha256: sha256.to_string(),
+        }
+    }
+
+    pub fn get_file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn get_sha256(&self) -> &str {
+        &self.sha256
+    }
+}
+
+/// Information about a single file of an optimized database.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileInformation {
+    entries: u64,
+    size: u64,
+    sha256: String,
+}
+
+impl FileInformation {
+    pub fn new(entries: u64, size: u64, sha256: &str) -> FileInformation {
+        FileInformation {
+            entries,
+            size,
+            sha256: sha256.to_string(),
+        }
+    }
+
+    pub fn get_entries(&self) -> u64 {
+        self.entries
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn get_sha256(&self) -> &str {
+        &self.sha256
+    }
+}
+
+/// The problems which can be found while verifying an optimized database against its manifest.
+#[derive(Debug)]
+pub enum VerificationError {
+    /// A file which is listed in the manifest does not exist.
+    MissingFile(String),
+    /// A file exists in the database folder but is not listed in the manifest.
+    UnexpectedFile(String),
+    /// The size of a file does not match the manifest.
+    SizeMismatch(String),
+    /// The SHA-256 checksum of a file does not match the manifest.
+    ChecksumMismatch(String),
+    /// The number of entries of a file does not match the manifest.
+    EntryCountMismatch(String),
+    /// The sum of the entries of all files does not match the total of the manifest.
+    TotalEntriesMismatch,
+    /// A file could not be read.
+    Io(String, Error),
+}
+
+impl Display for VerificationError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            VerificationError::MissingFile(ref name) => write!(f, "{} is missing", name),
+            VerificationError::UnexpectedFile(ref name) => {
+                write!(f, "{} is not part of the manifest", name)
+            }
+            VerificationError::SizeMismatch(ref name) => {
+                write!(f, "the size of {} does not match the manifest", name)
+            }
+            VerificationError::ChecksumMismatch(ref name) => {
+                write!(f, "the checksum of {} does not match the manifest", name)
+            }
+            VerificationError::EntryCountMismatch(ref name) => write!(
+                f,
+                "the number of entries in {} does not match the manifest",
+                name
+            ),
+            VerificationError::TotalEntriesMismatch => {
+                write!(f, "the total number of entries does not match the manifest")
+            }
+            VerificationError::Io(ref name, ref err) => {
+                write!(f, "{} could not be read: {}", name, err)
+            }
+        }
+    }
+}
+
+/// The manifest describes how an optimized password database was created and which files it contains.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct DatabaseManifest {
+    prefix_length: usize,
+    #[serde(default)]
+    hash_type: Option<HashType>,
+    #[serde(default)]
+    created_at: Option<String>,
+    #[serde(default)]
+    source: Option<SourceInformation>,
+    /// The number of patches which were applied since the database was created.
+    #[serde(default)]
+    version: u64,
+    #[serde(default)]
+    updated_at: Option<String>,
+    #[serde(default)]
+    total_entries: u64,
+    #[serde(default)]
+    files: BTreeMap<String, FileInformation>,
+}
+
+impl DatabaseManifest {
+    /// Create a new manifest for a database which is split by prefixes of the supplied length.
+    pub fn new(prefix_length: usize) -> DatabaseManifest {
+        DatabaseManifest {
+            prefix_length,
+            hash_type: None,
+            created_at: None,
+            source: None,
+            version: 0,
+            updated_at: None,
+            total_entries: 0,
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Read the manifest of the optimized database in the supplied folder.
+    ///
+    /// Databases which were created before manifests were introduced do not have one. For them a
+    /// manifest with the default prefix length is returned.
+    ///
+    /// # Example
+    /// ```
+    /// use pwned_rs::manifest::DatabaseManifest;
+    /// use std::path::Path;
+    ///
+    /// match DatabaseManifest::from_folder(Path::new("/path/to/optimized/database")) {
+    ///     Ok(manifest) => println!("The database uses prefixes of length {}", manifest.get_prefix_length()),
+    ///     Err(error) => println!("Could not read the manifest, the error was: {}", error)
+    /// }
+    /// ```
+    pub fn from_folder(path_to_folder: &Path) -> Result<DatabaseManifest, CreateInstanceError> {
+        let manifest_file = match File::open(path_to_folder.join(MANIFEST_FILE_NAME)) {
+            Ok(file_handle) => file_handle,
+            Err(ref error) if error.kind() == ErrorKind::NotFound => {
+                return Ok(DatabaseManifest::new(DEFAULT_PREFIX_LENGTH))
+            }
+            Err(error) => return Err(CreateInstanceError::Io(error)),
+        };
+
+        let manifest: DatabaseManifest =
+            match serde_json::from_reader(BufReader::new(manifest_file)) {
+                Ok(manifest) => manifest,
+                Err(_) => {
+                    return Err(CreateInstanceError::Format(
+                        FormatErrorKind::InvalidManifest,
+                    ))
+                }
+            };
+        if manifest.prefix_length < MINIMAL_PREFIX_LENGTH
+            || manifest.prefix_length > MAXIMAL_PREFIX_LENGTH
+        {
+            return Err(CreateInstanceError::Format(
+                FormatErrorKind::InvalidManifest,
+            ));
+        }
+        Ok(manifest)
+    }
+
+    /// Write the manifest into the supplied folder of an optimized database.
+    pub fn write_to_folder(&self, path_to_folder: &Path) -> Result<(), Error> {
+        let manifest_file = File::create(path_to_folder.join(MANIFEST_FILE_NAME))?;
+        serde_json::to_writer_pretty(BufWriter::new(manifest_file), self)?;
+        Ok(())
+    }
+
+    /// Get the number of characters of the hash prefixes which are used as file names.
+    pub fn get_prefix_length(&self) -> usize {
+        self.prefix_length
+    }
+
+    /// Get the type of the hashes stored in the database (if it is known).
+    pub fn get_hash_type(&self) -> Option<HashType> {
+        self.hash_type
+    }
+
+    pub fn set_hash_type(&mut self, hash_type: HashType) {
+        self.hash_type = Some(hash_type);
+    }
+
+    /// Get the time (RFC 3339) at which the database was created (if it is known).
+    pub fn get_created_at(&self) -> Option<&str> {
+        self.created_at.as_deref()
+    }
+
+    pub fn set_created_at(&mut self, created_at: &str) {
+        self.created_at = Some(created_at.to_string());
+    }
+
+    /// Get the information about the password file the database was created from (if it is known).
+    pub fn get_source(&self) -> Option<&SourceInformation> {
+        self.source.as_ref()
+    }
+
+    pub fn set_source(&mut self, source: SourceInformation) {
+        self.source = Some(source);
+    }
+
+    /// Get the number of patches which were applied to the database since it was created.
+    pub fn get_version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
+
+    /// Get the time (RFC 3339) at which the last patch was applied (if there was one).
+    pub fn get_updated_at(&self) -> Option<&str> {
+        self.updated_at.as_deref()
+    }
+
+    pub fn set_updated_at(&mut self, updated_at: &str) {
+        self.updated_at = Some(updated_at.to_string());
+    }
+
+    /// Get the total number of password hashes stored in the database.
+    pub fn get_total_entries(&self) -> u64 {
+        self.total_entries
+    }
+
+    /// Get the information about all files of the database, ordered by their names.
+    pub fn get_files(&self) -> &BTreeMap<String, FileInformation> {
+        &self.files
+    }
+
+    /// Add the information about a file of the database and update the total number of entries.
+    pub fn add_file(&mut self, file_name: &str, information: FileInformation) {
+        self.total_entries += information.entries;
+        if let Some(replaced) = self.files.insert(file_name.to_string(), information) {
+            self.total_entries -= replaced.entries;
+        }
+    }
+
+    /// Remove a file from the database and update the total number of entries.
+    pub fn remove_file(&mut self, file_name: &str) {
+        if let Some(removed) = self.files.remove(file_name) {
+            self.total_entries -= removed.entries;
+        }
+    }
+
+    /// Check if the file with the supplied name is part of the database.
+    pub fn lists_file(&self, file_name: &str) -> bool {
+        self.files.contains_key(file_name)
+    }
+
+    /// Check if the file with the supplied name has the size which is recorded in the manifest. A
+    /// file which is not listed cannot be checked, so its size is always accepted.
+    pub fn matches_file_size(&self, file_name: &str, size: u64) -> bool {
+        self.files
+            .get(file_name)
+            .is_none_or(|information| information.size == size)
+    }
+
+    /// Check if the manifest lists the files of the database, so the database can be verified.
+    pub fn has_file_list(&self) -> bool {
+        !self.files.is_empty()
+    }
+
+    /// Get the path of the file in the supplied folder which contains the supplied password hash.
+    pub fn get_file_path(&self, path_to_folder: &Path, hash: &HashDigest) -> PathBuf {
+        let hexadecimal_hash = hash.to_hex();
+        let prefix = hexadecimal_hash
+            .get(..self.prefix_length)
+            .unwrap_or(&hexadecimal_hash);
+        path_to_folder.join(format!("{}.txt", prefix))
+    }
+
+    /// Verify the files of the database in the supplied folder against the manifest.
+    ///
+    /// The quick verification just lists the folder once and checks that all files of the manifest
+    /// are part of it, other files in the folder are ignored. It does not access the files
+    /// themselves, so their sizes are checked by the lookup when a file is opened. The full
+    /// verification additionally checks the sizes, reports every file which is not listed in the
+    /// manifest and reads all files to compare their checksums and number of entries.
+    pub fn verify_folder(
+        &self,
+        path_to_folder: &Path,
+        full_verification: bool,
+    ) -> Vec<VerificationError> {
+        let mut found_errors = Vec::new();
+
+        // the folder is listed just once, so the quick verification does not access every file
+        let mut folder_files = BTreeSet::new();
+        match path_to_folder.read_dir() {
+            Ok(entries) => {
+                for entry in entries.filter_map(|entry| entry.ok()) {
+                    folder_files.insert(entry.file_name().to_string_lossy().to_string());
+                }
+            }
+            Err(error) => {
+                found_errors.push(VerificationError::Io(
+                    path_to_folder.display().to_string(),
+                    error,
+                ));
+                return found_errors;
+            }
+        }
+
+        // every file of the database folder (except the manifest) has to be listed in the manifest
+        if full_verification {
+            for file_name in &folder_files {
+                if file_name != MANIFEST_FILE_NAME && !self.files.contains_key(file_name) {
+                    found_errors.push(VerificationError::UnexpectedFile(file_name.clone()));
+                }
+            }
+        }
+
+        // check every file which is listed in the manifest
+        let mut summed_entries = 0;
+        for (file_name, information) in &self.files {
+            summed_entries += information.entries;
+            if !folder_files.contains(file_name) {
+                found_errors.push(VerificationError::MissingFile(file_name.clone()));
+                continue;
+            }
+            if !full_verification {
+                continue;
+            }
+
+            let file_path = path_to_folder.join(file_name);
+            let file_size = match file_path.metadata() {
+                Ok(data) => data.len(),
+                Err(error) => {
+                    found_errors.push(VerificationError::Io(file_name.clone(), error));
+                    continue;
+                }
+            };
+            if file_size != information.size {
+                found_errors.push(VerificationError::SizeMismatch(file_name.clone()));
+                continue;
+            }
+
+            match get_file_checksum_and_entries(&file_path) {
+                Ok((checksum, entries)) => {
+                    if !checksum.eq_ignore_ascii_case(&information.sha256) {
+                        found_errors.push(VerificationError::ChecksumMismatch(file_name.clone()));
+                    }
+                    if entries != information.entries {
+                        found_errors.push(VerificationError::EntryCountMismatch(file_name.clone()));
+                    }
+                }
+                Err(error) => found_errors.push(VerificationError::Io(file_name.clone(), error)),
+            }
+        }
+        if summed_entries != self.total_entries {
+            found_errors.push(VerificationError::TotalEntriesMismatch);
+        }
+
+        found_errors
+    }
+}
+
+/// Read the supplied file and return its hexadecimal SHA-256 checksum and the number of lines in it.
+fn get_file_checksum_and_entries(path_to_file: &Path) -> Result<(String, u64), Error> {
+    let mut file_reader = BufReader::with_capacity(1024 * 1024, File::open(path_to_file)?);
+    let mut hasher = Sha256::new();
+    let mut entries = 0;
+    let mut read_buffer = vec![0; 1024 * 1024];
+    loop {
+        let read_bytes = file_reader.read(&mut read_buffer)?;
+        if read_bytes == 0 {
+            break;
+        }
+        hasher.input(&read_buffer[..read_bytes]);
+        entries += read_buffer[..read_bytes]
+            .iter()
+            .filter(|c| **c == b'\n')
+            .count() as u64;
+    }
+    Ok((hasher.result_str(), entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::create_temp_path;
+
+    const SAMPLE_FILE_CONTENT: &str = "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3\n";
+
+    fn create_sample_database(folder_name: &str) -> (PathBuf, DatabaseManifest) {
+        let database_folder = create_temp_path(folder_name);
+        let _ = std::fs::remove_dir_all(&database_folder);
+        std::fs::create_dir_all(&database_folder).unwrap();
+        std::fs::write(database_folder.join("5BA.txt"), SAMPLE_FILE_CONTENT).unwrap();
+
+        let (checksum, entries) =
+            get_file_checksum_and_entries(&database_folder.join("5BA.txt")).unwrap();
+        let mut manifest = DatabaseManifest::new(3);
+        manifest.add_file(
+            "5BA.txt",
+            FileInformation::new(entries, SAMPLE_FILE_CONTENT.len() as u64, &checksum),
+        );
+        (database_folder, manifest)
+    }
+
+    #[test]
+    fn reading_the_manifest_of_a_folder_without_one_returns_the_default() {
+        let database_folder = create_temp_path("manifest-missing");
+        std::fs::create_dir_all(&database_folder).unwrap();
+
+        let manifest = DatabaseManifest::from_folder(&database_folder).unwrap();
+        assert_eq!(DEFAULT_PREFIX_LENGTH, manifest.get_prefix_length());
+        assert_eq!(false, manifest.has_file_list());
+
+        let _ = std::fs::remove_dir_all(database_folder);
+    }
+
+    #[test]
+    fn writing_and_reading_a_manifest_works() {
+        let database_folder = create_temp_path("manifest-written");
+        std::fs::create_dir_all(&database_folder).unwrap();
+
+        let mut written_manifest = DatabaseManifest::new(5);
+        written_manifest.set_hash_type(HashType::Ntlm);
+        written_manifest.set_source(SourceInformation::new("source.txt", 42, "abcdef"));
+        written_manifest.add_file("5BAA6.txt", FileInformation::new(2, 84, "abcdef"));
+        written_manifest.add_file("7C4A8.txt", FileInformation::new(3, 126, "abcdef"));
+        written_manifest.remove_file("7C4A8.txt");
+        written_manifest.set_version(2);
+        written_manifest.write_to_folder(&database_folder).unwrap();
+
+        let manifest = DatabaseManifest::from_folder(&database_folder).unwrap();
+        assert_eq!(written_manifest, manifest);
+        assert_eq!(2, manifest.get_total_entries());
+        assert_eq!(2, manifest.get_version());
+        assert_eq!(5, manifest.get_prefix_length());
+        assert_eq!(Some(HashType::Ntlm), manifest.get_hash_type());
+        assert_eq!(2, manifest.get_total_entries());
+
+        let digest = HashDigest::from_password("password", HashType::Sha1);
+        assert_eq!(
+            database_folder.join("5BAA6.txt"),
+            manifest.get_file_path(&database_folder, &digest)
+        );
+
+        let _ = std::fs::remove_dir_all(database_folder);
+    }
+
+    #[test]
+    fn verifying_a_complete_database_works() {
+        let (database_folder, manifest) = create_sample_database("manifest-complete");
+        manifest.write_to_folder(&database_folder).unwrap();
+
+        assert_eq!(0, manifest.verify_folder(&database_folder, true).len());
+        assert_eq!(
+            1,
+            manifest.get_files().get("5BA.txt").unwrap().get_entries()
+        );
+
+        let _ = std::fs::remove_dir_all(database_folder);
+    }
+
+    #[test]
+    fn verifying_a_modified_database_finds_the_problems() {
+        let (database_folder, manifest) = create_sample_database("manifest-modified");
+        std::fs::write(
+            database_folder.join("5BA.txt"),
+            SAMPLE_FILE_CONTENT.replace(":3", ":4"),
+        )
+        .unwrap();
+        std::fs::write(database_folder.join("FFF.txt"), "").unwrap();
+        std::fs::create_dir_all(database_folder.join(".patch-staging")).unwrap();
+
+        // the quick verification ignores all files which are not listed in the manifest
+        let quick_errors = manifest.verify_folder(&database_folder, false);
+        assert_eq!(0, quick_errors.len());
+        std::fs::remove_dir_all(database_folder.join(".patch-staging")).unwrap();
+
+        let full_errors = manifest.verify_folder(&database_folder, true);
+        assert_eq!(2, full_errors.len());
+        assert_eq!(
+            true,
+            matches!(full_errors[1], VerificationError::ChecksumMismatch(_))
+        );
+
+        // the size of a truncated file is checked by the lookup which opens it
+        std::fs::write(database_folder.join("5BA.txt"), &SAMPLE_FILE_CONTENT[..20]).unwrap();
+        assert_eq!(0, manifest.verify_folder(&database_folder, false).len());
+        assert_eq!(false, manifest.matches_file_size("5BA.txt", 20));
+        let truncated_errors = manifest.verify_folder(&database_folder, true);
+        assert_eq!(
+            true,
+            matches!(truncated_errors[1], VerificationError::SizeMismatch(_))
+        );
+
+        std::fs::remove_file(database_folder.join("5BA.txt")).unwrap();
+        let missing_errors = manifest.verify_folder(&database_folder, false);
+        assert_eq!(
+            true,
+            matches!(missing_errors[0], VerificationError::MissingFile(_))
+        );
+
+        let _ = std::fs::remove_dir_all(database_folder);
+    }
+}
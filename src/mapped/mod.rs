@@ -0,0 +1,778 @@
+use crate::database::{LookupError, PasswordDatabase};
+use crate::haveibeenpwned::{CreateInstanceError, FormatErrorKind};
+use crate::manifest::DatabaseManifest;
+use crate::merge::parse_source_tags;
+use crate::{HashDigest, HashType, PasswordHashEntry, MAXIMAL_HASH_LENGTH};
+use memmap2::Mmap;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// The longest prefix length for which all files of an optimized database are mapped at once.
+const MAXIMAL_EAGER_PREFIX_LENGTH: usize = 3;
+
+/// The number of files which stay mapped if the files of an optimized database are mapped on demand.
+/// Each mapping counts against the limit of mappings per process (`vm.max_map_count`), so not all
+/// files of a database with a long prefix can stay mapped.
+const MAPPED_FILE_CACHE_SIZE: usize = 256;
+
+/// Compare the hash part of a line (everything in front of the separator) with the seeked hash.
+/// The comparison ignores the case of the characters.
+fn compare_hash(line_hash: &[u8], seeked_hash: &[u8]) -> Ordering {
+    let line_hash = line_hash.iter().map(|c| c.to_ascii_uppercase());
+    let seeked_hash = seeked_hash.iter().map(|c| c.to_ascii_uppercase());
+    line_hash.cmp(seeked_hash)
+}
+
+/// Get the boundaries of the line in which the supplied position is located. The search for the
+/// line boundaries is limited to the supplied block.
+fn get_line_boundaries(
+    data: &[u8],
+    block_start: usize,
+    block_end: usize,
+    position: usize,
+) -> (usize, usize) {
+    let line_start = match data[block_start..position]
+        .iter()
+        .rposition(|c| *c == b'\n')
+    {
+        Some(offset) => block_start + offset + 1,
+        None => block_start,
+    };
+    let line_end = match data[line_start..block_end].iter().position(|c| *c == b'\n') {
+        Some(offset) => line_start + offset,
+        None => block_end,
+    };
+    (line_start, line_end)
+}
+
+/// Split a column of a line from the part after it (the line ending is not removed).
+fn split_column(line: &[u8]) -> (&[u8], &[u8]) {
+    match line.iter().position(|c| *c == b':') {
+        Some(separator) => (&line[..separator], &line[separator + 1..]),
+        None => (line, &[]),
+    }
+}
+
+/// Split a line into its hash and the part after it, which starts with the occurrence count (the
+/// line ending is not removed). A truncated or malformed line, whose hash does not have the length
+/// of the supplied hash type or which is not followed by a count, is rejected.
+fn split_line(line: &[u8], hash_type: HashType) -> Result<(&[u8], &[u8]), LookupError> {
+    let (hash, remaining_columns) = split_column(line);
+    let occurrences = split_column(remaining_columns).0;
+    let occurrences = occurrences.strip_suffix(b"\r").unwrap_or(occurrences);
+    if hash.len() != hash_type.get_hash_length()
+        || occurrences.is_empty()
+        || !occurrences.iter().all(u8::is_ascii_digit)
+    {
+        return Err(LookupError::Format(FormatErrorKind::LineFormatNotCorrect));
+    }
+    Ok((hash, remaining_columns))
+}
+
+/// Find the start of the first line (ordered by hash) whose hash is not less than the seeked hash.
+/// If all hashes are less than the seeked one, the length of the data is returned. Each line which
+/// is read during the search must contain a hash of the supplied type.
+fn find_first_line_not_less(
+    data: &[u8],
+    seeked_hash: &[u8],
+    hash_type: HashType,
+) -> Result<usize, LookupError> {
+    // both bounds always point to the beginning of a line (or the end of the data)
+    let mut lower_bound = 0;
+    let mut upper_bound = data.len();
+    while lower_bound < upper_bound {
+        let mid = lower_bound + (upper_bound - lower_bound) / 2;
+        let (line_start, line_end) = get_line_boundaries(data, lower_bound, upper_bound, mid);
+        let (line_hash, _) = split_line(&data[line_start..line_end], hash_type)?;
+
+        if compare_hash(line_hash, seeked_hash) == Ordering::Less {
+            lower_bound = (line_end + 1).min(data.len());
+        } else {
+            upper_bound = line_start;
+        }
+    }
+    Ok(lower_bound)
+}
+
+/// Get the type of the hashes in the supplied lines from the length of the first hash.
+fn detect_hash_type(data: &[u8]) -> Option<HashType> {
+    let (_, line_end) = get_line_boundaries(data, 0, data.len(), 0);
+    HashType::from_hash_length(split_column(&data[..line_end]).0.len())
+}
+
+/// Ensure that the seeked hash has the type of the hashes in the database. Otherwise the hash
+/// could never be found, which must not be reported as a password which was not pwned.
+fn check_hash_type(database_type: Option<HashType>, hash: &HashDigest) -> Result<(), LookupError> {
+    match database_type {
+        Some(database_type) if database_type != hash.get_hash_type() => {
+            Err(LookupError::HashTypeMismatch {
+                database: database_type,
+                requested: hash.get_hash_type(),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Search for the seeked hash in the supplied lines (ordered by hash) and return the matching line.
+fn find_ordered_line<'a>(
+    data: &'a [u8],
+    seeked_hash: &[u8],
+    hash_type: HashType,
+) -> Result<Option<&'a [u8]>, LookupError> {
+    let line_start = find_first_line_not_less(data, seeked_hash, hash_type)?;
+    if line_start >= data.len() {
+        return Ok(None);
+    }
+    let (_, line_end) = get_line_boundaries(data, line_start, data.len(), line_start);
+    let line = &data[line_start..line_end];
+    match compare_hash(split_line(line, hash_type)?.0, seeked_hash) {
+        Ordering::Equal => Ok(Some(line)),
+        _ => Ok(None),
+    }
+}
+
+/// Search for the seeked hash in the supplied lines (ordered by hash) and return the occurrence count
+/// of the matching line. The search works on the bytes directly and does not allocate any memory.
+fn search_ordered_lines(
+    data: &[u8],
+    seeked_hash: &[u8],
+    hash_type: HashType,
+) -> Result<Option<u64>, LookupError> {
+    let line = match find_ordered_line(data, seeked_hash, hash_type)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+
+    // a merged password file can contain further columns after the count
+    let (_, occurrences) = split_line(line, hash_type)?;
+    let occurrences = split_column(occurrences).0;
+    match std::str::from_utf8(occurrences)
+        .ok()
+        .and_then(|text| text.trim().parse::<u64>().ok())
+    {
+        Some(count) => Ok(Some(count)),
+        None => Err(LookupError::Format(FormatErrorKind::LineFormatNotCorrect)),
+    }
+}
+
+/// Get all entries of the supplied lines (ordered by hash) whose hash starts with the supplied prefix.
+fn get_ordered_lines_with_prefix(
+    data: &[u8],
+    prefix: &[u8],
+    hash_type: HashType,
+) -> Result<Vec<PasswordHashEntry>, LookupError> {
+    let mut found_entries = Vec::new();
+    let mut line_start = find_first_line_not_less(data, prefix, hash_type)?;
+    while line_start < data.len() {
+        let (_, line_end) = get_line_boundaries(data, line_start, data.len(), line_start);
+        let line = &data[line_start..line_end];
+        let (line_hash, _) = split_line(line, hash_type)?;
+        if line_hash.len() < prefix.len() || !line_hash[..prefix.len()].eq_ignore_ascii_case(prefix)
+        {
+            break;
+        }
+        match std::str::from_utf8(line)
+            .ok()
+            .and_then(|text| PasswordHashEntry::from_str(text.trim()).ok())
+        {
+            Some(entry) => found_entries.push(entry),
+            None => return Err(LookupError::Format(FormatErrorKind::LineFormatNotCorrect)),
+        }
+        line_start = line_end + 1;
+    }
+    Ok(found_entries)
+}
+
+/// This class maps a password file which is ordered by hash (either the original file or a single
+/// file of an optimized database) into memory and searches it directly.
+pub struct MappedDatabase {
+    mapped_file: Option<Mmap>,
+    hash_type: Option<HashType>,
+}
+
+impl MappedDatabase {
+    /// Map the supplied password file into memory.
+    ///
+    /// The file must not be modified as long as it is mapped.
+    ///
+    /// # Example
+    /// ```
+    /// use pwned_rs::mapped::MappedDatabase;
+    /// use std::path::{Path, PathBuf};
+    ///
+    /// match MappedDatabase::from_file(Path::new("/path/to/the/hash/file.txt")) {
+    ///     Ok(instance) => println!("Mapped the password file into memory!"),
+    ///     Err(error) => println!("Could not get an instance, the error was: {}", error)
+    /// }
+    /// ```
+    pub fn from_file(path_to_file: &Path) -> Result<MappedDatabase, CreateInstanceError> {
+        let file_handle = match File::open(path_to_file) {
+            Ok(handle) => handle,
+            Err(error) => return Err(CreateInstanceError::Io(error)),
+        };
+        let file_size = match file_handle.metadata() {
+            Ok(data) => data.len(),
+            Err(error) => return Err(CreateInstanceError::Io(error)),
+        };
+
+        // an empty file cannot be mapped, but it also does not contain any hashes
+        if file_size == 0 {
+            return Ok(MappedDatabase {
+                mapped_file: None,
+                hash_type: None,
+            });
+        }
+
+        // the mapping is only valid as long as no one else modifies or truncates the file
+        match unsafe { Mmap::map(&file_handle) } {
+            Ok(mapped_file) => Ok(MappedDatabase {
+                hash_type: detect_hash_type(&mapped_file),
+                mapped_file: Some(mapped_file),
+            }),
+            Err(error) => Err(CreateInstanceError::Io(error)),
+        }
+    }
+
+    /// Get all entries whose hash starts with the supplied (hexadecimal) prefix. If a line with
+    /// the prefix cannot be parsed, an error is returned instead of an incomplete list.
+    pub fn get_entries_with_prefix(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<PasswordHashEntry>, LookupError> {
+        match (&self.mapped_file, self.hash_type) {
+            (Some(mapped_file), Some(hash_type)) => {
+                get_ordered_lines_with_prefix(mapped_file, prefix.as_bytes(), hash_type)
+            }
+            (Some(_), None) => Err(LookupError::Format(FormatErrorKind::LineFormatNotCorrect)),
+            (None, _) => Ok(Vec::new()),
+        }
+    }
+}
+
+/// The files of an optimized database which were mapped on demand. If the cache is full, the file
+/// which was not used for the longest time is unmapped.
+struct MappedFileCache {
+    mapped_files: HashMap<usize, (Arc<MappedDatabase>, u64)>,
+    use_counter: u64,
+}
+
+impl MappedFileCache {
+    fn new() -> MappedFileCache {
+        MappedFileCache {
+            mapped_files: HashMap::new(),
+            use_counter: 0,
+        }
+    }
+
+    /// Get the mapped file for the supplied prefix or map it with the supplied function. If the
+    /// function does not return a file, nothing is cached.
+    fn get_or_map(
+        &mut self,
+        prefix_index: usize,
+        map_file: impl FnOnce() -> Result<Option<MappedDatabase>, LookupError>,
+    ) -> Result<Option<Arc<MappedDatabase>>, LookupError> {
+        self.use_counter += 1;
+        if let Some((database, last_use)) = self.mapped_files.get_mut(&prefix_index) {
+            *last_use = self.use_counter;
+            return Ok(Some(Arc::clone(database)));
+        }
+
+        let database = match map_file()? {
+            Some(database) => Arc::new(database),
+            None => return Ok(None),
+        };
+        if self.mapped_files.len() >= MAPPED_FILE_CACHE_SIZE {
+            let least_recently_used = self
+                .mapped_files
+                .iter()
+                .min_by_key(|(_, (_, last_use))| *last_use)
+                .map(|(index, _)| *index);
+            if let Some(index) = least_recently_used {
+                self.mapped_files.remove(&index);
+            }
+        }
+        self.mapped_files
+            .insert(prefix_index, (Arc::clone(&database), self.use_counter));
+        Ok(Some(database))
+    }
+}
+
+/// Map the file of an optimized database with the supplied path. If the file does not exist, `None`
+/// is returned, unless the manifest lists it. The size of a listed file has to match the manifest,
+/// so a truncated file is detected as soon as it is mapped.
+fn map_prefix_file(
+    manifest: &DatabaseManifest,
+    file_path: &Path,
+) -> Result<Option<MappedDatabase>, LookupError> {
+    let file_name = file_path.file_name().unwrap_or_default().to_string_lossy();
+    if !file_path.exists() {
+        if manifest.lists_file(&file_name) {
+            return Err(LookupError::MissingFile(file_path.to_path_buf()));
+        }
+        return Ok(None);
+    }
+
+    let database = MappedDatabase::from_file(file_path)?;
+    let file_size = database
+        .mapped_file
+        .as_ref()
+        .map_or(0, |mapped_file| mapped_file.len() as u64);
+    if !manifest.matches_file_size(&file_name, file_size) {
+        return Err(LookupError::SizeMismatch(file_path.to_path_buf()));
+    }
+    Ok(Some(database))
+}
+
+/// This class maps the files of an optimized password database into memory and searches the file
+/// which belongs to the prefix of the seeked hash.
+///
+/// If the database is split by short prefixes, all files are mapped at once. For longer prefixes the
+/// number of files gets too large, so each file is mapped on demand and just the recently used
+/// files stay mapped.
+pub struct MappedPrefixDatabase {
+    database_folder: PathBuf,
+    prefix_length: usize,
+    manifest: DatabaseManifest,
+    prefix_files: Vec<Option<MappedDatabase>>,
+    mapped_file_cache: Mutex<MappedFileCache>,
+}
+
+impl MappedPrefixDatabase {
+    /// Map the files of the optimized database in the supplied folder into memory.
+    ///
+    /// Files for prefixes which do not exist in the folder are treated as empty, unless they are
+    /// listed in the manifest of the database. A file whose size does not match the manifest is
+    /// rejected when it is mapped.
+    pub fn from_folder(path_to_folder: &Path) -> Result<MappedPrefixDatabase, CreateInstanceError> {
+        let manifest = DatabaseManifest::from_folder(path_to_folder)?;
+        let prefix_length = manifest.get_prefix_length();
+
+        let mut prefix_files = Vec::new();
+        if prefix_length <= MAXIMAL_EAGER_PREFIX_LENGTH {
+            for prefix in 0..(1 << (4 * prefix_length)) {
+                let file_path =
+                    path_to_folder.join(format!("{:0width$X}.txt", prefix, width = prefix_length));
+                match map_prefix_file(&manifest, &file_path) {
+                    Ok(database) => prefix_files.push(database),
+                    Err(LookupError::Io(error)) => return Err(CreateInstanceError::Io(error)),
+                    Err(LookupError::Format(kind)) => {
+                        return Err(CreateInstanceError::Format(kind))
+                    }
+                    Err(error) => {
+                        return Err(CreateInstanceError::Io(Error::new(
+                            ErrorKind::InvalidData,
+                            error.to_string(),
+                        )))
+                    }
+                }
+            }
+        }
+
+        Ok(MappedPrefixDatabase {
+            database_folder: path_to_folder.to_path_buf(),
+            prefix_length,
+            manifest,
+            prefix_files,
+            mapped_file_cache: Mutex::new(MappedFileCache::new()),
+        })
+    }
+
+    /// Run the supplied action on the mapped file which contains the hashes with the prefix of the
+    /// supplied hash. If there is no such file, `None` is returned, unless the manifest of the
+    /// database lists it.
+    fn with_prefix_file<T>(
+        &self,
+        hash: &str,
+        action: impl FnOnce(&MappedDatabase) -> T,
+    ) -> Result<Option<T>, LookupError> {
+        let prefix = match hash.get(..self.prefix_length) {
+            Some(prefix) => prefix,
+            None => return Ok(None),
+        };
+        let prefix_index = match usize::from_str_radix(prefix, 16) {
+            Ok(index) => index,
+            Err(_) => return Ok(None),
+        };
+        if !self.prefix_files.is_empty() {
+            return Ok(self
+                .prefix_files
+                .get(prefix_index)
+                .and_then(|database| database.as_ref())
+                .map(action));
+        }
+
+        // map the file on demand, since not all files were mapped in advance
+        let file_path = self
+            .database_folder
+            .join(format!("{}.txt", prefix.to_uppercase()));
+        let database = {
+            let mut mapped_file_cache = match self.mapped_file_cache.lock() {
+                Ok(cache) => cache,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            mapped_file_cache
+                .get_or_map(prefix_index, || map_prefix_file(&self.manifest, &file_path))?
+        };
+        Ok(database.map(|database| action(&database)))
+    }
+
+    /// Get all entries whose hash starts with the supplied (hexadecimal) prefix. If a file with
+    /// the prefix cannot be mapped or parsed, an error is returned instead of an incomplete list.
+    pub fn get_entries_with_prefix(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<PasswordHashEntry>, LookupError> {
+        if prefix.len() >= self.prefix_length {
+            return self
+                .with_prefix_file(prefix, |database| database.get_entries_with_prefix(prefix))?
+                .unwrap_or_else(|| Ok(Vec::new()));
+        }
+
+        // the prefix is shorter than the prefixes of the files, so all matching files have to be read
+        let missing_length = self.prefix_length - prefix.len();
+        let mut found_entries = Vec::new();
+        for missing_part in 0..(1 << (4 * missing_length)) {
+            let file_prefix = format!(
+                "{}{:0width$X}",
+                prefix,
+                missing_part,
+                width = missing_length
+            );
+            if let Some(entries) = self.with_prefix_file(&file_prefix, |database| {
+                database.get_entries_with_prefix(prefix)
+            })? {
+                found_entries.extend(entries?);
+            }
+        }
+        Ok(found_entries)
+    }
+}
+
+impl PasswordDatabase for MappedDatabase {
+    fn occurrences(&self, hash: &HashDigest) -> Result<Option<u64>, LookupError> {
+        check_hash_type(self.hash_type, hash)?;
+        let mut hex_buffer = [0; MAXIMAL_HASH_LENGTH];
+        match &self.mapped_file {
+            Some(mapped_file) => search_ordered_lines(
+                mapped_file,
+                hash.write_hex(&mut hex_buffer).as_bytes(),
+                hash.get_hash_type(),
+            ),
+            None => Ok(None),
+        }
+    }
+
+    fn sources(&self, hash: &HashDigest) -> Result<Vec<String>, LookupError> {
+        check_hash_type(self.hash_type, hash)?;
+        let mut hex_buffer = [0; MAXIMAL_HASH_LENGTH];
+        let line = match &self.mapped_file {
+            Some(mapped_file) => find_ordered_line(
+                mapped_file,
+                hash.write_hex(&mut hex_buffer).as_bytes(),
+                hash.get_hash_type(),
+            )?,
+            None => None,
+        };
+        match line.map(std::str::from_utf8) {
+            Some(Ok(line)) => Ok(parse_source_tags(line)),
+            Some(Err(_)) => Err(LookupError::Format(FormatErrorKind::LineFormatNotCorrect)),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn get_backend_name(&self) -> &'static str {
+        "mapped-file"
+    }
+}
+
+impl PasswordDatabase for MappedPrefixDatabase {
+    fn occurrences(&self, hash: &HashDigest) -> Result<Option<u64>, LookupError> {
+        check_hash_type(self.manifest.get_hash_type(), hash)?;
+        let mut hex_buffer = [0; MAXIMAL_HASH_LENGTH];
+        match self.with_prefix_file(hash.write_hex(&mut hex_buffer), |database| {
+            database.occurrences(hash)
+        })? {
+            Some(result) => result,
+            None => Ok(None),
+        }
+    }
+
+    fn get_backend_name(&self) -> &'static str {
+        "mapped-folder"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::FileInformation;
+    use crate::testing::create_temp_path;
+    use crate::HashType;
+
+    const SAMPLE_LINES: &str = "0000000A1D4B746FAA3FD526FF6D5BC8052FDB38:16\r\n\
+                                5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3730471\r\n\
+                                7C4A8D09CA3762AF61E59520943DC26494F8941B:24230577\r\n\
+                                FDC625010C4BEB998E590924DF39B7E59298612D:2";
+
+    #[test]
+    fn searching_ordered_lines_finds_all_entries() {
+        let data = SAMPLE_LINES.as_bytes();
+
+        let first = search_ordered_lines(
+            data,
+            b"0000000a1d4b746faa3fd526ff6d5bc8052fdb38",
+            HashType::Sha1,
+        )
+        .unwrap();
+        assert_eq!(Some(16), first);
+        let middle = search_ordered_lines(
+            data,
+            b"5baa61e4c9b93f3f0682250b6cf8331b7ee68fd8",
+            HashType::Sha1,
+        )
+        .unwrap();
+        assert_eq!(Some(3730471), middle);
+        let last = search_ordered_lines(
+            data,
+            b"FDC625010C4BEB998E590924DF39B7E59298612D",
+            HashType::Sha1,
+        )
+        .unwrap();
+        assert_eq!(Some(2), last);
+    }
+
+    #[test]
+    fn searching_ordered_lines_for_a_missing_hash_returns_nothing() {
+        let data = SAMPLE_LINES.as_bytes();
+
+        let smaller = search_ordered_lines(
+            data,
+            b"0000000000000000000000000000000000000000",
+            HashType::Sha1,
+        )
+        .unwrap();
+        assert_eq!(None, smaller);
+        let between = search_ordered_lines(
+            data,
+            b"6000000000000000000000000000000000000000",
+            HashType::Sha1,
+        )
+        .unwrap();
+        assert_eq!(None, between);
+        let larger = search_ordered_lines(
+            data,
+            b"FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+            HashType::Sha1,
+        )
+        .unwrap();
+        assert_eq!(None, larger);
+        assert_eq!(
+            None,
+            search_ordered_lines(b"", b"FFFF", HashType::Sha1).unwrap()
+        );
+    }
+
+    #[test]
+    fn searching_merged_lines_ignores_the_additional_columns() {
+        let data = b"5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:10:3:7:hibp,red-team\r\n\
+                     7C4A8D09CA3762AF61E59520943DC26494F8941B:1:red-team\r\n";
+
+        let merged = search_ordered_lines(
+            data,
+            b"5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8",
+            HashType::Sha1,
+        )
+        .unwrap();
+        assert_eq!(Some(10), merged);
+        let tagged = search_ordered_lines(
+            data,
+            b"7C4A8D09CA3762AF61E59520943DC26494F8941B",
+            HashType::Sha1,
+        );
+        assert_eq!(Some(1), tagged.unwrap());
+        assert_eq!(
+            true,
+            find_ordered_line(
+                data,
+                b"7C4A8D09CA3762AF61E59520943DC26494F8941B",
+                HashType::Sha1
+            )
+            .unwrap()
+            .is_some_and(|line| line.ends_with(b":red-team\r"))
+        );
+    }
+
+    #[test]
+    fn searching_lines_with_mixed_line_endings_works() {
+        let data = b"0000000A1D4B746FAA3FD526FF6D5BC8052FDB38:16\n\
+                     5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3730471\r\n\
+                     7C4A8D09CA3762AF61E59520943DC26494F8941B:24230577\n\
+                     FDC625010C4BEB998E590924DF39B7E59298612D:2";
+
+        for (hash, count) in &[
+            (&b"0000000A1D4B746FAA3FD526FF6D5BC8052FDB38"[..], 16),
+            (&b"5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8"[..], 3730471),
+            (&b"7C4A8D09CA3762AF61E59520943DC26494F8941B"[..], 24230577),
+            (&b"FDC625010C4BEB998E590924DF39B7E59298612D"[..], 2),
+        ] {
+            let found = search_ordered_lines(data, hash, HashType::Sha1).unwrap();
+            assert_eq!(Some(*count), found);
+        }
+        let missing = search_ordered_lines(
+            data,
+            b"6000000000000000000000000000000000000000",
+            HashType::Sha1,
+        );
+        assert_eq!(None, missing.unwrap());
+    }
+
+    #[test]
+    fn searching_truncated_or_malformed_lines_fails() {
+        let truncated_hash = b"0000000A1D4B746FAA3FD526FF6D5BC8052FDB38:16\r\n\
+                               5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3730471\r\n\
+                               7C4A8D09CA3762AF61E5";
+        let truncated_count = b"0000000A1D4B746FAA3FD526FF6D5BC8052FDB38:16\r\n\
+                                5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3730471\r\n\
+                                7C4A8D09CA3762AF61E59520943DC26494F8941B:";
+        let missing_count = b"0000000A1D4B746FAA3FD526FF6D5BC8052FDB38:16\r\n\
+                              5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8\r\n\
+                              7C4A8D09CA3762AF61E59520943DC26494F8941B:24230577";
+
+        // the broken line is read while searching, so it must not be reported as not found
+        for data in &[&truncated_hash[..], &truncated_count[..]] {
+            let result = search_ordered_lines(
+                data,
+                b"7C4A8D09CA3762AF61E59520943DC26494F8941B",
+                HashType::Sha1,
+            );
+            assert_eq!(true, result.is_err());
+            let prefix_result = get_ordered_lines_with_prefix(data, b"7C4A8", HashType::Sha1);
+            assert_eq!(true, prefix_result.is_err());
+        }
+        let result = search_ordered_lines(
+            missing_count,
+            b"5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8",
+            HashType::Sha1,
+        );
+        assert_eq!(true, result.is_err());
+
+        // a line of another hash type is malformed as well
+        let result = search_ordered_lines(
+            SAMPLE_LINES.as_bytes(),
+            b"5BAA61E4C9B93F3F0682250B6CF8331B",
+            HashType::Ntlm,
+        );
+        assert_eq!(true, result.is_err());
+    }
+
+    #[test]
+    fn getting_all_lines_with_a_prefix_works() {
+        let data = SAMPLE_LINES.as_bytes();
+
+        let found_entries = get_ordered_lines_with_prefix(data, b"5baa6", HashType::Sha1).unwrap();
+        assert_eq!(1, found_entries.len());
+        assert_eq!(3730471, found_entries[0].get_occurrences());
+
+        let all_entries = get_ordered_lines_with_prefix(data, b"", HashType::Sha1).unwrap();
+        assert_eq!(4, all_entries.len());
+        let no_entries = get_ordered_lines_with_prefix(data, b"5BAA7", HashType::Sha1).unwrap();
+        assert_eq!(0, no_entries.len());
+        let broken_entries =
+            get_ordered_lines_with_prefix(b"5BAA61E4C9B93F3F:3\n", b"5BAA6", HashType::Sha1);
+        assert_eq!(true, broken_entries.is_err());
+    }
+
+    #[test]
+    fn mapping_a_password_file_works() {
+        let database_path = create_temp_path("mapped-database.txt");
+        std::fs::write(&database_path, SAMPLE_LINES).unwrap();
+
+        let database = MappedDatabase::from_file(&database_path).unwrap();
+        let digest = HashDigest::from_password("password", HashType::Sha1);
+        assert_eq!(Some(3730471), database.occurrences(&digest).unwrap());
+
+        // a hash of another type is never reported as not found
+        let ntlm_digest = HashDigest::from_password("password", HashType::Ntlm);
+        assert_eq!(true, database.occurrences(&ntlm_digest).is_err());
+        assert_eq!(true, database.sources(&ntlm_digest).is_err());
+
+        let _ = std::fs::remove_file(database_path);
+    }
+
+    #[test]
+    fn the_cache_keeps_just_the_recently_used_files_mapped() {
+        let database_path = create_temp_path("mapped-cache.txt");
+        std::fs::write(&database_path, SAMPLE_LINES).unwrap();
+        let map_file = || {
+            MappedDatabase::from_file(&database_path)
+                .map(Some)
+                .map_err(LookupError::from)
+        };
+
+        let mut cache = MappedFileCache::new();
+        for prefix_index in 0..MAPPED_FILE_CACHE_SIZE {
+            cache.get_or_map(prefix_index, map_file).unwrap();
+        }
+
+        // a cached file is not mapped again and counts as recently used
+        let cached = cache.get_or_map(0, || panic!("The file should be cached"));
+        assert_eq!(true, cached.unwrap().is_some());
+        assert_eq!(true, cache.get_or_map(1000, || Ok(None)).unwrap().is_none());
+        cache.get_or_map(MAPPED_FILE_CACHE_SIZE, map_file).unwrap();
+        assert_eq!(MAPPED_FILE_CACHE_SIZE, cache.mapped_files.len());
+        assert_eq!(true, cache.mapped_files.contains_key(&0));
+        assert_eq!(false, cache.mapped_files.contains_key(&1));
+
+        let _ = std::fs::remove_file(database_path);
+    }
+
+    /// Create an optimized database whose only file is truncated after the manifest was written.
+    fn create_truncated_database(folder_name: &str, prefix_length: usize) -> PathBuf {
+        let database_folder = create_temp_path(folder_name);
+        std::fs::create_dir_all(&database_folder).unwrap();
+        let file_name = format!("{}.txt", &"5BAA61"[..prefix_length]);
+        let content = "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3730471\r\n";
+        std::fs::write(database_folder.join(&file_name), &content[..30]).unwrap();
+
+        let mut manifest = DatabaseManifest::new(prefix_length);
+        manifest.set_hash_type(HashType::Sha1);
+        manifest.add_file(
+            &file_name,
+            FileInformation::new(1, content.len() as u64, "abcdef"),
+        );
+        manifest.write_to_folder(&database_folder).unwrap();
+        database_folder
+    }
+
+    #[test]
+    fn mapping_a_truncated_file_of_an_optimized_database_fails() {
+        // with a short prefix, all files are mapped (and checked) in advance
+        let eager_folder = create_truncated_database("mapped-truncated-eager", 3);
+        assert_eq!(
+            true,
+            MappedPrefixDatabase::from_folder(&eager_folder).is_err()
+        );
+
+        let lazy_folder = create_truncated_database("mapped-truncated-lazy", 4);
+        let database = MappedPrefixDatabase::from_folder(&lazy_folder).unwrap();
+        let digest = HashDigest::from_password("password", HashType::Sha1);
+        assert_eq!(
+            true,
+            matches!(
+                database.occurrences(&digest),
+                Err(LookupError::SizeMismatch(_))
+            )
+        );
+        let other_digest = HashDigest::from_password("123456", HashType::Sha1);
+        assert_eq!(None, database.occurrences(&other_digest).unwrap());
+
+        let _ = std::fs::remove_dir_all(eager_folder);
+        let _ = std::fs::remove_dir_all(lazy_folder);
+    }
+}
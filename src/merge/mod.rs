@@ -0,0 +1,643 @@
+use crate::error::PwnedError;
+use crate::haveibeenpwned::DatabaseIterator;
+use crate::{HashType, PasswordHashEntry};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::{BufRead, ErrorKind};
+use std::iter::Peekable;
+use std::str::FromStr;
+
+/// The name of the backend which is reported for a password file which is merged with the input.
+pub const MERGE_BACKEND_NAME: &str = "ordered-file-merge";
+
+/// The separator between the IDs of the sources a merged entry was found in.
+const SOURCE_ID_SEPARATOR: char = ',';
+
+/// Something which can be looked up with a merge join, since it has a password hash.
+pub trait MergeCandidate {
+    fn get_password_hash(&self) -> &PasswordHashEntry;
+}
+
+impl MergeCandidate for PasswordHashEntry {
+    fn get_password_hash(&self) -> &PasswordHashEntry {
+        self
+    }
+}
+
+/// This class walks through an ordered password file and an ordered stream of candidates at the
+/// same time, so the password file is read just once in a single forward pass and no entry has to
+/// be searched. Every candidate is returned together with the number of its occurrences in the
+/// password file (`None` if it is not part of it). A candidate can occur multiple times.
+///
+/// The iterator stops at the first error (an unordered password file or candidate stream or a
+/// line which could not be parsed), which can be taken with `take_error`.
+///
+/// # Example
+/// ```
+/// use pwned_rs::haveibeenpwned::DatabaseIterator;
+/// use pwned_rs::merge::MergeJoin;
+/// use pwned_rs::PasswordHashEntry;
+///
+/// if let Ok(parser) = DatabaseIterator::from_file("/path/to/the/hash/file.txt") {
+///     let candidates = vec![PasswordHashEntry::from_password("password")];
+///     let mut merge_join = MergeJoin::new(parser, candidates.into_iter());
+///     for (candidate, occurrences) in merge_join.by_ref() {
+///         println!("{}: {:?}", candidate.get_hash(), occurrences);
+///     }
+///     if let Some(error) = merge_join.take_error() {
+///         println!("The lookup failed: {}", error);
+///     }
+/// }
+/// ```
+pub struct MergeJoin<I: Iterator>
+where
+    I::Item: MergeCandidate,
+{
+    database: DatabaseIterator,
+    current_entry: Option<PasswordHashEntry>,
+    database_started: bool,
+    candidates: Peekable<I>,
+    candidate_number: u64,
+    error: Option<PwnedError>,
+}
+
+impl<I: Iterator> MergeJoin<I>
+where
+    I::Item: MergeCandidate,
+{
+    /// Create a merge join of the password file and the candidates. Both have to be ordered by
+    /// hash, which is checked while they are read.
+    pub fn new(mut database: DatabaseIterator, candidates: I) -> MergeJoin<I> {
+        database.set_order_check(true);
+        MergeJoin {
+            database,
+            current_entry: None,
+            database_started: false,
+            candidates: candidates.peekable(),
+            candidate_number: 0,
+            error: None,
+        }
+    }
+
+    /// Get the number of bytes which were read from the password file so far.
+    pub fn get_consumed_bytes(&self) -> u64 {
+        self.database.get_consumed_bytes()
+    }
+
+    /// Get the size of the password file, if it is known.
+    pub fn get_file_size(&self) -> Option<u64> {
+        self.database.get_file_size()
+    }
+
+    /// Take the error which stopped the merge join early. If all candidates were looked up,
+    /// `None` is returned.
+    pub fn take_error(&mut self) -> Option<PwnedError> {
+        self.error.take()
+    }
+
+    /// Read the next entry of the password file. The parser ensures that it is not lower than the
+    /// last one, otherwise the merge would silently miss candidates.
+    fn advance_database(&mut self) -> Result<(), PwnedError> {
+        self.current_entry = self.database.next();
+        if self.current_entry.is_none() {
+            // an early stop of the parser must not be reported as candidates which were not found
+            if let Some(error) = self.database.take_error() {
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    fn lookup_candidate(
+        &mut self,
+        candidate: &PasswordHashEntry,
+    ) -> Result<Option<u64>, PwnedError> {
+        if !self.database_started {
+            self.database_started = true;
+            self.advance_database()?;
+        }
+        while self
+            .current_entry
+            .as_ref()
+            .is_some_and(|entry| entry < candidate)
+        {
+            self.advance_database()?;
+        }
+
+        // the current entry is kept, since the next candidate could be the same one
+        match &self.current_entry {
+            Some(entry) if entry == candidate => Ok(Some(entry.get_occurrences())),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<I: Iterator> Iterator for MergeJoin<I>
+where
+    I::Item: MergeCandidate,
+{
+    type Item = (I::Item, Option<u64>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
+        let candidate = self.candidates.next()?;
+        self.candidate_number += 1;
+
+        // the candidates are checked before the current one is returned, so no result is wrong
+        if let Some(next_candidate) = self.candidates.peek() {
+            if next_candidate.get_password_hash() < candidate.get_password_hash() {
+                self.error = Some(PwnedError::InvalidArgument(format!(
+                    "The candidates are not ordered by hash, candidate {} is out of order.",
+                    self.candidate_number + 1
+                )));
+                return None;
+            }
+        }
+
+        match self.lookup_candidate(candidate.get_password_hash()) {
+            Ok(occurrences) => Some((candidate, occurrences)),
+            Err(error) => {
+                self.error = Some(error);
+                None
+            }
+        }
+    }
+}
+
+/// A hash which was read from a file of candidates together with the line it was found on.
+pub struct HashCandidate {
+    line_number: u64,
+    password_hash: PasswordHashEntry,
+}
+
+impl HashCandidate {
+    pub fn get_line_number(&self) -> u64 {
+        self.line_number
+    }
+}
+
+impl MergeCandidate for HashCandidate {
+    fn get_password_hash(&self) -> &PasswordHashEntry {
+        &self.password_hash
+    }
+}
+
+/// This class reads the candidates for a merge join from a file with one hash per line. Anything
+/// after the hash which is separated by a colon (like an occurrence count) is ignored. Empty lines
+/// are skipped. Like the password file parser, it stops at the first line which is not valid.
+pub struct CandidateReader<R: BufRead> {
+    reader: R,
+    hash_type: HashType,
+    line_buffer: String,
+    line_number: u64,
+    error: Option<PwnedError>,
+}
+
+impl<R: BufRead> CandidateReader<R> {
+    /// Create a reader for candidates with hashes of the supplied type.
+    pub fn new(reader: R, hash_type: HashType) -> CandidateReader<R> {
+        CandidateReader {
+            reader,
+            hash_type,
+            line_buffer: String::new(),
+            line_number: 0,
+            error: None,
+        }
+    }
+
+    /// Take the error which stopped the reader early. If all candidates were read, `None` is
+    /// returned.
+    pub fn take_error(&mut self) -> Option<PwnedError> {
+        self.error.take()
+    }
+}
+
+impl<R: BufRead> Iterator for CandidateReader<R> {
+    type Item = HashCandidate;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
+
+        loop {
+            self.line_buffer.clear();
+            match self.reader.read_line(&mut self.line_buffer) {
+                Ok(0) => return None,
+                Ok(_) => self.line_number += 1,
+                Err(error) if error.kind() == ErrorKind::InvalidData => {
+                    self.error = Some(PwnedError::InvalidArgument(format!(
+                        "Line {} of the candidates is not valid UTF-8.",
+                        self.line_number + 1
+                    )));
+                    return None;
+                }
+                Err(error) => {
+                    self.error = Some(PwnedError::Io(
+                        "Could not read the candidates".to_string(),
+                        error,
+                    ));
+                    return None;
+                }
+            }
+
+            let hash = self
+                .line_buffer
+                .split(':')
+                .next()
+                .unwrap_or_default()
+                .trim();
+            if hash.is_empty() {
+                continue;
+            }
+            return match PasswordHashEntry::from_hash_with_type(hash, self.hash_type) {
+                Ok(password_hash) => Some(HashCandidate {
+                    line_number: self.line_number,
+                    password_hash,
+                }),
+                Err(error) => {
+                    self.error = Some(PwnedError::InvalidArgument(format!(
+                        "Line {} of the candidates could not be parsed: {}",
+                        self.line_number, error
+                    )));
+                    None
+                }
+            };
+        }
+    }
+}
+
+/// How the occurrences of a hash which is part of several sources are combined by a
+/// [SourceMerge](struct.SourceMerge.html).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CountMode {
+    /// The occurrences of all sources are added up.
+    Sum,
+    /// The highest number of occurrences of all sources is used.
+    Max,
+    /// The occurrences are added up and the ones of each source are kept in an additional column.
+    Columns,
+}
+
+impl FromStr for CountMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "sum" => Ok(CountMode::Sum),
+            "max" => Ok(CountMode::Max),
+            "columns" => Ok(CountMode::Columns),
+            _ => Err(format!("The count mode {} is not supported.", value)),
+        }
+    }
+}
+
+impl Display for CountMode {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            CountMode::Sum => write!(f, "sum"),
+            CountMode::Max => write!(f, "max"),
+            CountMode::Columns => write!(f, "columns"),
+        }
+    }
+}
+
+/// Check if the supplied ID can be used for tagging the entries of a merged password file. It has
+/// to start with a letter (so it cannot be confused with a count) and may just contain letters,
+/// digits, `-` and `_`.
+pub fn is_valid_source_id(source_id: &str) -> bool {
+    source_id.starts_with(|c: char| c.is_ascii_alphabetic())
+        && source_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Get the IDs of the sources a line of a merged password file is tagged with. They are stored in
+/// the last column after the count (and the counts of the single sources), e.g.
+/// `5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:12:hibp,red-team`. Lines without tags return an
+/// empty list.
+pub fn parse_source_tags(line: &str) -> Vec<String> {
+    match line.trim().split(':').skip(2).last() {
+        Some(tags) if tags.starts_with(|c: char| c.is_ascii_alphabetic()) => tags
+            .split(SOURCE_ID_SEPARATOR)
+            .map(|source_id| source_id.to_string())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// A hash of several merged sources together with its occurrences in each of them.
+pub struct MergedEntry {
+    hash: String,
+    counts: Vec<u64>,
+    found_in: Vec<bool>,
+}
+
+impl MergedEntry {
+    pub fn get_hash(&self) -> &str {
+        &self.hash
+    }
+
+    /// Get the occurrences of the hash in each source (in the order of the sources), which is 0 if
+    /// the source does not contain the hash.
+    pub fn get_counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Get the combined occurrences of the hash.
+    pub fn get_occurrences(&self, count_mode: CountMode) -> u64 {
+        match count_mode {
+            CountMode::Max => self.counts.iter().copied().max().unwrap_or_default(),
+            CountMode::Sum | CountMode::Columns => self
+                .counts
+                .iter()
+                .fold(0u64, |total, count| total.saturating_add(*count)),
+        }
+    }
+
+    /// Get the line of the merged password file for this entry. The counts of the single sources
+    /// are appended for [CountMode::Columns](enum.CountMode.html) and the IDs of the sources which
+    /// contain the hash are appended if they are supplied.
+    pub fn get_line_to_write(
+        &self,
+        count_mode: CountMode,
+        source_ids: Option<&[String]>,
+    ) -> String {
+        let mut line = format!("{}:{}", self.hash, self.get_occurrences(count_mode));
+        if count_mode == CountMode::Columns {
+            for count in &self.counts {
+                line.push_str(&format!(":{}", count));
+            }
+        }
+        if let Some(source_ids) = source_ids {
+            let found_sources: Vec<&str> = source_ids
+                .iter()
+                .zip(&self.found_in)
+                .filter(|(_, found)| **found)
+                .map(|(source_id, _)| source_id.as_str())
+                .collect();
+            line.push(':');
+            line.push_str(&found_sources.join(&SOURCE_ID_SEPARATOR.to_string()));
+        }
+        line.push('\n');
+        line
+    }
+}
+
+/// This class merges several password files which are ordered by hash into a single ordered
+/// stream of entries. Each hash is returned once together with its occurrences in every source,
+/// so all files are read just once in a single forward pass.
+///
+/// The iterator stops at the first error of one of the sources, which can be taken with
+/// `take_error`.
+///
+/// # Example
+/// ```
+/// use pwned_rs::haveibeenpwned::DatabaseIterator;
+/// use pwned_rs::merge::{CountMode, SourceMerge};
+///
+/// let sources = vec![
+///     DatabaseIterator::from_file("/path/to/the/hash/file.txt"),
+///     DatabaseIterator::from_file("/path/to/the/internal/list.txt"),
+/// ];
+/// if let Ok(sources) = sources.into_iter().collect::<Result<Vec<_>, _>>() {
+///     if let Ok(mut merge) = SourceMerge::new(sources) {
+///         for entry in merge.by_ref() {
+///             print!("{}", entry.get_line_to_write(CountMode::Sum, None));
+///         }
+///     }
+/// }
+/// ```
+pub struct SourceMerge {
+    sources: Vec<DatabaseIterator>,
+    heap: BinaryHeap<Reverse<(PasswordHashEntry, usize)>>,
+    started: bool,
+    error: Option<PwnedError>,
+    failed_source: Option<usize>,
+}
+
+impl SourceMerge {
+    /// Create a merge of the supplied sources. All of them have to contain hashes of the same
+    /// type and have to be ordered by hash, which is checked while they are read.
+    pub fn new(mut sources: Vec<DatabaseIterator>) -> Result<SourceMerge, PwnedError> {
+        if let Some(first_source) = sources.first() {
+            let hash_type = first_source.get_hash_type();
+            if let Some(index) = sources
+                .iter()
+                .position(|source| source.get_hash_type() != hash_type)
+            {
+                return Err(PwnedError::InvalidArgument(format!(
+                    "Source {} contains {} hashes, but the first one contains {} hashes.",
+                    index + 1,
+                    sources[index].get_hash_type(),
+                    hash_type
+                )));
+            }
+        }
+        for source in sources.iter_mut() {
+            source.set_order_check(true);
+        }
+
+        Ok(SourceMerge {
+            heap: BinaryHeap::with_capacity(sources.len()),
+            sources,
+            started: false,
+            error: None,
+            failed_source: None,
+        })
+    }
+
+    /// Get the number of bytes which were read from all sources so far.
+    pub fn get_consumed_bytes(&self) -> u64 {
+        self.sources
+            .iter()
+            .map(|source| source.get_consumed_bytes())
+            .sum()
+    }
+
+    /// Get the size of all sources, if it is known for every one of them.
+    pub fn get_file_size(&self) -> Option<u64> {
+        self.sources
+            .iter()
+            .map(|source| source.get_file_size())
+            .sum()
+    }
+
+    /// Get the index of the source which stopped the merge early.
+    pub fn get_failed_source(&self) -> Option<usize> {
+        self.failed_source
+    }
+
+    /// Take the error which stopped the merge early. If all sources were merged completely,
+    /// `None` is returned.
+    pub fn take_error(&mut self) -> Option<PwnedError> {
+        self.error.take()
+    }
+
+    /// Read the next entry of the supplied source into the heap.
+    fn advance_source(&mut self, source_index: usize) {
+        match self.sources[source_index].next() {
+            Some(entry) => self.heap.push(Reverse((entry, source_index))),
+            None => {
+                if let Some(error) = self.sources[source_index].take_error() {
+                    self.error = Some(error);
+                    self.failed_source = Some(source_index);
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for SourceMerge {
+    type Item = MergedEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            for source_index in 0..self.sources.len() {
+                self.advance_source(source_index);
+            }
+        }
+        if self.error.is_some() {
+            return None;
+        }
+
+        // the lowest hash is collected from all sources (and repeated lines of the same source)
+        let Reverse((lowest_entry, source_index)) = self.heap.pop()?;
+        let mut merged_entry = MergedEntry {
+            hash: lowest_entry.hash.to_uppercase(),
+            counts: vec![0; self.sources.len()],
+            found_in: vec![false; self.sources.len()],
+        };
+        merged_entry.counts[source_index] = lowest_entry.occurrences;
+        merged_entry.found_in[source_index] = true;
+        self.advance_source(source_index);
+        while let Some(Reverse((next_entry, _))) = self.heap.peek() {
+            if *next_entry != lowest_entry {
+                break;
+            }
+            if let Some(Reverse((next_entry, source_index))) = self.heap.pop() {
+                merged_entry.counts[source_index] =
+                    merged_entry.counts[source_index].saturating_add(next_entry.occurrences);
+                merged_entry.found_in[source_index] = true;
+                self.advance_source(source_index);
+            }
+        }
+
+        // an entry whose hash could still be part of the failed source must not be returned
+        if self.error.is_some() {
+            return None;
+        }
+        Some(merged_entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::create_parser;
+
+    const SAMPLE_PASSWORD_FILE: &str = "\
+        000000005AD76BD555C1D6D771DE417A4B87E4B4:4\n\
+        5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3\n\
+        F00000005AD76BD555C1D6D771DE417A4B87E4B4:1\n";
+
+    fn read_candidates(content: &str) -> Vec<HashCandidate> {
+        CandidateReader::new(content.as_bytes(), HashType::Sha1).collect()
+    }
+
+    #[test]
+    fn merging_ordered_candidates_works() {
+        let candidates = read_candidates(
+            "000000005AD76BD555C1D6D771DE417A4B87E4B4\n\
+             \n\
+             5baa61e4c9b93f3f0682250b6cf8331b7ee68fd8:ignored\n\
+             5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8\n\
+             FF0000005AD76BD555C1D6D771DE417A4B87E4B4\n",
+        );
+        let mut merge_join =
+            MergeJoin::new(create_parser(SAMPLE_PASSWORD_FILE), candidates.into_iter());
+
+        let results: Vec<(u64, Option<u64>)> = merge_join
+            .by_ref()
+            .map(|(candidate, occurrences)| (candidate.get_line_number(), occurrences))
+            .collect();
+        assert_eq!(
+            vec![(1, Some(4)), (3, Some(3)), (4, Some(3)), (5, None)],
+            results
+        );
+        assert_eq!(true, merge_join.take_error().is_none());
+    }
+
+    #[test]
+    fn merging_unordered_input_fails() {
+        let candidates = read_candidates(
+            "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8\n\
+             000000005AD76BD555C1D6D771DE417A4B87E4B4\n",
+        );
+        let mut merge_join =
+            MergeJoin::new(create_parser(SAMPLE_PASSWORD_FILE), candidates.into_iter());
+        assert_eq!(0, merge_join.by_ref().count());
+        assert_eq!(true, merge_join.take_error().is_some());
+
+        let unordered_file = "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3\n\
+                              000000005AD76BD555C1D6D771DE417A4B87E4B4:4\n";
+        let candidates = read_candidates("F00000005AD76BD555C1D6D771DE417A4B87E4B4\n");
+        let mut merge_join = MergeJoin::new(create_parser(unordered_file), candidates.into_iter());
+        assert_eq!(0, merge_join.by_ref().count());
+        match merge_join.take_error() {
+            Some(PwnedError::UnsortedInput { line_number, .. }) => assert_eq!(2, line_number),
+            _ => panic!("The unordered password file was not detected"),
+        }
+    }
+
+    #[test]
+    fn merging_sources_combines_their_counts() {
+        let internal_list = "5baa61e4c9b93f3f0682250b6cf8331b7ee68fd8:2\n\
+                             5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:5\n\
+                             7C4A8D09CA3762AF61E59520943DC26494F8941B:1\n";
+        let mut merge = SourceMerge::new(vec![
+            create_parser(SAMPLE_PASSWORD_FILE),
+            create_parser(internal_list),
+        ])
+        .unwrap();
+        let source_ids = vec!["hibp".to_string(), "red-team".to_string()];
+
+        let merged_entries: Vec<MergedEntry> = merge.by_ref().collect();
+        assert_eq!(true, merge.take_error().is_none());
+        assert_eq!(4, merged_entries.len());
+        assert_eq!(&[3, 7], merged_entries[1].get_counts());
+        assert_eq!(
+            "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:10\n",
+            merged_entries[1].get_line_to_write(CountMode::Sum, None)
+        );
+        assert_eq!(
+            "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:10:3:7:hibp,red-team\n",
+            merged_entries[1].get_line_to_write(CountMode::Columns, Some(&source_ids))
+        );
+        assert_eq!(
+            "7C4A8D09CA3762AF61E59520943DC26494F8941B:1:red-team\n",
+            merged_entries[2].get_line_to_write(CountMode::Max, Some(&source_ids))
+        );
+
+        let line = merged_entries[1].get_line_to_write(CountMode::Columns, Some(&source_ids));
+        assert_eq!(vec!["hibp", "red-team"], parse_source_tags(&line));
+        assert_eq!(
+            true,
+            parse_source_tags("5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3:3\r\n").is_empty()
+        );
+    }
+
+    #[test]
+    fn reading_invalid_candidates_fails() {
+        let mut candidate_reader = CandidateReader::new(
+            "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8\nnot-a-hash\n".as_bytes(),
+            HashType::Sha1,
+        );
+        assert_eq!(1, candidate_reader.by_ref().count());
+        assert_eq!(true, candidate_reader.take_error().is_some());
+    }
+}
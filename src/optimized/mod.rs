@@ -0,0 +1,134 @@
+use crate::database::{LookupError, PasswordDatabase};
+use crate::haveibeenpwned::{CreateInstanceError, DatabaseReader};
+use crate::manifest::DatabaseManifest;
+use crate::HashDigest;
+use log::debug;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// This class looks up password hashes in an optimized password database. For each lookup, the
+/// file which belongs to the prefix of the hash is read into memory. The last read file is kept, so
+/// looking up hashes in sorted order reads each file just once.
+pub struct OptimizedDatabase {
+    database_folder: PathBuf,
+    manifest: DatabaseManifest,
+    loaded_file: Mutex<Option<(PathBuf, DatabaseReader)>>,
+}
+
+impl OptimizedDatabase {
+    /// Open the optimized database in the supplied folder.
+    ///
+    /// # Example
+    /// ```
+    /// use pwned_rs::optimized::OptimizedDatabase;
+    /// use std::path::Path;
+    ///
+    /// match OptimizedDatabase::from_folder(Path::new("/path/to/optimized/database")) {
+    ///     Ok(instance) => println!("Opened the optimized database!"),
+    ///     Err(error) => println!("Could not get an instance, the error was: {}", error)
+    /// }
+    /// ```
+    pub fn from_folder(path_to_folder: &Path) -> Result<OptimizedDatabase, CreateInstanceError> {
+        Ok(OptimizedDatabase {
+            database_folder: path_to_folder.to_path_buf(),
+            manifest: DatabaseManifest::from_folder(path_to_folder)?,
+            loaded_file: Mutex::new(None),
+        })
+    }
+
+    /// Get the manifest which describes the files of the database.
+    pub fn get_manifest(&self) -> &DatabaseManifest {
+        &self.manifest
+    }
+}
+
+impl PasswordDatabase for OptimizedDatabase {
+    fn occurrences(&self, hash: &HashDigest) -> Result<Option<u64>, LookupError> {
+        if let Some(database_type) = self.manifest.get_hash_type() {
+            if database_type != hash.get_hash_type() {
+                return Err(LookupError::HashTypeMismatch {
+                    database: database_type,
+                    requested: hash.get_hash_type(),
+                });
+            }
+        }
+
+        // if there is no file for the prefix, there is no password hash starting with it (unless
+        // the file was deleted, which must not be reported as a password which was not found)
+        let file_path = self.manifest.get_file_path(&self.database_folder, hash);
+        if !file_path.exists() {
+            let file_name = file_path.file_name().unwrap_or_default().to_string_lossy();
+            if self.manifest.lists_file(&file_name) {
+                return Err(LookupError::MissingFile(file_path));
+            }
+            return Ok(None);
+        }
+
+        let mut loaded_file = match self.loaded_file.lock() {
+            Ok(loaded_file) => loaded_file,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some((loaded_path, database)) = loaded_file.as_ref() {
+            if *loaded_path == file_path {
+                return database.occurrences(hash);
+            }
+        }
+
+        // the size is checked just for the file which is actually read, not for the whole database
+        let file_name = file_path.file_name().unwrap_or_default().to_string_lossy();
+        if !self
+            .manifest
+            .matches_file_size(&file_name, file_path.metadata()?.len())
+        {
+            return Err(LookupError::SizeMismatch(file_path));
+        }
+
+        debug!("Looking up passwords in {}...", file_path.display());
+        let database = DatabaseReader::from_file(&file_path)?;
+        let found_count = database.occurrences(hash);
+        *loaded_file = Some((file_path, database));
+        found_count
+    }
+
+    fn get_backend_name(&self) -> &'static str {
+        "optimized-folder"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::FileInformation;
+    use crate::testing::create_temp_path;
+    use crate::HashType;
+
+    #[test]
+    fn looking_up_a_hash_in_a_truncated_file_fails() {
+        let database_folder = create_temp_path("optimized-truncated");
+        std::fs::create_dir_all(&database_folder).unwrap();
+        let content = "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3730471\r\n";
+        std::fs::write(database_folder.join("5BA.txt"), &content[..30]).unwrap();
+        let mut manifest = DatabaseManifest::new(3);
+        manifest.set_hash_type(HashType::Sha1);
+        manifest.add_file(
+            "5BA.txt",
+            FileInformation::new(1, content.len() as u64, "abcdef"),
+        );
+        manifest.write_to_folder(&database_folder).unwrap();
+
+        // the database can be opened, the size is checked for the file which is read
+        let database = OptimizedDatabase::from_folder(&database_folder).unwrap();
+        let digest = HashDigest::from_password("password", HashType::Sha1);
+        assert_eq!(
+            true,
+            matches!(
+                database.occurrences(&digest),
+                Err(LookupError::SizeMismatch(_))
+            )
+        );
+        let other_digest = HashDigest::from_password("123456", HashType::Sha1);
+        assert_eq!(None, database.occurrences(&other_digest).unwrap());
+
+        let _ = std::fs::remove_dir_all(database_folder);
+    }
+}
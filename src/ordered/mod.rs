@@ -0,0 +1,223 @@
+use crate::database::{LookupError, PasswordDatabase};
+use crate::haveibeenpwned::{CreateInstanceError, FormatErrorKind};
+use crate::merge::parse_source_tags;
+use crate::{HashDigest, PasswordHashEntry};
+use std::cmp::Ordering;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// This class searches a password file which is ordered by hash (either the original file or a
+/// single file of an optimized database) with a binary search directly on the file. Just a few
+/// lines of the file have to be read for each lookup.
+pub struct DivideAndConquerLookup {
+    file_handle: Mutex<BufReader<File>>,
+    file_size: u64,
+}
+
+impl DivideAndConquerLookup {
+    /// Open the supplied password file for searching it.
+    ///
+    /// # Example
+    /// ```
+    /// use pwned_rs::ordered::DivideAndConquerLookup;
+    /// use std::path::Path;
+    ///
+    /// match DivideAndConquerLookup::from_file(Path::new("/path/to/the/hash/file.txt")) {
+    ///     Ok(instance) => println!("Opened the password file for searching it!"),
+    ///     Err(error) => println!("Could not get an instance, the error was: {}", error)
+    /// }
+    /// ```
+    pub fn from_file(password_file: &Path) -> Result<DivideAndConquerLookup, CreateInstanceError> {
+        let file_handle = match OpenOptions::new()
+            .append(false)
+            .write(false)
+            .read(true)
+            .open(password_file)
+        {
+            Ok(handle) => handle,
+            Err(error) => return Err(CreateInstanceError::Io(error)),
+        };
+        let file_size = match file_handle.metadata() {
+            Ok(metadata) => metadata.len(),
+            Err(error) => return Err(CreateInstanceError::Io(error)),
+        };
+
+        Ok(DivideAndConquerLookup {
+            file_handle: Mutex::new(BufReader::new(file_handle)),
+            file_size,
+        })
+    }
+}
+
+/// Get the position of the first line which starts at or after the supplied position.
+fn get_next_line_start(reader: &mut BufReader<File>, position: u64) -> Result<u64, LookupError> {
+    if position == 0 {
+        return Ok(0);
+    }
+
+    // if the previous character is a line break, a line starts at the position itself
+    reader.seek(SeekFrom::Start(position - 1))?;
+    let mut skipped_line = Vec::new();
+    let skipped_bytes = reader.read_until(b'\n', &mut skipped_line)?;
+    Ok(position - 1 + skipped_bytes as u64)
+}
+
+impl DivideAndConquerLookup {
+    /// Search the line of the supplied password hash and return it together with its parsed entry.
+    fn find_line(
+        &self,
+        hash: &HashDigest,
+    ) -> Result<Option<(PasswordHashEntry, String)>, LookupError> {
+        let seeked_hash = hash.to_hex();
+        let mut reader = match self.file_handle.lock() {
+            Ok(reader) => reader,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        // all lines which start in the range from the head to the tail position are candidates
+        let mut head_position = 0;
+        let mut tail_position = self.file_size;
+        let mut line_read_buffer = String::new();
+        while head_position < tail_position {
+            let mid = head_position + (tail_position - head_position) / 2;
+            let line_start = get_next_line_start(&mut reader, mid)?;
+            if line_start >= tail_position {
+                tail_position = mid;
+                continue;
+            }
+
+            // read the line and extract the password hash
+            reader.seek(SeekFrom::Start(line_start))?;
+            line_read_buffer.clear();
+            let line_length = reader.read_line(&mut line_read_buffer)? as u64;
+            let entry_at_current_line = match PasswordHashEntry::from_str(line_read_buffer.trim()) {
+                Ok(entry) => entry,
+                Err(_) => return Err(LookupError::Format(FormatErrorKind::LineFormatNotCorrect)),
+            };
+            if entry_at_current_line.get_hash_type() != hash.get_hash_type() {
+                return Err(LookupError::HashTypeMismatch {
+                    database: entry_at_current_line.get_hash_type(),
+                    requested: hash.get_hash_type(),
+                });
+            }
+
+            // determine in which block we should continue our search
+            match entry_at_current_line
+                .get_hash()
+                .to_uppercase()
+                .cmp(&seeked_hash)
+            {
+                Ordering::Equal => return Ok(Some((entry_at_current_line, line_read_buffer))),
+                Ordering::Less => head_position = line_start + line_length,
+                Ordering::Greater => tail_position = line_start,
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl PasswordDatabase for DivideAndConquerLookup {
+    fn occurrences(&self, hash: &HashDigest) -> Result<Option<u64>, LookupError> {
+        Ok(self
+            .find_line(hash)?
+            .map(|(entry, _)| entry.get_occurrences()))
+    }
+
+    fn sources(&self, hash: &HashDigest) -> Result<Vec<String>, LookupError> {
+        Ok(self
+            .find_line(hash)?
+            .map(|(_, line)| parse_source_tags(&line))
+            .unwrap_or_default())
+    }
+
+    fn get_backend_name(&self) -> &'static str {
+        "ordered-file"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::create_sample_file;
+    use crate::HashType;
+
+    #[test]
+    fn looking_up_all_entries_of_an_ordered_file_works() {
+        let file_path = create_sample_file(
+            "ordered-lookup.txt",
+            "0000000A1D4B746FAA3FD526FF6D5BC8052FDB38:16\r\n\
+             5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3730471\r\n\
+             7C4A8D09CA3762AF61E59520943DC26494F8941B:24230577\r\n\
+             FDC625010C4BEB998E590924DF39B7E59298612D:2\r\n",
+        );
+        let database = DivideAndConquerLookup::from_file(&file_path).unwrap();
+
+        for (hash, count) in &[
+            ("0000000a1d4b746faa3fd526ff6d5bc8052fdb38", 16),
+            ("5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8", 3730471),
+            ("7C4A8D09CA3762AF61E59520943DC26494F8941B", 24230577),
+            ("FDC625010C4BEB998E590924DF39B7E59298612D", 2),
+        ] {
+            let digest = HashDigest::from_hex(hash, HashType::Sha1).unwrap();
+            assert_eq!(Some(*count), database.occurrences(&digest).unwrap());
+        }
+        for hash in &[
+            "0000000000000000000000000000000000000000",
+            "6000000000000000000000000000000000000000",
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+        ] {
+            let digest = HashDigest::from_hex(hash, HashType::Sha1).unwrap();
+            assert_eq!(None, database.occurrences(&digest).unwrap());
+        }
+
+        let ntlm_digest = HashDigest::from_password("password", HashType::Ntlm);
+        assert_eq!(true, database.occurrences(&ntlm_digest).is_err());
+
+        let _ = std::fs::remove_file(file_path);
+    }
+
+    #[test]
+    fn looking_up_entries_in_a_file_with_mixed_line_endings_works() {
+        let file_path = create_sample_file(
+            "ordered-mixed-line-endings.txt",
+            "0000000A1D4B746FAA3FD526FF6D5BC8052FDB38:16\n\
+             5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3730471\r\n\
+             7C4A8D09CA3762AF61E59520943DC26494F8941B:24230577\n\
+             FDC625010C4BEB998E590924DF39B7E59298612D:2",
+        );
+        let database = DivideAndConquerLookup::from_file(&file_path).unwrap();
+
+        for (password, count) in &[
+            ("password", 3730471),
+            ("123456", 24230577),
+            ("sample_password", 2),
+        ] {
+            let digest = HashDigest::from_password(password, HashType::Sha1);
+            assert_eq!(Some(*count), database.occurrences(&digest).unwrap());
+        }
+        let digest = HashDigest::from_password("unknown", HashType::Sha1);
+        assert_eq!(None, database.occurrences(&digest).unwrap());
+
+        let _ = std::fs::remove_file(file_path);
+    }
+
+    #[test]
+    fn looking_up_an_entry_in_a_truncated_file_fails() {
+        let file_path = create_sample_file(
+            "ordered-truncated.txt",
+            "0000000A1D4B746FAA3FD526FF6D5BC8052FDB38:16\r\n\
+             5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3730471\r\n\
+             7C4A8D09CA3762AF61E5",
+        );
+        let database = DivideAndConquerLookup::from_file(&file_path).unwrap();
+
+        // the truncated line is read while searching, so it must not be reported as not found
+        let digest = HashDigest::from_password("123456", HashType::Sha1);
+        assert_eq!(true, database.occurrences(&digest).is_err());
+
+        let _ = std::fs::remove_file(file_path);
+    }
+}
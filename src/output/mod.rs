@@ -0,0 +1,215 @@
+use crate::HashDigest;
+use serde::Serialize;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::{Error, Write};
+use std::str::FromStr;
+
+/// The number of hash characters which are always part of a record (the prefix of the range API).
+const HASH_PREFIX_LENGTH: usize = 5;
+
+/// The columns of the CSV output in the order in which they are written.
+const CSV_HEADER: &str = "input,hash_prefix,hash,found,occurrences,backend,sources";
+
+/// The formats in which the results of a lookup can be written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// A sentence for humans per result.
+    Plain,
+    /// One JSON object per result and line.
+    Json,
+    /// A header line followed by one line of comma-separated values per result.
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "plain" => Ok(OutputFormat::Plain),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!("The output format {} is not supported.", value)),
+        }
+    }
+}
+
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            OutputFormat::Plain => write!(f, "plain"),
+            OutputFormat::Json => write!(f, "json"),
+            OutputFormat::Csv => write!(f, "csv"),
+        }
+    }
+}
+
+/// The result of looking up a single password. It never contains the password itself.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LookupRecord {
+    /// Identifies the looked up password within the input (e.g. the line number of a batch).
+    input: String,
+    hash_prefix: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+    found: bool,
+    /// The number of occurrences, if the backend knows it.
+    occurrences: Option<u64>,
+    backend: String,
+    /// The IDs of the sources of a merged database the hash was found in.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    sources: Vec<String>,
+}
+
+impl LookupRecord {
+    /// Create the record for the supplied hash. The full hash is just stored if it is requested,
+    /// otherwise only its prefix is part of the record.
+    pub fn new(
+        input: &str,
+        digest: &HashDigest,
+        include_hash: bool,
+        found: bool,
+        occurrences: Option<u64>,
+        backend: &str,
+    ) -> LookupRecord {
+        let hash = digest.to_hex();
+        LookupRecord {
+            input: input.to_string(),
+            hash_prefix: hash[..HASH_PREFIX_LENGTH].to_string(),
+            hash: if include_hash { Some(hash) } else { None },
+            found,
+            occurrences,
+            backend: backend.to_string(),
+            sources: Vec::new(),
+        }
+    }
+
+    /// Add the IDs of the sources the hash was found in to the record.
+    pub fn with_sources(mut self, sources: Vec<String>) -> LookupRecord {
+        self.sources = sources;
+        self
+    }
+}
+
+/// Quote a value for the CSV output if it contains a separator, a quote or a line break.
+fn escape_csv_value(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// This class writes the results of lookups in the selected format.
+pub struct RecordWriter<W: Write> {
+    output: W,
+    format: OutputFormat,
+    header_written: bool,
+}
+
+impl<W: Write> RecordWriter<W> {
+    /// Create a writer which writes the records in the supplied format into the output.
+    pub fn new(output: W, format: OutputFormat) -> RecordWriter<W> {
+        RecordWriter {
+            output,
+            format,
+            header_written: false,
+        }
+    }
+
+    /// Write the supplied record. For the plain format, the supplied message is written instead,
+    /// since it describes the result in the words of the subcommand. The output is not flushed, so
+    /// a buffered output has to be flushed with `flush` after the last record.
+    pub fn write_record(
+        &mut self,
+        record: &LookupRecord,
+        plain_message: &str,
+    ) -> Result<(), Error> {
+        match self.format {
+            OutputFormat::Plain => writeln!(self.output, "{}", plain_message)?,
+            OutputFormat::Json => {
+                serde_json::to_writer(&mut self.output, record)?;
+                writeln!(self.output)?;
+            }
+            OutputFormat::Csv => {
+                if !self.header_written {
+                    writeln!(self.output, "{}", CSV_HEADER)?;
+                    self.header_written = true;
+                }
+                writeln!(
+                    self.output,
+                    "{},{},{},{},{},{},{}",
+                    escape_csv_value(&record.input),
+                    record.hash_prefix,
+                    record.hash.as_deref().unwrap_or_default(),
+                    record.found,
+                    record
+                        .occurrences
+                        .map(|count| count.to_string())
+                        .unwrap_or_default(),
+                    escape_csv_value(&record.backend),
+                    escape_csv_value(&record.sources.join(","))
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush the output, so all written records reach their destination.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.output.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HashType;
+
+    fn write_sample_records(format: OutputFormat, include_hash: bool) -> String {
+        let digest = HashDigest::from_password("password", HashType::Sha1);
+        let mut writer = RecordWriter::new(Vec::new(), format);
+        writer
+            .write_record(
+                &LookupRecord::new("line:1", &digest, include_hash, true, Some(3), "compiled")
+                    .with_sources(vec!["hibp".to_string(), "red-team".to_string()]),
+                "found",
+            )
+            .unwrap();
+        writer
+            .write_record(
+                &LookupRecord::new("line,2", &digest, include_hash, false, None, "compiled"),
+                "not found",
+            )
+            .unwrap();
+        String::from_utf8(writer.output).unwrap()
+    }
+
+    #[test]
+    fn writing_records_as_json_works() {
+        assert_eq!(
+            "{\"input\":\"line:1\",\"hash_prefix\":\"5BAA6\",\"found\":true,\"occurrences\":3,\"backend\":\"compiled\",\"sources\":[\"hibp\",\"red-team\"]}\n\
+             {\"input\":\"line,2\",\"hash_prefix\":\"5BAA6\",\"found\":false,\"occurrences\":null,\"backend\":\"compiled\"}\n",
+            write_sample_records(OutputFormat::Json, false)
+        );
+        assert_eq!(
+            true,
+            write_sample_records(OutputFormat::Json, true)
+                .contains("\"hash\":\"5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8\"")
+        );
+    }
+
+    #[test]
+    fn writing_records_as_csv_and_plain_text_works() {
+        assert_eq!(
+            "input,hash_prefix,hash,found,occurrences,backend,sources\n\
+             line:1,5BAA6,5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8,true,3,compiled,\"hibp,red-team\"\n\
+             \"line,2\",5BAA6,5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8,false,,compiled,\n",
+            write_sample_records(OutputFormat::Csv, true)
+        );
+        assert_eq!(
+            "found\nnot found\n",
+            write_sample_records(OutputFormat::Plain, true)
+        );
+    }
+}
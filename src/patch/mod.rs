@@ -0,0 +1,615 @@
+use crate::error::PwnedError;
+use crate::haveibeenpwned::DatabaseIterator;
+use crate::manifest::SourceInformation;
+use crate::{HashType, PasswordHashEntry};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// The first line of every patch.
+const PATCH_MAGIC_LINE: &str = "#pwned-rs-patch";
+
+/// The version of the format which is written by the [PatchWriter](struct.PatchWriter.html).
+const PATCH_FORMAT_VERSION: u32 = 1;
+
+/// The number of bytes at the end of a patch in which its summary is searched.
+const SUMMARY_SEARCH_SIZE: u64 = 4096;
+
+/// The possible errors which can occur while reading or applying a patch.
+#[derive(Debug)]
+pub enum PatchError {
+    /// There was a generic IO error.
+    Io(Error),
+    /// The file is not a patch or it is incomplete (the summary at its end is missing).
+    NotAPatch,
+    /// The format of the patch is not supported.
+    UnsupportedVersion(u32),
+    /// A line of the patch could not be parsed or is not ordered by hash.
+    InvalidLine(u64),
+    /// The number of operations does not match the summary of the patch.
+    OperationCountMismatch,
+    /// The patch contains hashes of another type than the database.
+    HashTypeMismatch { patch: HashType, database: HashType },
+    /// The patch was created for another version of the password file than the database.
+    BaseMismatch { patch: String, database: String },
+    /// An operation of the patch does not fit the content of the database.
+    Conflict { hash: String, reason: &'static str },
+}
+
+impl Display for PatchError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            PatchError::Io(ref err) => write!(f, "IO error: {}", err),
+            PatchError::NotAPatch => write!(f, "the file is not a patch or it is incomplete"),
+            PatchError::UnsupportedVersion(version) => {
+                write!(f, "the version {} of the patch is not supported", version)
+            }
+            PatchError::InvalidLine(line_number) => write!(
+                f,
+                "line {} of the patch is not valid or not ordered by hash",
+                line_number
+            ),
+            PatchError::OperationCountMismatch => write!(
+                f,
+                "the number of operations does not match the summary of the patch"
+            ),
+            PatchError::HashTypeMismatch { patch, database } => write!(
+                f,
+                "the patch contains {} hashes, but the database contains {} hashes",
+                patch, database
+            ),
+            PatchError::BaseMismatch {
+                ref patch,
+                ref database,
+            } => write!(
+                f,
+                "the patch was created for the password file with the checksum {}, but the database was created from {}",
+                patch, database
+            ),
+            PatchError::Conflict { ref hash, reason } => {
+                write!(f, "the hash {} {}", hash, reason)
+            }
+        }
+    }
+}
+
+impl From<Error> for PatchError {
+    fn from(error: Error) -> Self {
+        PatchError::Io(error)
+    }
+}
+
+/// A single change between two versions of a password file.
+pub enum PatchOperation {
+    /// The hash is part of the new version only.
+    Add(PasswordHashEntry),
+    /// The hash is part of the old version only.
+    Remove(PasswordHashEntry),
+    /// The hash is part of both versions, but its number of occurrences changed.
+    Change(PasswordHashEntry),
+}
+
+impl PatchOperation {
+    /// Get the entry the operation belongs to. For added and changed hashes, it contains the
+    /// number of occurrences of the new version.
+    pub fn get_password_hash(&self) -> &PasswordHashEntry {
+        match self {
+            PatchOperation::Add(entry)
+            | PatchOperation::Remove(entry)
+            | PatchOperation::Change(entry) => entry,
+        }
+    }
+
+    fn get_line_to_write(&self) -> String {
+        match self {
+            PatchOperation::Add(entry) => format!("+{}", entry.get_line_to_write()),
+            PatchOperation::Remove(entry) => format!("-{}\n", entry.hash),
+            PatchOperation::Change(entry) => format!("~{}", entry.get_line_to_write()),
+        }
+    }
+
+    fn from_line(line: &str, hash_type: HashType) -> Option<PatchOperation> {
+        let (marker, entry_line) = (line.get(..1)?, line.get(1..)?);
+        let entry = match marker {
+            "-" => PasswordHashEntry::from_hash_with_type(entry_line, hash_type).ok()?,
+            _ => entry_line.parse::<PasswordHashEntry>().ok()?,
+        };
+        if entry.get_hash_type() != hash_type {
+            return None;
+        }
+        match marker {
+            "+" => Some(PatchOperation::Add(entry)),
+            "-" => Some(PatchOperation::Remove(entry)),
+            "~" => Some(PatchOperation::Change(entry)),
+            _ => None,
+        }
+    }
+}
+
+/// The summary which is stored at the end of a patch. It describes which password file the patch
+/// belongs to and which one it produces.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PatchSummary {
+    version: u32,
+    hash_type: HashType,
+    /// The SHA-256 checksum of the password file the patch has to be applied to.
+    base_sha256: String,
+    /// The password file the database corresponds to after the patch was applied.
+    target: SourceInformation,
+    added: u64,
+    removed: u64,
+    changed: u64,
+}
+
+impl PatchSummary {
+    pub fn get_hash_type(&self) -> HashType {
+        self.hash_type
+    }
+
+    pub fn get_base_sha256(&self) -> &str {
+        &self.base_sha256
+    }
+
+    pub fn get_target(&self) -> &SourceInformation {
+        &self.target
+    }
+
+    pub fn get_added(&self) -> u64 {
+        self.added
+    }
+
+    pub fn get_removed(&self) -> u64 {
+        self.removed
+    }
+
+    pub fn get_changed(&self) -> u64 {
+        self.changed
+    }
+}
+
+/// This class writes the operations of a patch. The summary is written by `finish`, a patch
+/// without it is treated as incomplete.
+pub struct PatchWriter<W: Write> {
+    output: W,
+    hash_type: HashType,
+    added: u64,
+    removed: u64,
+    changed: u64,
+}
+
+impl<W: Write> PatchWriter<W> {
+    /// Create a writer for a patch with hashes of the supplied type.
+    pub fn new(mut output: W, hash_type: HashType) -> Result<PatchWriter<W>, Error> {
+        writeln!(output, "{}", PATCH_MAGIC_LINE)?;
+        Ok(PatchWriter {
+            output,
+            hash_type,
+            added: 0,
+            removed: 0,
+            changed: 0,
+        })
+    }
+
+    /// Append the supplied operation. The operations have to be written in the order of their
+    /// hashes.
+    pub fn write_operation(&mut self, operation: &PatchOperation) -> Result<(), Error> {
+        self.output
+            .write_all(operation.get_line_to_write().as_bytes())?;
+        match operation {
+            PatchOperation::Add(_) => self.added += 1,
+            PatchOperation::Remove(_) => self.removed += 1,
+            PatchOperation::Change(_) => self.changed += 1,
+        }
+        Ok(())
+    }
+
+    /// Write the summary of the patch and flush the output.
+    pub fn finish(
+        mut self,
+        base_sha256: &str,
+        target: SourceInformation,
+    ) -> Result<PatchSummary, Error> {
+        let summary = PatchSummary {
+            version: PATCH_FORMAT_VERSION,
+            hash_type: self.hash_type,
+            base_sha256: base_sha256.to_string(),
+            target,
+            added: self.added,
+            removed: self.removed,
+            changed: self.changed,
+        };
+        writeln!(self.output, "#{}", serde_json::to_string(&summary)?)?;
+        self.output.flush()?;
+        Ok(summary)
+    }
+}
+
+/// Read the summary at the end of the supplied patch.
+fn read_summary(patch_file: &mut File) -> Result<PatchSummary, PatchError> {
+    let file_size = patch_file.metadata()?.len();
+    patch_file.seek(SeekFrom::Start(
+        file_size.saturating_sub(SUMMARY_SEARCH_SIZE),
+    ))?;
+    let mut file_end = String::new();
+    if patch_file.read_to_string(&mut file_end).is_err() {
+        return Err(PatchError::NotAPatch);
+    }
+    let summary_line = match file_end.trim_end().rsplit('\n').next() {
+        Some(line) if line.starts_with("#{") => &line[1..],
+        _ => return Err(PatchError::NotAPatch),
+    };
+    let summary: PatchSummary = match serde_json::from_str(summary_line) {
+        Ok(summary) => summary,
+        Err(_) => return Err(PatchError::NotAPatch),
+    };
+    if summary.version != PATCH_FORMAT_VERSION {
+        return Err(PatchError::UnsupportedVersion(summary.version));
+    }
+    Ok(summary)
+}
+
+/// This class reads the operations of a patch which was written by a
+/// [PatchWriter](struct.PatchWriter.html). The summary is read when the patch is opened, so it
+/// can be checked before any operation is applied.
+///
+/// The iterator stops at the first line which is not valid or not ordered by hash, the error can
+/// be taken with `take_error`. It is also reported if the patch contains fewer or more operations
+/// than stated in its summary.
+pub struct PatchReader {
+    reader: BufReader<File>,
+    summary: PatchSummary,
+    line_buffer: String,
+    line_number: u64,
+    read_operations: u64,
+    previous_hash: Option<PasswordHashEntry>,
+    end_reached: bool,
+    error: Option<PatchError>,
+}
+
+impl PatchReader {
+    /// Open the supplied patch and read its summary.
+    pub fn from_file(path_to_file: &Path) -> Result<PatchReader, PatchError> {
+        let mut patch_file = File::open(path_to_file)?;
+        let summary = read_summary(&mut patch_file)?;
+        patch_file.seek(SeekFrom::Start(0))?;
+
+        let mut reader = BufReader::with_capacity(1024 * 1024, patch_file);
+        let mut first_line = String::new();
+        if reader.read_line(&mut first_line).is_err() || first_line.trim_end() != PATCH_MAGIC_LINE {
+            return Err(PatchError::NotAPatch);
+        }
+
+        Ok(PatchReader {
+            reader,
+            summary,
+            line_buffer: String::new(),
+            line_number: 1,
+            read_operations: 0,
+            previous_hash: None,
+            end_reached: false,
+            error: None,
+        })
+    }
+
+    pub fn get_summary(&self) -> &PatchSummary {
+        &self.summary
+    }
+
+    /// Take the error which stopped the reader early. If all operations were read, `None` is
+    /// returned.
+    pub fn take_error(&mut self) -> Option<PatchError> {
+        self.error.take()
+    }
+
+    fn stop_with_error(&mut self, error: PatchError) -> Option<PatchOperation> {
+        self.error = Some(error);
+        None
+    }
+}
+
+impl Iterator for PatchReader {
+    type Item = PatchOperation;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.end_reached || self.error.is_some() {
+            return None;
+        }
+
+        self.line_buffer.clear();
+        match self.reader.read_line(&mut self.line_buffer) {
+            Ok(0) => return self.stop_with_error(PatchError::NotAPatch),
+            Ok(_) => self.line_number += 1,
+            Err(error) => return self.stop_with_error(PatchError::Io(error)),
+        }
+
+        // the summary is the last line, so all operations were read
+        let line = self.line_buffer.trim_end();
+        if line.starts_with('#') {
+            self.end_reached = true;
+            let summary = &self.summary;
+            if self.read_operations != summary.added + summary.removed + summary.changed {
+                return self.stop_with_error(PatchError::OperationCountMismatch);
+            }
+            return None;
+        }
+
+        let operation = match PatchOperation::from_line(line, self.summary.hash_type) {
+            Some(operation) => operation,
+            None => return self.stop_with_error(PatchError::InvalidLine(self.line_number)),
+        };
+        if self
+            .previous_hash
+            .as_ref()
+            .is_some_and(|previous| previous >= operation.get_password_hash())
+        {
+            return self.stop_with_error(PatchError::InvalidLine(self.line_number));
+        }
+        self.previous_hash = Some(PasswordHashEntry {
+            hash: operation.get_password_hash().hash.clone(),
+            hash_type: self.summary.hash_type,
+            occurrences: 0,
+            entry_size: 0,
+        });
+        self.read_operations += 1;
+        Some(operation)
+    }
+}
+
+/// This class walks through two versions of a password file (both ordered by hash) at the same
+/// time and returns the operations which turn the old version into the new one.
+///
+/// The iterator stops at the first error of one of the password files, which can be taken with
+/// `take_error`.
+pub struct DatabaseDiff {
+    base: DatabaseIterator,
+    target: DatabaseIterator,
+    base_entry: Option<PasswordHashEntry>,
+    target_entry: Option<PasswordHashEntry>,
+    started: bool,
+    error: Option<PwnedError>,
+}
+
+impl DatabaseDiff {
+    /// Create the diff of the supplied password files, which have to contain hashes of the same
+    /// type.
+    pub fn new(
+        mut base: DatabaseIterator,
+        mut target: DatabaseIterator,
+    ) -> Result<DatabaseDiff, PwnedError> {
+        if base.get_hash_type() != target.get_hash_type() {
+            return Err(PwnedError::InvalidArgument(format!(
+                "The old password file contains {} hashes, but the new one contains {} hashes.",
+                base.get_hash_type(),
+                target.get_hash_type()
+            )));
+        }
+        base.set_order_check(true);
+        target.set_order_check(true);
+
+        Ok(DatabaseDiff {
+            base,
+            target,
+            base_entry: None,
+            target_entry: None,
+            started: false,
+            error: None,
+        })
+    }
+
+    /// Get the parser of the old password file.
+    pub fn get_base(&self) -> &DatabaseIterator {
+        &self.base
+    }
+
+    /// Get the parser of the new password file.
+    pub fn get_target(&self) -> &DatabaseIterator {
+        &self.target
+    }
+
+    /// Take the error which stopped the diff early. If both password files were compared
+    /// completely, `None` is returned.
+    pub fn take_error(&mut self) -> Option<PwnedError> {
+        self.error.take()
+    }
+
+    fn advance_base(&mut self) {
+        self.base_entry = self.base.next();
+        if self.base_entry.is_none() && self.error.is_none() {
+            self.error = self.base.take_error();
+        }
+    }
+
+    fn advance_target(&mut self) {
+        self.target_entry = self.target.next();
+        if self.target_entry.is_none() && self.error.is_none() {
+            self.error = self.target.take_error();
+        }
+    }
+}
+
+impl Iterator for DatabaseDiff {
+    type Item = PatchOperation;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            self.advance_base();
+            self.advance_target();
+        }
+
+        loop {
+            // an early stop of a parser must not be reported as removed or added hashes
+            if self.error.is_some() {
+                return None;
+            }
+            let ordering = match (&self.base_entry, &self.target_entry) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(base_entry), Some(target_entry)) => base_entry.cmp(target_entry),
+            };
+
+            match ordering {
+                Ordering::Less => {
+                    let removed_entry = self.base_entry.take()?;
+                    self.advance_base();
+                    return Some(PatchOperation::Remove(removed_entry));
+                }
+                Ordering::Greater => {
+                    let added_entry = self.target_entry.take()?;
+                    self.advance_target();
+                    return Some(PatchOperation::Add(added_entry));
+                }
+                Ordering::Equal => {
+                    let base_occurrences = self.base_entry.take()?.occurrences;
+                    let target_entry = self.target_entry.take()?;
+                    self.advance_base();
+                    self.advance_target();
+                    if base_occurrences != target_entry.occurrences {
+                        return Some(PatchOperation::Change(target_entry));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Apply the operations of a patch to the entries of a database (both ordered by hash) and pass
+/// the resulting entries in their order to the supplied sink. An operation which does not fit the
+/// entries (like an added hash which is already part of them) stops the application with a
+/// conflict.
+pub fn apply_operations<E, O, S>(entries: E, operations: O, mut sink: S) -> Result<(), PwnedError>
+where
+    E: Iterator<Item = PasswordHashEntry>,
+    O: IntoIterator<Item = PatchOperation>,
+    S: FnMut(&PasswordHashEntry) -> Result<(), PwnedError>,
+{
+    let mut entries = entries.peekable();
+    for operation in operations {
+        let operation_hash = operation.get_password_hash();
+        while let Some(entry) = entries.next_if(|entry| entry < operation_hash) {
+            sink(&entry)?;
+        }
+        let existing_entry = entries.next_if(|entry| entry == operation_hash);
+
+        let reason = match (&operation, existing_entry) {
+            (PatchOperation::Add(entry), None) | (PatchOperation::Change(entry), Some(_)) => {
+                sink(entry)?;
+                continue;
+            }
+            (PatchOperation::Remove(_), Some(_)) => continue,
+            (PatchOperation::Add(_), Some(_)) => {
+                "should be added, but is already part of the database"
+            }
+            (PatchOperation::Remove(_), None) => {
+                "should be removed, but is not part of the database"
+            }
+            (PatchOperation::Change(_), None) => {
+                "should be changed, but is not part of the database"
+            }
+        };
+        return Err(PwnedError::Patch(PatchError::Conflict {
+            hash: operation_hash.get_hash().to_uppercase(),
+            reason,
+        }));
+    }
+    for entry in entries {
+        sink(&entry)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{create_parser, create_temp_path};
+    use std::str::FromStr;
+
+    const BASE_FILE: &str = "\
+        000000005AD76BD555C1D6D771DE417A4B87E4B4:4\n\
+        5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3\n\
+        F00000005AD76BD555C1D6D771DE417A4B87E4B4:1\n";
+
+    const TARGET_FILE: &str = "\
+        5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:5\n\
+        7C4A8D09CA3762AF61E59520943DC26494F8941B:2\n\
+        F00000005AD76BD555C1D6D771DE417A4B87E4B4:1\n";
+
+    fn parse_entries(content: &str) -> Vec<PasswordHashEntry> {
+        content
+            .lines()
+            .map(|line| PasswordHashEntry::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn writing_and_applying_a_patch_works() {
+        let mut diff =
+            DatabaseDiff::new(create_parser(BASE_FILE), create_parser(TARGET_FILE)).unwrap();
+        let patch_path = create_temp_path("patch-written.patch");
+        let mut writer =
+            PatchWriter::new(File::create(&patch_path).unwrap(), HashType::Sha1).unwrap();
+        for operation in diff.by_ref() {
+            writer.write_operation(&operation).unwrap();
+        }
+        assert_eq!(true, diff.take_error().is_none());
+        let summary = writer
+            .finish("ab", SourceInformation::new("target.txt", 126, "cd"))
+            .unwrap();
+        assert_eq!(
+            (1, 1, 1),
+            (
+                summary.get_added(),
+                summary.get_removed(),
+                summary.get_changed()
+            )
+        );
+
+        let mut reader = PatchReader::from_file(&patch_path).unwrap();
+        assert_eq!(&summary, reader.get_summary());
+        let mut patched_file = String::new();
+        apply_operations(
+            parse_entries(BASE_FILE).into_iter(),
+            reader.by_ref(),
+            |entry| {
+                patched_file.push_str(&entry.get_line_to_write());
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(true, reader.take_error().is_none());
+        assert_eq!(TARGET_FILE, patched_file);
+
+        // the patch does not fit the patched file, since the added hash is already part of it
+        let reader = PatchReader::from_file(&patch_path).unwrap();
+        let result = apply_operations(parse_entries(TARGET_FILE).into_iter(), reader, |_| Ok(()));
+        assert_eq!(
+            true,
+            matches!(result, Err(PwnedError::Patch(PatchError::Conflict { .. })))
+        );
+
+        let _ = std::fs::remove_file(patch_path);
+    }
+
+    #[test]
+    fn reading_an_incomplete_patch_fails() {
+        let patch_path = create_temp_path("patch-incomplete.patch");
+        std::fs::write(
+            &patch_path,
+            "#pwned-rs-patch\n+5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3\n",
+        )
+        .unwrap();
+        assert_eq!(
+            true,
+            matches!(
+                PatchReader::from_file(&patch_path),
+                Err(PatchError::NotAPatch)
+            )
+        );
+
+        let _ = std::fs::remove_file(patch_path);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::{HashType, PasswordHashEntry};
+use std::io::{BufRead, Error};
+
+/// The NT hash of an empty password.
+pub const EMPTY_NT_HASH: &str = "31D6CFE0D16AE931B73C59D7E0C089C0";
+
+/// The LM hash which is stored if the account does not have an LM hash (or an empty password).
+pub const EMPTY_LM_HASH: &str = "AAD3B435B51404EEAAD3B435B51404EE";
+
+/// The suffix secretsdump adds to the names of the entries of the password history.
+const HISTORY_MARKER: &str = "_history";
+
+/// A single account of a pwdump / secretsdump file (`user:rid:lmhash:nthash:::`).
+pub struct DumpedAccount {
+    name: String,
+    rid: u64,
+    nt_hash: PasswordHashEntry,
+    has_lm_hash: bool,
+}
+
+impl DumpedAccount {
+    /// Parse a single line of a dump. Returns `None` for all lines which do not describe the
+    /// current password of an account (like the status messages of secretsdump or the entries
+    /// of the password history).
+    pub fn from_line(line: &str) -> Option<DumpedAccount> {
+        let mut fields = line.trim_end().splitn(5, ':');
+        let name = fields.next()?;
+        let rid = fields.next()?.parse::<u64>().ok()?;
+        let lm_hash = fields.next()?;
+        let nt_hash =
+            PasswordHashEntry::from_hash_with_type(fields.next()?, HashType::Ntlm).ok()?;
+        if name.is_empty() || is_history_entry(name) {
+            return None;
+        }
+
+        Some(DumpedAccount {
+            name: name.to_string(),
+            rid,
+            nt_hash,
+            has_lm_hash: !lm_hash.is_empty() && !lm_hash.eq_ignore_ascii_case(EMPTY_LM_HASH),
+        })
+    }
+
+    /// Get the name of the account, including the domain if the dump contains it.
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_rid(&self) -> u64 {
+        self.rid
+    }
+
+    pub fn get_nt_hash(&self) -> &PasswordHashEntry {
+        &self.nt_hash
+    }
+
+    /// Check if the account still has an LM hash, which can be cracked within minutes.
+    pub fn has_lm_hash(&self) -> bool {
+        self.has_lm_hash
+    }
+
+    /// Check if the password of the account is empty.
+    pub fn has_empty_password(&self) -> bool {
+        self.nt_hash.get_hash().eq_ignore_ascii_case(EMPTY_NT_HASH)
+    }
+}
+
+/// Check if the account name belongs to an old password of an account (e.g. `alice_history0`).
+fn is_history_entry(name: &str) -> bool {
+    match name.rfind(HISTORY_MARKER) {
+        Some(position) => {
+            let index = &name[position + HISTORY_MARKER.len()..];
+            !index.is_empty() && index.chars().all(|c| c.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+/// The accounts of a dump together with the number of lines which did not describe an account.
+pub struct DumpContent {
+    pub accounts: Vec<DumpedAccount>,
+    pub skipped_lines: u64,
+}
+
+/// Read all accounts from the supplied dump. The file is read line by line, so just the parsed
+/// accounts have to be kept in memory.
+pub fn read_dump(reader: &mut dyn BufRead) -> Result<DumpContent, Error> {
+    let mut content = DumpContent {
+        accounts: Vec::new(),
+        skipped_lines: 0,
+    };
+
+    let mut line_buffer = String::new();
+    loop {
+        line_buffer.clear();
+        if reader.read_line(&mut line_buffer)? == 0 {
+            break;
+        }
+        if line_buffer.trim().is_empty() {
+            continue;
+        }
+        match DumpedAccount::from_line(&line_buffer) {
+            Some(account) => content.accounts.push(account),
+            None => content.skipped_lines += 1,
+        }
+    }
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing_secretsdump_lines_works() {
+        let account = DumpedAccount::from_line(
+            "CORP\\alice:1104:aad3b435b51404eeaad3b435b51404ee:8846f7eaee8fb117ad06bdd830b7586c::: (status=Enabled)\r\n",
+        )
+        .unwrap();
+        assert_eq!("CORP\\alice", account.get_name());
+        assert_eq!(1104, account.get_rid());
+        assert_eq!(false, account.has_lm_hash());
+        assert_eq!(false, account.has_empty_password());
+        assert_eq!(
+            "8846f7eaee8fb117ad06bdd830b7586c",
+            account.get_nt_hash().get_hash()
+        );
+
+        let account = DumpedAccount::from_line(
+            "Guest:501:e52cac67419a9a224a3b108f3fa6cb6d:31d6cfe0d16ae931b73c59d7e0c089c0:::",
+        )
+        .unwrap();
+        assert_eq!(true, account.has_lm_hash());
+        assert_eq!(true, account.has_empty_password());
+
+        assert_eq!(
+            true,
+            DumpedAccount::from_line("[*] Dumping domain credentials").is_none()
+        );
+        assert_eq!(
+            true,
+            DumpedAccount::from_line(
+                "alice_history0:1104:aad3b435b51404eeaad3b435b51404ee:8846f7eaee8fb117ad06bdd830b7586c:::"
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn reading_a_dump_counts_the_skipped_lines() {
+        let mut dump = "[*] Using the DRSUAPI method\n\
+                        alice:1104:aad3b435b51404eeaad3b435b51404ee:8846f7eaee8fb117ad06bdd830b7586c:::\n\
+                        \n\
+                        bob:1105:aad3b435b51404eeaad3b435b51404ee:not-a-hash:::\n"
+            .as_bytes();
+
+        let content = read_dump(&mut dump).unwrap();
+        assert_eq!(1, content.accounts.len());
+        assert_eq!(2, content.skipped_lines);
+    }
+}
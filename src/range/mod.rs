@@ -0,0 +1,445 @@
+use crate::{HashType, PasswordHashEntry};
+use log::{debug, warn};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::{create_dir_all, rename, File, OpenOptions};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// The number of hexadecimal characters of the prefix which is sent to the range API.
+pub const RANGE_PREFIX_LENGTH: usize = 5;
+
+/// The number of different prefixes the range API can be queried for (16^5).
+pub const NUMBER_OF_PREFIXES: u32 = 1 << 20;
+
+/// The base URL of the official range API.
+pub const DEFAULT_BASE_URL: &str = "https://api.pwnedpasswords.com";
+
+/// The time after which a request is given up (and retried).
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The time to wait before the first retry, it is doubled for every further one.
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// The longest time to wait before a request is retried.
+const MAXIMAL_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Get the prefix (five uppercase hexadecimal characters) with the supplied index.
+pub fn get_range_prefix(index: u32) -> String {
+    format!("{:05X}", index)
+}
+
+/// The possible errors which can occur while querying the range API.
+#[derive(Debug)]
+pub enum RangeError {
+    /// The server answered with an unexpected status code.
+    Status { prefix: String, status_code: u16 },
+    /// The request could not be sent or the response could not be received.
+    Transport { prefix: String, description: String },
+    /// A line of the response is not a valid `SUFFIX:COUNT` line.
+    InvalidResponse { prefix: String, line: String },
+    /// There was a generic IO error (e.g. while accessing the cache).
+    Io(Error),
+}
+
+impl Display for RangeError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            RangeError::Status {
+                ref prefix,
+                status_code,
+            } => write!(
+                f,
+                "the server answered the request for the prefix {} with the status code {}",
+                prefix, status_code
+            ),
+            RangeError::Transport {
+                ref prefix,
+                ref description,
+            } => write!(
+                f,
+                "the request for the prefix {} failed: {}",
+                prefix, description
+            ),
+            RangeError::InvalidResponse {
+                ref prefix,
+                ref line,
+            } => write!(
+                f,
+                "the response for the prefix {} contains the invalid line '{}'",
+                prefix, line
+            ),
+            RangeError::Io(ref err) => write!(f, "IO error: {}", err),
+        }
+    }
+}
+
+impl From<Error> for RangeError {
+    fn from(error: Error) -> Self {
+        RangeError::Io(error)
+    }
+}
+
+/// The answer of the range API for a single prefix.
+pub enum RangeResponse {
+    /// The suffixes have changed (or no ETag was sent), the body contains all of them.
+    Modified { body: String, etag: Option<String> },
+    /// The suffixes did not change since the response with the sent ETag.
+    NotModified,
+}
+
+/// Parse the body of a response for the supplied prefix. Every line contains a suffix and its
+/// count, lines with a count of zero (the padding of the API) are skipped. The entries are
+/// returned ordered by hash, even if the server sent them in another order.
+pub fn parse_range_response(
+    prefix: &str,
+    body: &str,
+    hash_type: HashType,
+) -> Result<Vec<PasswordHashEntry>, RangeError> {
+    let mut entries = Vec::new();
+    for line in body.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let invalid_response = || RangeError::InvalidResponse {
+            prefix: prefix.to_string(),
+            line: line.to_string(),
+        };
+        let entry = PasswordHashEntry::from_str(&format!("{}{}", prefix, line))
+            .map_err(|_| invalid_response())?;
+        if entry.get_hash_type() != hash_type {
+            return Err(invalid_response());
+        }
+        if entry.get_occurrences() > 0 {
+            entries.push(entry);
+        }
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+/// A client for the k-anonymity range API (the official one or a local mirror of it). Failed
+/// requests, rate limits and errors of the server are retried with an increasing delay.
+#[derive(Clone)]
+pub struct RangeClient {
+    agent: ureq::Agent,
+    base_url: String,
+    hash_type: HashType,
+    retries: u32,
+    add_padding: bool,
+}
+
+impl RangeClient {
+    /// Create a client for the API at the supplied base URL (without the `/range/` path), which
+    /// requests hashes of the supplied type.
+    pub fn new(base_url: &str, hash_type: HashType) -> RangeClient {
+        let agent = ureq::AgentBuilder::new()
+            .timeout(REQUEST_TIMEOUT)
+            .user_agent(&format!("pwned-rs/{}", env!("CARGO_PKG_VERSION")))
+            .build();
+        RangeClient {
+            agent,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            hash_type,
+            retries: 5,
+            add_padding: false,
+        }
+    }
+
+    /// Set how often a failed request is retried before it is given up.
+    pub fn set_retries(&mut self, retries: u32) {
+        self.retries = retries;
+    }
+
+    /// Request responses which are padded with random suffixes (with a count of zero), so their
+    /// size does not reveal the prefix. The padding is removed by `parse_range_response`.
+    pub fn set_padding(&mut self, add_padding: bool) {
+        self.add_padding = add_padding;
+    }
+
+    pub fn get_base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn get_hash_type(&self) -> HashType {
+        self.hash_type
+    }
+
+    /// Get the URL which is requested for the supplied prefix.
+    pub fn get_range_url(&self, prefix: &str) -> String {
+        match self.hash_type {
+            HashType::Sha1 => format!("{}/range/{}", self.base_url, prefix),
+            HashType::Ntlm => format!("{}/range/{}?mode=ntlm", self.base_url, prefix),
+        }
+    }
+
+    /// Request the suffixes of the supplied prefix. If the ETag of a previous response is
+    /// supplied, the server can answer that nothing changed since then.
+    pub fn fetch(&self, prefix: &str, etag: Option<&str>) -> Result<RangeResponse, RangeError> {
+        let mut attempt = 0;
+        loop {
+            let (error, retry_after) = match self.fetch_once(prefix, etag) {
+                Ok(response) => return Ok(response),
+                Err(failed_request) => failed_request,
+            };
+            // other client errors (like an invalid prefix) would fail again
+            let retryable = match error {
+                RangeError::Status { status_code, .. } => status_code == 429 || status_code >= 500,
+                _ => true,
+            };
+            if !retryable || attempt >= self.retries {
+                return Err(error);
+            }
+
+            // the delay grows with every attempt, but the server can request a longer one
+            let delay = retry_after
+                .unwrap_or_else(|| INITIAL_RETRY_DELAY * 2u32.pow(attempt.min(16)))
+                .min(MAXIMAL_RETRY_DELAY);
+            attempt += 1;
+            warn!(
+                "Retrying the prefix {} in {} ms (attempt {} of {}), since {}",
+                prefix,
+                delay.as_millis(),
+                attempt,
+                self.retries,
+                error
+            );
+            thread::sleep(delay);
+        }
+    }
+
+    /// Send a single request. If it failed, the error is returned together with the delay the
+    /// server requested before the next attempt (if any).
+    fn fetch_once(
+        &self,
+        prefix: &str,
+        etag: Option<&str>,
+    ) -> Result<RangeResponse, (RangeError, Option<Duration>)> {
+        let mut request = self.agent.get(&self.get_range_url(prefix));
+        if let Some(etag) = etag {
+            request = request.set("If-None-Match", etag);
+        }
+        if self.add_padding {
+            request = request.set("Add-Padding", "true");
+        }
+
+        let transport_error = |description: String| RangeError::Transport {
+            prefix: prefix.to_string(),
+            description,
+        };
+        let response = match request.call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(status_code, response)) => {
+                let retry_after = response
+                    .header("Retry-After")
+                    .and_then(|seconds| seconds.trim().parse::<u64>().ok())
+                    .map(Duration::from_secs);
+                let error = RangeError::Status {
+                    prefix: prefix.to_string(),
+                    status_code,
+                };
+                return Err((error, retry_after));
+            }
+            Err(ureq::Error::Transport(transport)) => {
+                return Err((transport_error(transport.to_string()), None))
+            }
+        };
+
+        match response.status() {
+            304 => {
+                debug!("The suffixes of the prefix {} did not change", prefix);
+                Ok(RangeResponse::NotModified)
+            }
+            200 => {
+                let etag = response.header("ETag").map(|etag| etag.to_string());
+                match response.into_string() {
+                    Ok(body) => Ok(RangeResponse::Modified { body, etag }),
+                    Err(error) => Err((transport_error(error.to_string()), None)),
+                }
+            }
+            status_code => Err((
+                RangeError::Status {
+                    prefix: prefix.to_string(),
+                    status_code,
+                },
+                None,
+            )),
+        }
+    }
+}
+
+/// A folder in which the responses of the range API are stored together with their ETags, so
+/// they can be revalidated instead of being downloaded again. The responses are split into
+/// subfolders by the first two characters of the prefix.
+///
+/// Each file starts with a line containing the ETag (which is empty if the server did not send
+/// one), followed by the body of the response.
+pub struct RangeCache {
+    cache_folder: PathBuf,
+}
+
+impl RangeCache {
+    /// Use the supplied folder as cache, it is created if it does not exist yet.
+    pub fn new(cache_folder: &Path) -> Result<RangeCache, Error> {
+        create_dir_all(cache_folder)?;
+        Ok(RangeCache {
+            cache_folder: cache_folder.to_path_buf(),
+        })
+    }
+
+    pub fn get_folder(&self) -> &Path {
+        &self.cache_folder
+    }
+
+    /// Get the path of the file in which the response for the supplied prefix is stored.
+    pub fn get_path(&self, prefix: &str) -> PathBuf {
+        self.cache_folder
+            .join(&prefix[..2])
+            .join(format!("{}.txt", prefix))
+    }
+
+    /// Get the time at which the response for the supplied prefix was stored or revalidated the
+    /// last time (`None` if it is not cached).
+    pub fn get_modification_time(&self, prefix: &str) -> Option<SystemTime> {
+        self.get_path(prefix)
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Read just the ETag of the cached response for the supplied prefix (if there is one).
+    pub fn read_etag(&self, prefix: &str) -> Result<Option<String>, Error> {
+        let cache_file = match File::open(self.get_path(prefix)) {
+            Ok(cache_file) => cache_file,
+            Err(ref error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let mut etag = String::new();
+        BufReader::new(cache_file).read_line(&mut etag)?;
+        let etag = etag.trim_end();
+        if etag.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(etag.to_string()))
+    }
+
+    /// Read the body of the cached response for the supplied prefix.
+    pub fn read_body(&self, prefix: &str) -> Result<String, Error> {
+        let mut cache_file = BufReader::new(File::open(self.get_path(prefix))?);
+        let mut etag = String::new();
+        cache_file.read_line(&mut etag)?;
+        let mut body = String::new();
+        cache_file.read_to_string(&mut body)?;
+        Ok(body)
+    }
+
+    /// Store the response for the supplied prefix. The file is replaced at once, so an
+    /// interrupted write never leaves an incomplete response behind.
+    pub fn write(&self, prefix: &str, etag: Option<&str>, body: &str) -> Result<(), Error> {
+        let cache_path = self.get_path(prefix);
+        if let Some(folder) = cache_path.parent() {
+            create_dir_all(folder)?;
+        }
+        let temporary_path = cache_path.with_extension("tmp");
+        let mut temporary_file = File::create(&temporary_path)?;
+        writeln!(temporary_file, "{}", etag.unwrap_or_default())?;
+        temporary_file.write_all(body.as_bytes())?;
+        rename(temporary_path, cache_path)
+    }
+
+    /// Mark the cached response for the supplied prefix as revalidated.
+    pub fn touch(&self, prefix: &str) -> Result<(), Error> {
+        OpenOptions::new()
+            .write(true)
+            .open(self.get_path(prefix))?
+            .set_modified(SystemTime::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::create_temp_path;
+    use tiny_http::{Header, Response, Server};
+
+    #[test]
+    fn parsing_a_range_response_works() {
+        let body = "1E4C9B93F3F0682250B6CF8331B7EE68FD8:3\r\n\
+                    011053FD0102E94D6AE2F8B83D76FAF94F6:0\r\n\
+                    0018A45C4D1DEF81644B54AB7F969B88D65:1\r\n";
+        let entries = parse_range_response("5BAA6", body, HashType::Sha1).unwrap();
+        assert_eq!(2, entries.len());
+        assert_eq!(
+            "5BAA60018A45C4D1DEF81644B54AB7F969B88D65",
+            entries[0].get_hash()
+        );
+        assert_eq!(3, entries[1].get_occurrences());
+
+        assert_eq!(
+            true,
+            parse_range_response(
+                "5BAA6",
+                "1E4C9B93F3F0682250B6CF8331B7EE68FD8",
+                HashType::Sha1
+            )
+            .is_err()
+        );
+        assert_eq!(
+            true,
+            parse_range_response("5BAA6", body, HashType::Ntlm).is_err()
+        );
+    }
+
+    #[test]
+    fn fetching_and_caching_a_range_works() {
+        // a stand-in for the range API which supports conditional requests
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let server_thread = thread::spawn(move || {
+            for request in server.incoming_requests().take(2) {
+                assert_eq!("/range/5BAA6", request.url());
+                let revalidated = request
+                    .headers()
+                    .iter()
+                    .any(|header| header.field.equiv("If-None-Match"));
+                let response = if revalidated {
+                    Response::from_string("").with_status_code(304)
+                } else {
+                    Response::from_string("1E4C9B93F3F0682250B6CF8331B7EE68FD8:3\r\n")
+                };
+                let etag = Header::from_bytes(&b"ETag"[..], &b"\"v1\""[..]).unwrap();
+                request.respond(response.with_header(etag)).unwrap();
+            }
+        });
+
+        let cache_folder = create_temp_path("range-cache-test");
+        let _ = std::fs::remove_dir_all(&cache_folder);
+        let cache = RangeCache::new(&cache_folder).unwrap();
+        let client = RangeClient::new(&base_url, HashType::Sha1);
+        match client.fetch("5BAA6", None).unwrap() {
+            RangeResponse::Modified { body, etag } => {
+                cache.write("5BAA6", etag.as_deref(), &body).unwrap()
+            }
+            RangeResponse::NotModified => panic!("the first response has to contain the body"),
+        }
+        let etag = cache.read_etag("5BAA6").unwrap();
+        assert_eq!(Some("\"v1\"".to_string()), etag);
+        assert_eq!(
+            "1E4C9B93F3F0682250B6CF8331B7EE68FD8:3\r\n",
+            cache.read_body("5BAA6").unwrap()
+        );
+        assert_eq!(
+            true,
+            matches!(
+                client.fetch("5BAA6", etag.as_deref()).unwrap(),
+                RangeResponse::NotModified
+            )
+        );
+
+        server_thread.join().unwrap();
+        let _ = std::fs::remove_dir_all(cache_folder);
+    }
+}
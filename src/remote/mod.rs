@@ -0,0 +1,238 @@
+use crate::database::{LookupError, PasswordDatabase};
+use crate::range::{
+    parse_range_response, RangeCache, RangeClient, RangeError, RangeResponse, RANGE_PREFIX_LENGTH,
+};
+use crate::{HashDigest, PasswordHashEntry};
+use log::{debug, warn};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// This class looks up password hashes through the k-anonymity range API. Just the first five
+/// characters of a hash are sent to the server, which answers with the suffixes of all hashes
+/// starting with them. The suffixes of the last prefix are kept, so looking up hashes in sorted
+/// order requests each prefix just once.
+///
+/// The responses can be cached on disk. A cached response is used without asking the server as
+/// long as it is younger than the time to live, afterwards it is revalidated with its ETag.
+pub struct RemoteDatabase {
+    client: RangeClient,
+    cache: Option<(RangeCache, Duration)>,
+    loaded_prefix: Mutex<Option<(String, Vec<PasswordHashEntry>)>>,
+}
+
+impl RemoteDatabase {
+    /// Create a database which sends its lookups through the supplied client.
+    ///
+    /// # Example
+    /// ```
+    /// use pwned_rs::range::{RangeClient, DEFAULT_BASE_URL};
+    /// use pwned_rs::remote::RemoteDatabase;
+    /// use pwned_rs::HashType;
+    ///
+    /// let mut client = RangeClient::new(DEFAULT_BASE_URL, HashType::Sha1);
+    /// client.set_padding(true);
+    /// let database = RemoteDatabase::new(client);
+    /// ```
+    pub fn new(client: RangeClient) -> RemoteDatabase {
+        RemoteDatabase {
+            client,
+            cache: None,
+            loaded_prefix: Mutex::new(None),
+        }
+    }
+
+    /// Store the responses in the supplied cache and use them for the supplied time.
+    pub fn set_cache(&mut self, cache: RangeCache, time_to_live: Duration) {
+        self.cache = Some((cache, time_to_live));
+    }
+
+    /// Get the body of the response for the supplied prefix, either from the cache or from the
+    /// server.
+    fn get_response_body(&self, prefix: &str) -> Result<String, RangeError> {
+        let (cache, time_to_live) = match self.cache {
+            Some((ref cache, time_to_live)) => (cache, time_to_live),
+            None => {
+                return match self.client.fetch(prefix, None)? {
+                    RangeResponse::Modified { body, .. } => Ok(body),
+                    RangeResponse::NotModified => Err(RangeError::Status {
+                        prefix: prefix.to_string(),
+                        status_code: 304,
+                    }),
+                }
+            }
+        };
+
+        // a fresh response is used directly, an older one is revalidated
+        let is_fresh = cache.get_modification_time(prefix).is_some_and(|modified| {
+            SystemTime::now()
+                .duration_since(modified)
+                .is_ok_and(|age| age < time_to_live)
+        });
+        if is_fresh {
+            match cache.read_body(prefix) {
+                Ok(body) => {
+                    debug!("Using the cached response for the prefix {}", prefix);
+                    return Ok(body);
+                }
+                Err(error) => warn!(
+                    "Could not read the cached response for the prefix {}: {}",
+                    prefix, error
+                ),
+            }
+        }
+
+        let etag = cache.read_etag(prefix).unwrap_or_default();
+        match self.client.fetch(prefix, etag.as_deref())? {
+            RangeResponse::Modified { body, etag } => {
+                // the cache is just an optimization, so the lookup does not fail without it
+                if let Err(error) = cache.write(prefix, etag.as_deref(), &body) {
+                    warn!(
+                        "Could not cache the response for the prefix {}: {}",
+                        prefix, error
+                    );
+                }
+                Ok(body)
+            }
+            RangeResponse::NotModified => {
+                if let Err(error) = cache.touch(prefix) {
+                    warn!(
+                        "Could not update the cached response for the prefix {}: {}",
+                        prefix, error
+                    );
+                }
+                Ok(cache.read_body(prefix)?)
+            }
+        }
+    }
+}
+
+impl PasswordDatabase for RemoteDatabase {
+    fn occurrences(&self, hash: &HashDigest) -> Result<Option<u64>, LookupError> {
+        if hash.get_hash_type() != self.client.get_hash_type() {
+            return Err(LookupError::HashTypeMismatch {
+                database: self.client.get_hash_type(),
+                requested: hash.get_hash_type(),
+            });
+        }
+
+        // just the prefix of the hash leaves this function
+        let hexadecimal_hash = hash.to_hex().to_uppercase();
+        let prefix = &hexadecimal_hash[..RANGE_PREFIX_LENGTH];
+        let mut loaded_prefix = match self.loaded_prefix.lock() {
+            Ok(loaded_prefix) => loaded_prefix,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let is_loaded = matches!(loaded_prefix.as_ref(), Some((loaded, _)) if loaded == prefix);
+        if !is_loaded {
+            let body = self.get_response_body(prefix)?;
+            let entries = parse_range_response(prefix, &body, self.client.get_hash_type())?;
+            *loaded_prefix = Some((prefix.to_string(), entries));
+        }
+
+        let entries = match loaded_prefix.as_ref() {
+            Some((_, entries)) => entries,
+            None => return Ok(None),
+        };
+        Ok(entries
+            .iter()
+            .find(|entry| entry.get_hash().eq_ignore_ascii_case(&hexadecimal_hash))
+            .map(|entry| entry.get_occurrences()))
+    }
+
+    fn get_backend_name(&self) -> &'static str {
+        "remote-range-api"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::create_temp_path;
+    use crate::HashType;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use tiny_http::{Header, Response, Server};
+
+    /// Start a stand-in for the range API which answers every request for the prefix 5BAA6 with a
+    /// padded response and counts the requests.
+    fn start_mock_server(expected_requests: usize) -> (String, Arc<AtomicUsize>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let received_requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&received_requests);
+        thread::spawn(move || {
+            for request in server.incoming_requests().take(expected_requests) {
+                counter.fetch_add(1, Ordering::SeqCst);
+                assert_eq!("/range/5BAA6", request.url());
+                assert_eq!(
+                    true,
+                    request
+                        .headers()
+                        .iter()
+                        .any(|header| header.field.equiv("Add-Padding"))
+                );
+                let body = "003D68EB55068C33ACE09247EE4C639306B:0\r\n\
+                            1E4C9B93F3F0682250B6CF8331B7EE68FD8:3861493\r\n\
+                            1E4C9B93F3F0682250B6CF8331B7EE68FD9:0\r\n";
+                let etag = Header::from_bytes(&b"ETag"[..], &b"\"v1\""[..]).unwrap();
+                request
+                    .respond(Response::from_string(body).with_header(etag))
+                    .unwrap();
+            }
+        });
+        (base_url, received_requests)
+    }
+
+    fn create_client(base_url: &str) -> RangeClient {
+        let mut client = RangeClient::new(base_url, HashType::Sha1);
+        client.set_padding(true);
+        client.set_retries(0);
+        client
+    }
+
+    #[test]
+    fn looking_up_hashes_through_the_range_api_works() {
+        let (base_url, received_requests) = start_mock_server(1);
+        let database = RemoteDatabase::new(create_client(&base_url));
+
+        let password = HashDigest::from_password("password", HashType::Sha1);
+        assert_eq!(Some(3861493), database.occurrences(&password).unwrap());
+
+        // the padding entries are not reported as found and the prefix is not requested again
+        let padding =
+            HashDigest::from_hex("5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD9", HashType::Sha1)
+                .unwrap();
+        assert_eq!(None, database.occurrences(&padding).unwrap());
+        assert_eq!(1, received_requests.load(Ordering::SeqCst));
+
+        let ntlm_hash = HashDigest::from_password("password", HashType::Ntlm);
+        assert_eq!(true, database.occurrences(&ntlm_hash).is_err());
+    }
+
+    #[test]
+    fn cached_responses_are_used_until_they_expire() {
+        let (base_url, received_requests) = start_mock_server(2);
+        let cache_folder = create_temp_path("remote-cache-test");
+        let _ = std::fs::remove_dir_all(&cache_folder);
+        let password = HashDigest::from_password("password", HashType::Sha1);
+
+        // every new instance forgets the loaded prefix, so just the cache can prevent a request
+        for _ in 0..2 {
+            let mut database = RemoteDatabase::new(create_client(&base_url));
+            database.set_cache(
+                RangeCache::new(&cache_folder).unwrap(),
+                Duration::from_secs(3600),
+            );
+            assert_eq!(Some(3861493), database.occurrences(&password).unwrap());
+        }
+        assert_eq!(1, received_requests.load(Ordering::SeqCst));
+
+        let mut database = RemoteDatabase::new(create_client(&base_url));
+        database.set_cache(RangeCache::new(&cache_folder).unwrap(), Duration::ZERO);
+        assert_eq!(Some(3861493), database.occurrences(&password).unwrap());
+        assert_eq!(2, received_requests.load(Ordering::SeqCst));
+
+        let _ = std::fs::remove_dir_all(cache_folder);
+    }
+}
@@ -0,0 +1,357 @@
+use log::debug;
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::io::{Error, ErrorKind};
+use std::ptr;
+use std::sync::atomic::{compiler_fence, Ordering};
+use std::sync::Once;
+
+/// The maximal number of bytes of a password which is read from the terminal. The buffer for the
+/// password is allocated once with this size, so it never has to be moved while the user types.
+pub const MAXIMAL_SECRET_LENGTH: usize = 1024;
+
+/// Makes sure that a failure to lock the memory of the secrets is just logged once.
+static LOCK_FAILURE_REPORTED: Once = Once::new();
+
+/// Overwrite the supplied memory with zeros in a way the compiler cannot optimize away.
+///
+/// # Safety
+///
+/// The pointer has to be valid for writing the supplied number of bytes.
+unsafe fn wipe_memory(memory: *mut u8, length: usize) {
+    for offset in 0..length {
+        ptr::write_volatile(memory.add(offset), 0);
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// Overwrite the memory of the supplied value with zeros. This is used for the state of the hash
+/// functions, which still contains the last block of the password after the digest was computed.
+///
+/// # Safety
+///
+/// The type must not own any heap memory and an instance which consists of zeros only has to be
+/// valid (like for the structs of the hash functions, which just contain integers and arrays).
+pub(crate) unsafe fn wipe_value<T>(value: &mut T) {
+    wipe_memory(value as *mut T as *mut u8, std::mem::size_of::<T>());
+}
+
+/// Prevent the supplied memory from being written into the swap space. Returns `true` if the
+/// memory could be locked.
+#[cfg(unix)]
+fn lock_memory(memory: *const u8, length: usize) -> bool {
+    if length == 0 {
+        return false;
+    }
+    match unsafe { libc::mlock(memory as *const libc::c_void, length) } {
+        0 => true,
+        _ => {
+            // the limit for locked memory is the same for all secrets, so it is reported once
+            let error = Error::last_os_error();
+            LOCK_FAILURE_REPORTED.call_once(|| {
+                debug!(
+                    "Could not lock the memory of a secret, it could be swapped to disk: {}",
+                    error
+                )
+            });
+            false
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn lock_memory(_memory: *const u8, _length: usize) -> bool {
+    LOCK_FAILURE_REPORTED
+        .call_once(|| debug!("Locking the memory of a secret is not supported on this platform"));
+    false
+}
+
+#[cfg(unix)]
+fn unlock_memory(memory: *const u8, length: usize) {
+    unsafe {
+        libc::munlock(memory as *const libc::c_void, length);
+    }
+}
+
+#[cfg(not(unix))]
+fn unlock_memory(_memory: *const u8, _length: usize) {}
+
+/// A buffer for sensitive bytes (like a password or its UTF-16 encoding). The buffer is allocated
+/// once with a fixed capacity and never moved, so no copies of its content are left behind in
+/// freed memory. Where possible, the memory is locked to keep it out of the swap space, and it is
+/// overwritten with zeros when the buffer is dropped.
+pub struct SecretBytes {
+    bytes: Vec<u8>,
+    is_locked: bool,
+}
+
+impl SecretBytes {
+    /// Create an empty buffer which can hold up to the supplied number of bytes.
+    pub fn with_capacity(capacity: usize) -> SecretBytes {
+        SecretBytes::from_vec(Vec::with_capacity(capacity))
+    }
+
+    /// Take over the memory of the supplied vector without copying it.
+    fn from_vec(bytes: Vec<u8>) -> SecretBytes {
+        let is_locked = lock_memory(bytes.as_ptr(), bytes.capacity());
+        SecretBytes { bytes, is_locked }
+    }
+
+    /// Append the supplied bytes. If they do not fit into the remaining capacity, nothing is
+    /// appended and `false` is returned.
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) -> bool {
+        if self.bytes.capacity() - self.bytes.len() < bytes.len() {
+            return false;
+        }
+        self.bytes.extend_from_slice(bytes);
+        true
+    }
+
+    /// Remove the last byte (and overwrite it) if there is one.
+    pub fn pop(&mut self) -> Option<u8> {
+        let byte = self.bytes.pop()?;
+        unsafe { wipe_memory(self.bytes.as_mut_ptr().add(self.bytes.len()), 1) };
+        Some(byte)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn get_capacity(&self) -> usize {
+        self.bytes.capacity()
+    }
+
+    /// Check if the memory of the buffer was locked, i.e. it cannot be written into the swap space.
+    pub fn is_locked(&self) -> bool {
+        self.is_locked
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        // the whole allocation is wiped, which includes bytes removed before
+        let capacity = self.bytes.capacity();
+        unsafe { wipe_memory(self.bytes.as_mut_ptr(), capacity) };
+        if self.is_locked {
+            unlock_memory(self.bytes.as_ptr(), capacity);
+        }
+    }
+}
+
+impl Debug for SecretBytes {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "SecretBytes([REDACTED])")
+    }
+}
+
+/// A UTF-8 encoded password which is wiped from the memory as soon as it is not needed anymore.
+/// It can be hashed with [HashDigest::from_secret](../struct.HashDigest.html#method.from_secret)
+/// without copying it into any other buffer.
+///
+/// The type deliberately implements neither `Clone` nor `Display` and its `Debug` output does not
+/// contain the password.
+pub struct SecretString {
+    bytes: SecretBytes,
+}
+
+impl SecretString {
+    /// Take over the memory of the supplied string without copying it. Copies of the password
+    /// which were made while the string was built (e.g. when it grew) cannot be wiped, so a
+    /// password should rather be read directly into a secret, like `read_secret_from_tty` does.
+    ///
+    /// # Example
+    /// ```
+    /// use pwned_rs::secret::SecretString;
+    ///
+    /// let secret = SecretString::from_string(String::from("password"));
+    /// assert_eq!("password", secret.as_str());
+    /// ```
+    pub fn from_string(password: String) -> SecretString {
+        SecretString {
+            bytes: SecretBytes::from_vec(password.into_bytes()),
+        }
+    }
+
+    /// Create a secret from bytes which were read into a secret buffer.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the bytes are not valid UTF-8. The buffer is wiped in this case.
+    pub fn from_secret_bytes(bytes: SecretBytes) -> Result<SecretString, Error> {
+        match std::str::from_utf8(bytes.as_bytes()) {
+            Ok(_) => Ok(SecretString { bytes }),
+            Err(_) => Err(Error::new(
+                ErrorKind::InvalidData,
+                "The password is not valid UTF-8",
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // the content was checked (or taken from a string) when the secret was created
+        unsafe { std::str::from_utf8_unchecked(self.bytes.as_bytes()) }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.bytes.as_bytes()
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Check if the memory of the password was locked, i.e. it cannot be written into the swap
+    /// space.
+    pub fn is_locked(&self) -> bool {
+        self.bytes.is_locked()
+    }
+}
+
+impl Debug for SecretString {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "SecretString([REDACTED])")
+    }
+}
+
+/// Show the supplied prompt on the terminal and read a password with the echo turned off. The
+/// password is read byte by byte into a locked buffer of `MAXIMAL_SECRET_LENGTH` bytes, so it is
+/// never stored in an ordinary buffer.
+///
+/// # Errors
+///
+/// An error is returned if the terminal could not be used, if the password is longer than
+/// `MAXIMAL_SECRET_LENGTH` bytes or if it is not valid UTF-8.
+#[cfg(unix)]
+pub fn read_secret_from_tty(prompt: &str) -> Result<SecretString, Error> {
+    use std::fs::OpenOptions;
+    use std::io::{Read, Write};
+    use std::os::unix::io::AsRawFd;
+
+    let mut terminal = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    write!(terminal, "{}", prompt)?;
+    terminal.flush()?;
+
+    // hide the password while it is typed, but show the new line when the user hits enter
+    let terminal_fd = terminal.as_raw_fd();
+    let mut original_settings = None;
+    if unsafe { libc::isatty(terminal_fd) } == 1 {
+        let mut settings = std::mem::MaybeUninit::<libc::termios>::uninit();
+        if unsafe { libc::tcgetattr(terminal_fd, settings.as_mut_ptr()) } != 0 {
+            return Err(Error::last_os_error());
+        }
+        let settings = unsafe { settings.assume_init() };
+        let mut hidden_settings = settings;
+        hidden_settings.c_lflag &= !libc::ECHO;
+        hidden_settings.c_lflag |= libc::ECHONL;
+        if unsafe { libc::tcsetattr(terminal_fd, libc::TCSANOW, &hidden_settings) } != 0 {
+            return Err(Error::last_os_error());
+        }
+        original_settings = Some(settings);
+    }
+
+    let mut password = SecretBytes::with_capacity(MAXIMAL_SECRET_LENGTH);
+    let mut next_byte = [0u8; 1];
+    let mut read_result = Ok(());
+    loop {
+        match terminal.read(&mut next_byte) {
+            Ok(0) => break,
+            Ok(_) if next_byte[0] == b'\n' => break,
+            Ok(_) => {
+                if !password.extend_from_slice(&next_byte) {
+                    read_result = Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "The password is longer than {} bytes",
+                            MAXIMAL_SECRET_LENGTH
+                        ),
+                    ));
+                    break;
+                }
+            }
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => {
+                read_result = Err(error);
+                break;
+            }
+        }
+    }
+    unsafe { wipe_value(&mut next_byte) };
+
+    // the terminal is restored before any error is returned
+    if let Some(settings) = original_settings {
+        if unsafe { libc::tcsetattr(terminal_fd, libc::TCSANOW, &settings) } != 0 {
+            return Err(Error::last_os_error());
+        }
+    }
+    read_result?;
+
+    if password.as_bytes().last() == Some(&b'\r') {
+        password.pop();
+    }
+    SecretString::from_secret_bytes(password)
+}
+
+/// Show the supplied prompt on the terminal and read a password with the echo turned off. On this
+/// platform, the password is read by `rpassword` and taken over afterwards, so its buffer cannot
+/// be locked while it is typed.
+#[cfg(not(unix))]
+pub fn read_secret_from_tty(prompt: &str) -> Result<SecretString, Error> {
+    rpassword::read_password_from_tty(Some(prompt)).map(SecretString::from_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HashDigest, HashType, PasswordHashEntry};
+
+    #[test]
+    fn secret_buffers_do_not_grow_beyond_their_capacity() {
+        let mut bytes = SecretBytes::with_capacity(4);
+        assert_eq!(true, bytes.extend_from_slice(b"abc"));
+        assert_eq!(false, bytes.extend_from_slice(b"de"));
+        assert_eq!(true, bytes.extend_from_slice(b"d"));
+        assert_eq!(4, bytes.get_capacity());
+        assert_eq!(Some(b'd'), bytes.pop());
+        assert_eq!(b"abc", bytes.as_bytes());
+
+        let secret = SecretString::from_secret_bytes(bytes).unwrap();
+        assert_eq!("abc", secret.as_str());
+        assert_eq!("SecretString([REDACTED])", format!("{:?}", secret));
+
+        let mut invalid_bytes = SecretBytes::with_capacity(2);
+        invalid_bytes.extend_from_slice(&[0xc3, 0x28]);
+        assert_eq!(
+            true,
+            SecretString::from_secret_bytes(invalid_bytes).is_err()
+        );
+    }
+
+    #[test]
+    fn secrets_are_hashed_like_plain_passwords() {
+        for password in &["password", "pässwörd", ""] {
+            let secret = SecretString::from_string(password.to_string());
+            for hash_type in &[HashType::Sha1, HashType::Ntlm] {
+                assert_eq!(
+                    HashDigest::from_password(password, *hash_type),
+                    HashDigest::from_secret(&secret, *hash_type)
+                );
+                assert_eq!(
+                    hash_type.hash_password(password),
+                    PasswordHashEntry::from_password_with_type(password, *hash_type).get_hash()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use crate::error::PwnedError;
+use crate::PasswordHashEntry;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::fs::{remove_file, File};
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write};
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+/// The maximum number of sorted runs which are merged at once. If there are more runs, they are
+/// merged in multiple passes, so the number of open files and read buffers stays bounded.
+const MAX_MERGE_FAN_IN: usize = 64;
+
+/// The size of the read buffer of each run while they are merged.
+const RUN_READER_CAPACITY: usize = 256 * 1024;
+
+/// The number of sorters created by this process, which keeps the names of their runs unique.
+static SORTER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// This class sorts password hash entries by their hash with a bounded amount of memory. The
+/// entries are collected until the memory limit is reached, then they are sorted and written to
+/// a temporary file (a sorted run). When all entries were added, the runs are merged into the
+/// ordered password file. Entries with the same hash are combined by adding up their occurrences.
+///
+/// The temporary files are removed when the sorter is dropped, even if the sort failed.
+///
+/// # Example
+/// ```
+/// use pwned_rs::sort::ExternalSorter;
+/// use pwned_rs::PasswordHashEntry;
+///
+/// let mut sorter = ExternalSorter::new(&std::env::temp_dir(), 1024 * 1024 * 64);
+/// for password in &["password", "123456", "password"] {
+///     sorter.add_entry(PasswordHashEntry::from_password(password)).unwrap();
+/// }
+/// let mut ordered_file = Vec::new();
+/// let written_entries = sorter.finish(&mut ordered_file).unwrap();
+/// assert_eq!(2, written_entries);
+/// ```
+pub struct ExternalSorter {
+    temp_dir: PathBuf,
+    memory_limit: usize,
+    sorter_id: usize,
+    buffered_entries: Vec<PasswordHashEntry>,
+    buffered_bytes: usize,
+    runs: Vec<PathBuf>,
+    created_runs: usize,
+}
+
+impl ExternalSorter {
+    /// Create a sorter which stores its temporary files in the supplied folder and keeps about
+    /// `memory_limit` bytes of entries in memory.
+    pub fn new(temp_dir: &Path, memory_limit: usize) -> ExternalSorter {
+        ExternalSorter {
+            temp_dir: temp_dir.to_path_buf(),
+            memory_limit,
+            sorter_id: SORTER_COUNT.fetch_add(1, AtomicOrdering::SeqCst),
+            buffered_entries: Vec::new(),
+            buffered_bytes: 0,
+            runs: Vec::new(),
+            created_runs: 0,
+        }
+    }
+
+    /// Get the number of temporary files which were written so far.
+    pub fn get_number_of_runs(&self) -> usize {
+        self.created_runs
+    }
+
+    /// Add an entry to the sorter. If the memory limit is reached, the collected entries are
+    /// written to a temporary file.
+    pub fn add_entry(&mut self, mut entry: PasswordHashEntry) -> Result<(), PwnedError> {
+        // the ordered password files use upper case hashes
+        entry.hash.make_ascii_uppercase();
+        self.buffered_bytes += size_of::<PasswordHashEntry>() + entry.hash.capacity();
+        self.buffered_entries.push(entry);
+
+        if self.buffered_bytes >= self.memory_limit {
+            self.write_buffered_run()?;
+        }
+        Ok(())
+    }
+
+    /// Write all entries ordered by their hashes to the supplied writer and return the number of
+    /// written (distinct) entries.
+    pub fn finish<W: Write>(mut self, writer: &mut W) -> Result<u64, PwnedError> {
+        // if all entries fit into memory, no temporary file is required
+        if self.runs.is_empty() {
+            self.buffered_entries.sort_unstable();
+            let entries = std::mem::take(&mut self.buffered_entries);
+            return write_combined_entries(entries.into_iter().map(Ok), writer).map_err(|error| {
+                PwnedError::Io("Could not write the ordered entries".to_string(), error)
+            });
+        }
+        if !self.buffered_entries.is_empty() {
+            self.write_buffered_run()?;
+        }
+
+        // merge the runs in multiple passes until they can be merged at once
+        while self.runs.len() > MAX_MERGE_FAN_IN {
+            let merged_runs = self.runs[..MAX_MERGE_FAN_IN].to_vec();
+            let run_path = self.create_run_path();
+            self.runs.push(run_path.clone());
+            let mut run_writer = create_run_writer(&run_path)?;
+            merge_runs(&merged_runs, &mut run_writer)?;
+            run_writer.flush().map_err(map_run_write_error)?;
+
+            for merged_run in self.runs.drain(..MAX_MERGE_FAN_IN) {
+                let _ = remove_file(merged_run);
+            }
+        }
+        merge_runs(&self.runs, writer)
+    }
+
+    /// Sort the entries which are currently in memory and write them to a new temporary file.
+    fn write_buffered_run(&mut self) -> Result<(), PwnedError> {
+        self.buffered_entries.sort_unstable();
+        let run_path = self.create_run_path();
+        self.runs.push(run_path.clone());
+
+        let mut run_writer = create_run_writer(&run_path)?;
+        let entries = std::mem::take(&mut self.buffered_entries);
+        write_combined_entries(entries.into_iter().map(Ok), &mut run_writer)
+            .and_then(|_| run_writer.flush())
+            .map_err(map_run_write_error)?;
+        self.buffered_bytes = 0;
+        Ok(())
+    }
+
+    fn create_run_path(&mut self) -> PathBuf {
+        self.created_runs += 1;
+        self.temp_dir.join(format!(
+            "pwned-rs-sort-{}-{}-{}.tmp",
+            std::process::id(),
+            self.sorter_id,
+            self.created_runs
+        ))
+    }
+}
+
+impl Drop for ExternalSorter {
+    fn drop(&mut self) {
+        for run in &self.runs {
+            let _ = remove_file(run);
+        }
+    }
+}
+
+fn map_run_write_error(error: Error) -> PwnedError {
+    PwnedError::Io(
+        "Could not write a temporary file of the sort".to_string(),
+        error,
+    )
+}
+
+fn create_run_writer(run_path: &Path) -> Result<BufWriter<File>, PwnedError> {
+    match File::create(run_path) {
+        Ok(file) => Ok(BufWriter::new(file)),
+        Err(error) => Err(PwnedError::Io(
+            format!("Could not create the temporary file {}", run_path.display()),
+            error,
+        )),
+    }
+}
+
+/// Write the ordered entries as lines of a password file. Subsequent entries with the same hash
+/// are written as a single line with the sum of their occurrences.
+fn write_combined_entries<W: Write>(
+    entries: impl Iterator<Item = Result<PasswordHashEntry, Error>>,
+    writer: &mut W,
+) -> Result<u64, Error> {
+    let mut written_entries = 0;
+    let mut pending_entry: Option<PasswordHashEntry> = None;
+    for entry in entries {
+        let entry = entry?;
+        match pending_entry.as_mut() {
+            Some(pending) if *pending == entry => {
+                pending.occurrences = pending.occurrences.saturating_add(entry.occurrences);
+            }
+            _ => {
+                if let Some(pending) = pending_entry.replace(entry) {
+                    writeln!(writer, "{}:{}", pending.hash, pending.occurrences)?;
+                    written_entries += 1;
+                }
+            }
+        }
+    }
+    if let Some(pending) = pending_entry {
+        writeln!(writer, "{}:{}", pending.hash, pending.occurrences)?;
+        written_entries += 1;
+    }
+    Ok(written_entries)
+}
+
+/// Merge the supplied sorted runs into a single ordered password file.
+fn merge_runs<W: Write>(runs: &[PathBuf], writer: &mut W) -> Result<u64, PwnedError> {
+    let mut run_readers = Vec::with_capacity(runs.len());
+    for run in runs {
+        match File::open(run) {
+            Ok(file) => run_readers.push(RunReader {
+                reader: BufReader::with_capacity(RUN_READER_CAPACITY, file),
+                line_buffer: String::new(),
+            }),
+            Err(error) => {
+                return Err(PwnedError::Io(
+                    format!("Could not open the temporary file {}", run.display()),
+                    error,
+                ))
+            }
+        }
+    }
+
+    // the heap always contains the lowest entry of each run which was not written yet
+    let mut heap = BinaryHeap::with_capacity(run_readers.len());
+    for (run_index, run_reader) in run_readers.iter_mut().enumerate() {
+        if let Some(entry) = run_reader.next_entry().map_err(map_run_read_error)? {
+            heap.push(Reverse(HeapEntry { entry, run_index }));
+        }
+    }
+    let merged_entries = std::iter::from_fn(|| {
+        let Reverse(lowest) = heap.pop()?;
+        match run_readers[lowest.run_index].next_entry() {
+            Ok(Some(entry)) => heap.push(Reverse(HeapEntry {
+                entry,
+                run_index: lowest.run_index,
+            })),
+            Ok(None) => {}
+            Err(error) => return Some(Err(error)),
+        }
+        Some(Ok(lowest.entry))
+    });
+
+    write_combined_entries(merged_entries, writer)
+        .map_err(|error| PwnedError::Io("Could not merge the ordered entries".to_string(), error))
+}
+
+fn map_run_read_error(error: Error) -> PwnedError {
+    PwnedError::Io(
+        "Could not read a temporary file of the sort".to_string(),
+        error,
+    )
+}
+
+/// The next entry of a sorted run, ordered by the hash and then by the run it was read from.
+struct HeapEntry {
+    entry: PasswordHashEntry,
+    run_index: usize,
+}
+
+impl PartialEq for HeapEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for HeapEntry {}
+
+impl PartialOrd for HeapEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for HeapEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.entry
+            .cmp(&other.entry)
+            .then(self.run_index.cmp(&other.run_index))
+    }
+}
+
+/// Reads the entries of a sorted run. The runs are written by the sorter itself, so they do not
+/// need the checks of the password file parser (and its large read buffer).
+struct RunReader {
+    reader: BufReader<File>,
+    line_buffer: String,
+}
+
+impl RunReader {
+    fn next_entry(&mut self) -> Result<Option<PasswordHashEntry>, Error> {
+        self.line_buffer.clear();
+        if self.reader.read_line(&mut self.line_buffer)? == 0 {
+            return Ok(None);
+        }
+        match PasswordHashEntry::from_str(self.line_buffer.trim_end()) {
+            Ok(entry) => Ok(Some(entry)),
+            Err(error) => Err(Error::new(ErrorKind::InvalidData, error.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HashType;
+
+    #[test]
+    fn sorting_with_multiple_merge_passes_works() {
+        let temp_dir = std::env::temp_dir();
+        let mut sorter = ExternalSorter::new(&temp_dir, 1);
+        for index in (0..200).rev() {
+            let hash = HashType::Sha1.hash_password(&(index % 150).to_string());
+            let line = format!("{}:{}", hash, index + 1);
+            sorter
+                .add_entry(PasswordHashEntry::from_str(&line).unwrap())
+                .unwrap();
+        }
+        assert_eq!(200, sorter.get_number_of_runs());
+        let run_paths = sorter.runs.clone();
+
+        let mut ordered_file = Vec::new();
+        assert_eq!(150, sorter.finish(&mut ordered_file).unwrap());
+        assert_eq!(false, run_paths.iter().any(|path| path.exists()));
+
+        let ordered_file = String::from_utf8(ordered_file).unwrap();
+        let entries: Vec<PasswordHashEntry> = ordered_file
+            .lines()
+            .map(|line| PasswordHashEntry::from_str(line).unwrap())
+            .collect();
+        assert_eq!(true, entries.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(ordered_file.to_uppercase(), ordered_file);
+
+        // the password "0" was added with the occurrences 1 and 151
+        let zero_hash = HashType::Sha1.hash_password("0").to_uppercase();
+        assert_eq!(true, ordered_file.contains(&format!("{}:152\n", zero_hash)));
+    }
+
+    #[test]
+    fn sorting_in_memory_does_not_create_temporary_files() {
+        let mut sorter = ExternalSorter::new(Path::new("/this/folder/does/not/exist"), 1024);
+        sorter
+            .add_entry(PasswordHashEntry::from_password("password"))
+            .unwrap();
+        sorter
+            .add_entry(PasswordHashEntry::from_password("123456"))
+            .unwrap();
+
+        let mut ordered_file = Vec::new();
+        assert_eq!(2, sorter.finish(&mut ordered_file).unwrap());
+        assert_eq!(
+            "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:0\n7C4A8D09CA3762AF61E59520943DC26494F8941B:0\n",
+            String::from_utf8(ordered_file).unwrap()
+        );
+    }
+}
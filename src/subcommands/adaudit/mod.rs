@@ -0,0 +1,380 @@
+use crate::database::LookupOutcome;
+use crate::error::PwnedError;
+use crate::haveibeenpwned::DatabaseIterator;
+use crate::merge::{MergeCandidate, MergeJoin};
+use crate::output::OutputFormat;
+use crate::pwdump::{read_dump, DumpedAccount};
+use crate::subcommands::create_progress_bar;
+use crate::{HashType, PasswordHashEntry};
+use clap::ArgMatches;
+use log::{debug, info};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{stdin, stdout, BufReader, Error, Write};
+use std::str::FromStr;
+
+/// All accounts of the dump which use the same NT hash (and therefore the same password).
+struct HashGroup<'a> {
+    nt_hash: &'a PasswordHashEntry,
+    accounts: Vec<&'a DumpedAccount>,
+    occurrences: Option<u64>,
+}
+
+/// The numbers which summarize the audit.
+#[derive(Debug, PartialEq, Serialize)]
+struct AuditStatistics {
+    accounts: usize,
+    skipped_lines: u64,
+    distinct_hashes: usize,
+    compromised_accounts: usize,
+    empty_passwords: usize,
+    lm_hashes: usize,
+    accounts_sharing_a_password: usize,
+    shared_passwords: usize,
+}
+
+/// An account whose password was found in password breaches.
+#[derive(Debug, PartialEq, Serialize)]
+struct CompromisedAccount {
+    account: String,
+    rid: u64,
+    occurrences: u64,
+    /// The number of other accounts which use the same password.
+    shared_with: usize,
+}
+
+/// A password which is used by several accounts. The hash itself is never part of the report.
+#[derive(Debug, PartialEq, Serialize)]
+struct SharedPassword {
+    accounts: Vec<String>,
+    compromised: bool,
+}
+
+/// The result of the audit which is handed to the auditors.
+#[derive(Debug, PartialEq, Serialize)]
+struct AuditReport {
+    statistics: AuditStatistics,
+    compromised_accounts: Vec<CompromisedAccount>,
+    shared_passwords: Vec<SharedPassword>,
+}
+
+/// Group the accounts by their NT hash. The groups are ordered by the hash, so they can be merged
+/// with the password file.
+fn group_by_hash(accounts: &[DumpedAccount]) -> Vec<HashGroup<'_>> {
+    let mut sorted_accounts: Vec<&DumpedAccount> = accounts.iter().collect();
+    sorted_accounts.sort_by(|first, second| first.get_nt_hash().cmp(second.get_nt_hash()));
+
+    let mut hash_groups: Vec<HashGroup> = Vec::new();
+    for account in sorted_accounts {
+        match hash_groups.last_mut() {
+            Some(group) if group.nt_hash == account.get_nt_hash() => group.accounts.push(account),
+            _ => hash_groups.push(HashGroup {
+                nt_hash: account.get_nt_hash(),
+                accounts: vec![account],
+                occurrences: None,
+            }),
+        }
+    }
+    hash_groups
+}
+
+impl MergeCandidate for HashGroup<'_> {
+    fn get_password_hash(&self) -> &PasswordHashEntry {
+        self.nt_hash
+    }
+}
+
+/// Merge the ordered hash groups with the NTLM password file, so the password file has to be read
+/// just once in a single forward pass.
+fn lookup_in_ordered_file<'a>(
+    password_file: &str,
+    hash_groups: Vec<HashGroup<'a>>,
+) -> Result<Vec<HashGroup<'a>>, PwnedError> {
+    let parser = match DatabaseIterator::from_file(password_file) {
+        Ok(parser) => parser,
+        Err(error) => {
+            return Err(PwnedError::Database(
+                "Could not get an instance of the parser".to_string(),
+                error,
+            ))
+        }
+    };
+    if parser.get_hash_type() != HashType::Ntlm {
+        return Err(PwnedError::InvalidArgument(format!(
+            "The password file contains {} hashes, but the dump can just be checked against NTLM hashes.",
+            parser.get_hash_type()
+        )));
+    }
+
+    let progress_bar = create_progress_bar(parser.get_file_size());
+    let mut merge_join = MergeJoin::new(parser, hash_groups.into_iter());
+    let mut looked_up_groups = Vec::new();
+    while let Some((mut hash_group, occurrences)) = merge_join.next() {
+        hash_group.occurrences = occurrences;
+        looked_up_groups.push(hash_group);
+        progress_bar.set_position(merge_join.get_consumed_bytes());
+    }
+    progress_bar.finish_and_clear();
+
+    // if the merge stopped early, the remaining accounts could use a breached password
+    match merge_join.take_error() {
+        Some(error) => Err(error),
+        None => Ok(looked_up_groups),
+    }
+}
+
+fn build_report(hash_groups: &[HashGroup], skipped_lines: u64) -> AuditReport {
+    let mut compromised_accounts = Vec::new();
+    let mut shared_passwords = Vec::new();
+    for group in hash_groups {
+        if let Some(occurrences) = group.occurrences {
+            for account in &group.accounts {
+                compromised_accounts.push(CompromisedAccount {
+                    account: account.get_name().to_string(),
+                    rid: account.get_rid(),
+                    occurrences,
+                    shared_with: group.accounts.len() - 1,
+                });
+            }
+        }
+        if group.accounts.len() > 1 {
+            let mut account_names: Vec<String> = group
+                .accounts
+                .iter()
+                .map(|account| account.get_name().to_string())
+                .collect();
+            account_names.sort();
+            shared_passwords.push(SharedPassword {
+                accounts: account_names,
+                compromised: group.occurrences.is_some(),
+            });
+        }
+    }
+
+    // the most common passwords are the most urgent ones
+    compromised_accounts.sort_by(|first, second| {
+        second
+            .occurrences
+            .cmp(&first.occurrences)
+            .then_with(|| first.account.cmp(&second.account))
+    });
+    shared_passwords
+        .sort_by_key(|shared_password| std::cmp::Reverse(shared_password.accounts.len()));
+
+    let all_accounts = || hash_groups.iter().flat_map(|group| group.accounts.iter());
+    AuditReport {
+        statistics: AuditStatistics {
+            accounts: all_accounts().count(),
+            skipped_lines,
+            distinct_hashes: hash_groups.len(),
+            compromised_accounts: compromised_accounts.len(),
+            empty_passwords: all_accounts()
+                .filter(|account| account.has_empty_password())
+                .count(),
+            lm_hashes: all_accounts()
+                .filter(|account| account.has_lm_hash())
+                .count(),
+            accounts_sharing_a_password: shared_passwords
+                .iter()
+                .map(|shared_password| shared_password.accounts.len())
+                .sum(),
+            shared_passwords: shared_passwords.len(),
+        },
+        compromised_accounts,
+        shared_passwords,
+    }
+}
+
+fn write_plain_report(report: &AuditReport, output: &mut dyn Write) -> Result<(), Error> {
+    let statistics = &report.statistics;
+    writeln!(output, "Password audit of the Active Directory accounts")?;
+    writeln!(output, "===============================================")?;
+    writeln!(
+        output,
+        "Audited accounts:                 {}",
+        statistics.accounts
+    )?;
+    writeln!(
+        output,
+        "Skipped lines of the dump:        {}",
+        statistics.skipped_lines
+    )?;
+    writeln!(
+        output,
+        "Distinct passwords:               {}",
+        statistics.distinct_hashes
+    )?;
+    writeln!(
+        output,
+        "Accounts with breached passwords: {}",
+        statistics.compromised_accounts
+    )?;
+    writeln!(
+        output,
+        "Accounts with an empty password:  {}",
+        statistics.empty_passwords
+    )?;
+    writeln!(
+        output,
+        "Accounts with an LM hash:         {}",
+        statistics.lm_hashes
+    )?;
+    writeln!(
+        output,
+        "Accounts sharing a password:      {} ({} passwords)",
+        statistics.accounts_sharing_a_password, statistics.shared_passwords
+    )?;
+
+    writeln!(output)?;
+    writeln!(output, "Accounts with breached passwords")?;
+    writeln!(output, "--------------------------------")?;
+    if report.compromised_accounts.is_empty() {
+        writeln!(output, "None")?;
+    }
+    for account in &report.compromised_accounts {
+        write!(
+            output,
+            "{} (RID {}): found {} times in password breaches",
+            account.account, account.rid, account.occurrences
+        )?;
+        match account.shared_with {
+            0 => writeln!(output)?,
+            count => writeln!(output, ", shared with {} other accounts", count)?,
+        }
+    }
+
+    writeln!(output)?;
+    writeln!(output, "Accounts sharing the same password")?;
+    writeln!(output, "----------------------------------")?;
+    if report.shared_passwords.is_empty() {
+        writeln!(output, "None")?;
+    }
+    for (index, shared_password) in report.shared_passwords.iter().enumerate() {
+        writeln!(
+            output,
+            "Password {} ({} accounts{}): {}",
+            index + 1,
+            shared_password.accounts.len(),
+            if shared_password.compromised {
+                ", breached"
+            } else {
+                ""
+            },
+            shared_password.accounts.join(", ")
+        )?;
+    }
+    output.flush()
+}
+
+/// Check all accounts of a pwdump / secretsdump file against the NTLM password file and write a
+/// report for the auditors. The outcome is `Found` with the number of affected accounts if at
+/// least one account uses a breached password.
+pub fn run_subcommand(matches: &ArgMatches) -> Result<LookupOutcome, PwnedError> {
+    // get the path to the dump with the NT hashes of the accounts
+    let dump_path = match matches.value_of("dump-file") {
+        Some(path) => path,
+        None => return Err(PwnedError::InvalidArgument("It seems that the path to the dump of the accounts was not provided, please see the help for usage instructions.".to_string())),
+    };
+
+    // get the path to the NTLM password file ordered by hash
+    let password_file = match matches.value_of("password-hashes") {
+        Some(path) => path,
+        None => return Err(PwnedError::InvalidArgument("It seems that the path to the NTLM password file was not provided, please see the help for usage instructions.".to_string())),
+    };
+    let output_format = OutputFormat::from_str(matches.value_of("output").unwrap_or("plain"))
+        .map_err(PwnedError::InvalidArgument)?;
+
+    // read the accounts of the dump, either from the file or from stdin
+    let read_content = if dump_path == "-" {
+        read_dump(&mut stdin().lock())
+    } else {
+        match File::open(dump_path) {
+            Ok(file_handle) => read_dump(&mut BufReader::new(file_handle)),
+            Err(error) => {
+                return Err(PwnedError::Io(
+                    "Could not open the dump of the accounts".to_string(),
+                    error,
+                ))
+            }
+        }
+    };
+    let dump_content = match read_content {
+        Ok(content) => content,
+        Err(error) => {
+            return Err(PwnedError::Io(
+                "Could not read the dump of the accounts".to_string(),
+                error,
+            ))
+        }
+    };
+    debug!(
+        "Read {} accounts from the dump, {} lines were skipped",
+        dump_content.accounts.len(),
+        dump_content.skipped_lines
+    );
+
+    // look up every distinct hash just once
+    let hash_groups = lookup_in_ordered_file(password_file, group_by_hash(&dump_content.accounts))?;
+    let report = build_report(&hash_groups, dump_content.skipped_lines);
+
+    let written_report = match output_format {
+        OutputFormat::Json => serde_json::to_writer_pretty(stdout(), &report)
+            .map_err(Error::from)
+            .and_then(|_| writeln!(stdout())),
+        _ => write_plain_report(&report, &mut stdout()),
+    };
+    if let Err(error) = written_report {
+        return Err(PwnedError::Io(
+            "Could not write the report of the audit".to_string(),
+            error,
+        ));
+    }
+
+    info!(
+        "{} of {} accounts use a password which was found in password breaches",
+        report.statistics.compromised_accounts, report.statistics.accounts
+    );
+    match report.statistics.compromised_accounts {
+        0 => Ok(LookupOutcome::NotFound),
+        count => Ok(LookupOutcome::Found(count as u64)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn building_the_report_groups_accounts_by_password() {
+        let mut dump = "alice:1104:aad3b435b51404eeaad3b435b51404ee:8846f7eaee8fb117ad06bdd830b7586c:::\n\
+                        bob:1105:aad3b435b51404eeaad3b435b51404ee:8846F7EAEE8FB117AD06BDD830B7586C:::\n\
+                        carol:1106:aad3b435b51404eeaad3b435b51404ee:31d6cfe0d16ae931b73c59d7e0c089c0:::\n\
+                        dave:1107:e52cac67419a9a224a3b108f3fa6cb6d:0cb6948805f797bf2a82807973b89537:::\n"
+            .as_bytes();
+        let dump_content = read_dump(&mut dump).unwrap();
+
+        let mut hash_groups = group_by_hash(&dump_content.accounts);
+        assert_eq!(3, hash_groups.len());
+        for group in hash_groups.iter_mut() {
+            if group.accounts.len() == 2 {
+                group.occurrences = Some(42);
+            }
+        }
+
+        let report = build_report(&hash_groups, 0);
+        assert_eq!(4, report.statistics.accounts);
+        assert_eq!(2, report.statistics.compromised_accounts);
+        assert_eq!(1, report.statistics.empty_passwords);
+        assert_eq!(1, report.statistics.lm_hashes);
+        assert_eq!(2, report.statistics.accounts_sharing_a_password);
+        assert_eq!("alice", report.compromised_accounts[0].account);
+        assert_eq!(1, report.compromised_accounts[0].shared_with);
+        assert_eq!(
+            vec![SharedPassword {
+                accounts: vec!["alice".to_string(), "bob".to_string()],
+                compromised: true,
+            }],
+            report.shared_passwords
+        );
+    }
+}
@@ -0,0 +1,687 @@
+use crate::compiled::{
+    is_compiled_database, CompiledDatabase, CompiledDatabaseWriter, CompiledRecordReader,
+};
+use crate::error::PwnedError;
+use crate::haveibeenpwned::DatabaseIterator;
+use crate::manifest::{DatabaseManifest, MANIFEST_FILE_NAME};
+use crate::patch::{apply_operations, PatchError, PatchOperation, PatchReader, PatchSummary};
+use crate::subcommands::create_counting_progress_bar;
+use crate::subcommands::optimize::PrefixFileWriter;
+use crate::{HashType, PasswordHashEntry};
+use chrono::Utc;
+use clap::ArgMatches;
+use indicatif::ProgressBar;
+use log::{debug, info, warn};
+use std::fs::{create_dir, read_to_string, remove_dir_all, remove_file, rename, write};
+use std::path::Path;
+
+/// The name of the folder (within the optimized database) in which the changed files are written
+/// before they replace the original ones.
+pub(crate) const STAGING_FOLDER_NAME: &str = ".patch-staging";
+
+/// The name of the file (within the staging folder) which lists the files of the optimized
+/// database which are removed by the patch.
+const REMOVED_FILES_NAME: &str = "removed-files";
+
+/// Check that the patch was created for the password file the database was created from. Without
+/// `--force`, a patch for another version is rejected, since it would silently corrupt the counts.
+fn check_base(
+    summary: &PatchSummary,
+    database_checksum: &str,
+    force: bool,
+) -> Result<(), PwnedError> {
+    if database_checksum.is_empty() {
+        warn!("The checksum of the password file the database was created from is unknown, so it cannot be checked that the patch belongs to it");
+    } else if !database_checksum.eq_ignore_ascii_case(summary.get_base_sha256()) {
+        if !force {
+            return Err(PwnedError::Patch(PatchError::BaseMismatch {
+                patch: summary.get_base_sha256().to_string(),
+                database: database_checksum.to_string(),
+            }));
+        }
+        warn!("The patch was created for another version of the password file, applying it anyway");
+    }
+    Ok(())
+}
+
+/// Check that the patch contains hashes of the same type as the database.
+fn check_hash_type(summary: &PatchSummary, hash_type: HashType) -> Result<(), PwnedError> {
+    if summary.get_hash_type() != hash_type {
+        return Err(PwnedError::Patch(PatchError::HashTypeMismatch {
+            patch: summary.get_hash_type(),
+            database: hash_type,
+        }));
+    }
+    Ok(())
+}
+
+/// Take the error which stopped the patch reader early, so an incomplete patch is never reported
+/// as applied.
+fn check_patch_reader(patch_reader: &mut PatchReader) -> Result<(), PwnedError> {
+    match patch_reader.take_error() {
+        Some(error) => Err(PwnedError::Patch(error)),
+        None => Ok(()),
+    }
+}
+
+/// Apply the operations of a single prefix to the file of the optimized database and write the
+/// result into the staging folder. If no hash of the prefix is left, `false` is returned and the
+/// staged file is removed again.
+fn patch_prefix_file(
+    database_folder: &Path,
+    staging_folder: &Path,
+    prefix: &str,
+    operations: Vec<PatchOperation>,
+    manifest: &mut DatabaseManifest,
+) -> Result<bool, PwnedError> {
+    let file_name = format!("{}.txt", prefix);
+    let original_file = database_folder.join(&file_name);
+    let mut parser = if original_file.is_file() {
+        match DatabaseIterator::from_file(&original_file.to_string_lossy()) {
+            Ok(parser) => Some(parser),
+            Err(error) => {
+                return Err(PwnedError::Database(
+                    format!("Could not read the file {}", original_file.display()),
+                    error,
+                ))
+            }
+        }
+    } else {
+        None
+    };
+
+    let write_error = |error| {
+        PwnedError::Io(
+            format!("Could not write the patched file {}", file_name),
+            error,
+        )
+    };
+    let mut prefix_writer =
+        PrefixFileWriter::create(staging_folder, prefix.to_string()).map_err(write_error)?;
+    let entries: Box<dyn Iterator<Item = PasswordHashEntry>> = match parser {
+        Some(ref mut parser) => Box::new(parser.by_ref()),
+        None => Box::new(std::iter::empty()),
+    };
+    apply_operations(entries, operations, |entry| {
+        prefix_writer
+            .write_line(entry.get_line_to_write().as_bytes())
+            .map_err(write_error)
+    })?;
+    if let Some(error) = parser.as_mut().and_then(|parser| parser.take_error()) {
+        return Err(error);
+    }
+
+    if prefix_writer.get_entries() == 0 {
+        drop(prefix_writer);
+        remove_file(staging_folder.join(&file_name)).map_err(write_error)?;
+        manifest.remove_file(&file_name);
+        return Ok(false);
+    }
+    prefix_writer.finish(manifest).map_err(write_error)?;
+    Ok(true)
+}
+
+/// Write the patched files of the optimized database into the staging folder and return the
+/// prefixes of the files which were changed together with the information if the file is kept.
+fn stage_prefix_files(
+    database_folder: &Path,
+    staging_folder: &Path,
+    manifest: &mut DatabaseManifest,
+    patch_reader: &mut PatchReader,
+    progress_bar: &ProgressBar,
+) -> Result<Vec<(String, bool)>, PwnedError> {
+    let prefix_length = manifest.get_prefix_length();
+    let mut changed_prefixes = Vec::new();
+    let mut current_prefix = String::new();
+    let mut pending_operations = Vec::new();
+    let mut applied_operations: u64 = 0;
+
+    // the operations are ordered by hash, so all operations of a prefix follow each other
+    for operation in patch_reader.by_ref() {
+        let prefix = match operation
+            .get_password_hash()
+            .get_dynamic_prefix(prefix_length)
+        {
+            Some(prefix) => prefix.to_uppercase(),
+            None => {
+                return Err(PwnedError::InvalidArgument(
+                    "The prefix length of the database is longer than the hashes of the patch."
+                        .to_string(),
+                ))
+            }
+        };
+        if prefix != current_prefix && !pending_operations.is_empty() {
+            let kept = patch_prefix_file(
+                database_folder,
+                staging_folder,
+                &current_prefix,
+                std::mem::take(&mut pending_operations),
+                manifest,
+            )?;
+            changed_prefixes.push((current_prefix.clone(), kept));
+            progress_bar.set_position(applied_operations);
+        }
+        current_prefix = prefix;
+        pending_operations.push(operation);
+        applied_operations += 1;
+    }
+    check_patch_reader(patch_reader)?;
+    if !pending_operations.is_empty() {
+        let kept = patch_prefix_file(
+            database_folder,
+            staging_folder,
+            &current_prefix,
+            pending_operations,
+            manifest,
+        )?;
+        changed_prefixes.push((current_prefix, kept));
+    }
+    progress_bar.set_position(applied_operations);
+    Ok(changed_prefixes)
+}
+
+/// Move the staged files into the optimized database, remove the files which are no longer needed
+/// and replace the manifest by the staged one. Files which were already moved are skipped, so a
+/// replacement which was interrupted can be finished by calling this again.
+fn finish_staged_patch(
+    database_folder: &Path,
+    staging_folder: &Path,
+) -> Result<DatabaseManifest, PwnedError> {
+    let replace_error = |error| {
+        PwnedError::Io(
+            format!("Could not replace the files of the optimized database, please run apply-patch again to finish it (the staged files are kept in {})", staging_folder.display()),
+            error,
+        )
+    };
+    let staged_manifest = match DatabaseManifest::from_folder(staging_folder) {
+        Ok(manifest) => manifest,
+        Err(error) => {
+            return Err(PwnedError::Database(
+                "Could not read the staged manifest of the optimized database".to_string(),
+                error,
+            ))
+        }
+    };
+    let removed_files =
+        read_to_string(staging_folder.join(REMOVED_FILES_NAME)).map_err(replace_error)?;
+
+    for staged_file in staged_manifest.get_files().keys() {
+        if staging_folder.join(staged_file).is_file() {
+            rename(
+                staging_folder.join(staged_file),
+                database_folder.join(staged_file),
+            )
+            .map_err(replace_error)?;
+        }
+    }
+    for removed_file in removed_files.lines() {
+        if database_folder.join(removed_file).is_file() {
+            remove_file(database_folder.join(removed_file)).map_err(replace_error)?;
+        }
+    }
+
+    // the manifest is replaced last, so it never describes files which are not in place yet
+    rename(
+        staging_folder.join(MANIFEST_FILE_NAME),
+        database_folder.join(MANIFEST_FILE_NAME),
+    )
+    .map_err(replace_error)?;
+    if let Err(error) = remove_dir_all(staging_folder) {
+        warn!(
+            "Could not remove the folder {}, the error was: {}",
+            staging_folder.display(),
+            error
+        );
+    }
+    Ok(staged_manifest)
+}
+
+/// Take care of the staging folder which was left behind by an earlier run. If all files of the
+/// earlier patch were staged, the replacement is finished. Otherwise the database was not touched
+/// yet, so the incomplete staging folder is just removed.
+fn recover_staging_folder(database_folder: &Path, staging_folder: &Path) -> Result<(), PwnedError> {
+    if staging_folder.join(MANIFEST_FILE_NAME).is_file()
+        && DatabaseManifest::from_folder(staging_folder).is_ok()
+    {
+        warn!("An earlier patch was interrupted while replacing the files of the optimized database, finishing it");
+        let manifest = finish_staged_patch(database_folder, staging_folder)?;
+        info!(
+            "Finished the earlier patch, the optimized database is now at version {}",
+            manifest.get_version()
+        );
+        return Ok(());
+    }
+
+    warn!("An earlier patch was interrupted before any file of the optimized database was replaced, removing the staged files");
+    if let Err(error) = remove_dir_all(staging_folder) {
+        return Err(PwnedError::Io(
+            format!(
+                "Could not remove the folder {} which was left by an earlier patch",
+                staging_folder.display()
+            ),
+            error,
+        ));
+    }
+    Ok(())
+}
+
+/// Apply the patch to the optimized database in the supplied folder. Just the files of the
+/// prefixes which contain changed hashes are rewritten.
+fn patch_optimized_database(
+    database_folder: &Path,
+    mut patch_reader: PatchReader,
+    force: bool,
+) -> Result<(), PwnedError> {
+    // finish or discard the changes of an earlier patch which was interrupted
+    let staging_folder = database_folder.join(STAGING_FOLDER_NAME);
+    if staging_folder.is_dir() {
+        recover_staging_folder(database_folder, &staging_folder)?;
+    }
+
+    let mut manifest = match DatabaseManifest::from_folder(database_folder) {
+        Ok(manifest) => manifest,
+        Err(error) => {
+            return Err(PwnedError::Database(
+                "Could not read the manifest of the optimized database".to_string(),
+                error,
+            ))
+        }
+    };
+    if !manifest.has_file_list() {
+        return Err(PwnedError::InvalidArgument("The optimized database does not list its files in the manifest, please optimize the password file again before applying patches.".to_string()));
+    }
+    let summary = patch_reader.get_summary().clone();
+    if manifest.get_source().map(|source| source.get_sha256())
+        == Some(summary.get_target().get_sha256())
+    {
+        info!("The patch was already applied to the optimized database");
+        return Ok(());
+    }
+    if let Some(hash_type) = manifest.get_hash_type() {
+        check_hash_type(&summary, hash_type)?;
+    }
+    let database_checksum = manifest
+        .get_source()
+        .map(|source| source.get_sha256().to_string())
+        .unwrap_or_default();
+    check_base(&summary, &database_checksum, force)?;
+
+    // the patched files are written into a staging folder first, so the database is not touched
+    // if the patch does not fit it
+    if let Err(error) = create_dir(&staging_folder) {
+        return Err(PwnedError::Io(
+            format!(
+                "Could not create the folder {} (is another patch being applied?)",
+                staging_folder.display()
+            ),
+            error,
+        ));
+    }
+    let progress_bar = create_counting_progress_bar(
+        summary.get_added() + summary.get_removed() + summary.get_changed(),
+        "operations",
+    );
+    let changed_prefixes = match stage_prefix_files(
+        database_folder,
+        &staging_folder,
+        &mut manifest,
+        &mut patch_reader,
+        &progress_bar,
+    ) {
+        Ok(changed_prefixes) => changed_prefixes,
+        Err(error) => {
+            progress_bar.abandon();
+            let _ = remove_dir_all(&staging_folder);
+            return Err(error);
+        }
+    };
+    progress_bar.finish_with_message("staged");
+
+    // the updated manifest completes the staging folder, from then on an interrupted replacement
+    // of the files is finished by the next run
+    let removed_files: String = changed_prefixes
+        .iter()
+        .filter(|(_, kept)| !kept)
+        .map(|(prefix, _)| format!("{}.txt\n", prefix))
+        .collect();
+    manifest.set_source(summary.get_target().clone());
+    manifest.set_version(manifest.get_version() + 1);
+    manifest.set_updated_at(&Utc::now().to_rfc3339());
+    let staging_result = write(staging_folder.join(REMOVED_FILES_NAME), removed_files)
+        .and_then(|_| manifest.write_to_folder(&staging_folder));
+    if let Err(error) = staging_result {
+        let _ = remove_dir_all(&staging_folder);
+        return Err(PwnedError::Io(
+            "Could not write the manifest of the optimized database".to_string(),
+            error,
+        ));
+    }
+    finish_staged_patch(database_folder, &staging_folder)?;
+
+    info!(
+        "Rewrote {} files of the optimized database, it is now at version {}",
+        changed_prefixes.len(),
+        manifest.get_version()
+    );
+    Ok(())
+}
+
+/// Apply the patch to the compiled database. Since the records have fixed offsets, the whole
+/// database is written again into a temporary file which replaces the original one.
+fn patch_compiled_database(
+    database_file: &Path,
+    mut patch_reader: PatchReader,
+    force: bool,
+) -> Result<(), PwnedError> {
+    let open_error =
+        |error| PwnedError::Database("Could not open the compiled database".to_string(), error);
+    let database = CompiledDatabase::from_file(database_file).map_err(open_error)?;
+    let summary = patch_reader.get_summary().clone();
+    check_hash_type(&summary, database.get_hash_type())?;
+    check_base(&summary, &database.get_source_checksum(), force)?;
+    let version = database.get_version() + 1;
+    drop(database);
+
+    let mut record_reader = CompiledRecordReader::from_file(database_file).map_err(open_error)?;
+    let mut database_writer =
+        CompiledDatabaseWriter::create(database_file, summary.get_hash_type())?;
+    database_writer.set_version(version);
+
+    let progress_bar = create_counting_progress_bar(
+        summary.get_added() + summary.get_removed() + summary.get_changed(),
+        "operations",
+    );
+    let mut applied_operations: u64 = 0;
+    let operations = patch_reader.by_ref().inspect(|_| {
+        applied_operations += 1;
+        progress_bar.set_position(applied_operations);
+    });
+    let result = apply_operations(record_reader.by_ref(), operations, |entry| {
+        database_writer
+            .write_entry(entry)
+            .map_err(PwnedError::Compile)
+    })
+    .and_then(|_| check_patch_reader(&mut patch_reader))
+    .and_then(|_| match record_reader.take_error() {
+        Some(error) => Err(PwnedError::Io(
+            "Could not read the compiled database".to_string(),
+            error,
+        )),
+        None => Ok(()),
+    })
+    .and_then(|_| {
+        database_writer
+            .finish(summary.get_target().get_sha256())
+            .map_err(PwnedError::Compile)
+    });
+    let record_count = match result {
+        Ok(record_count) => record_count,
+        Err(error) => {
+            progress_bar.abandon();
+            return Err(error);
+        }
+    };
+    progress_bar.finish_with_message("applied");
+    info!(
+        "The compiled database contains {} password hashes and is now at version {}",
+        record_count, version
+    );
+    Ok(())
+}
+
+pub fn run_subcommand(matches: &ArgMatches) -> Result<(), PwnedError> {
+    // get the path to the password database which should be updated
+    let database_path = match matches.value_of("password-database") {
+        Some(path) => Path::new(path),
+        None => return Err(PwnedError::InvalidArgument("It seems that the path to the password database was not provided, please see the help for usage instructions.".to_string())),
+    };
+    debug!("Got {} as the password database", database_path.display());
+
+    // get the path to the patch and read its summary
+    let patch_file = match matches.value_of("patch-file") {
+        Some(path) => Path::new(path),
+        None => return Err(PwnedError::InvalidArgument("It seems that the path to the patch was not provided, please see the help for usage instructions.".to_string())),
+    };
+    let patch_reader = PatchReader::from_file(patch_file)?;
+    let summary = patch_reader.get_summary();
+    info!(
+        "The patch adds {}, removes {} and changes {} password hashes",
+        summary.get_added(),
+        summary.get_removed(),
+        summary.get_changed()
+    );
+
+    let force = matches.is_present("force");
+    if database_path.is_dir() {
+        patch_optimized_database(database_path, patch_reader, force)
+    } else if is_compiled_database(database_path) {
+        patch_compiled_database(database_path, patch_reader, force)
+    } else {
+        Err(PwnedError::InvalidArgument(format!(
+            "{} is neither the folder of an optimized database nor a compiled database.",
+            database_path.display()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiled::CompiledDatabase;
+    use crate::database::PasswordDatabase;
+    use crate::manifest::SourceInformation;
+    use crate::patch::{DatabaseDiff, PatchWriter};
+    use crate::testing::{create_parser, create_temp_path};
+    use crate::HashDigest;
+    use std::fs::File;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    const BASE_FILE: &str = "\
+        000000005AD76BD555C1D6D771DE417A4B87E4B4:4\n\
+        5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3\n\
+        F00000005AD76BD555C1D6D771DE417A4B87E4B4:1\n";
+
+    const TARGET_FILE: &str = "\
+        5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:5\n\
+        7C4A8D09CA3762AF61E59520943DC26494F8941B:2\n\
+        F00000005AD76BD555C1D6D771DE417A4B87E4B4:1\n";
+
+    const BASE_CHECKSUM: &str = "abababababababababababababababababababababababababababababababab";
+    const TARGET_CHECKSUM: &str =
+        "cdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcd";
+
+    fn get_target_information() -> SourceInformation {
+        SourceInformation::new("target.txt", TARGET_FILE.len() as u64, TARGET_CHECKSUM)
+    }
+
+    /// Write the patch from the base to the target file.
+    fn create_sample_patch(file_name: &str) -> PathBuf {
+        let mut diff =
+            DatabaseDiff::new(create_parser(BASE_FILE), create_parser(TARGET_FILE)).unwrap();
+        let patch_path = create_temp_path(file_name);
+        let mut writer =
+            PatchWriter::new(File::create(&patch_path).unwrap(), HashType::Sha1).unwrap();
+        for operation in diff.by_ref() {
+            writer.write_operation(&operation).unwrap();
+        }
+        writer
+            .finish(BASE_CHECKSUM, get_target_information())
+            .unwrap();
+        patch_path
+    }
+
+    /// Write the supplied lines into the file of the prefix and add it to the manifest.
+    fn write_prefix_file(
+        folder: &Path,
+        prefix: &str,
+        lines: &[&str],
+        manifest: &mut DatabaseManifest,
+    ) {
+        let mut prefix_writer = PrefixFileWriter::create(folder, prefix.to_string()).unwrap();
+        for line in lines {
+            prefix_writer
+                .write_line(format!("{}\n", line).as_bytes())
+                .unwrap();
+        }
+        prefix_writer.finish(manifest).unwrap();
+    }
+
+    /// Create an optimized database (split by prefixes of length 3) of the base file.
+    fn create_sample_database(folder_name: &str) -> PathBuf {
+        let database_folder = create_temp_path(folder_name);
+        create_dir(&database_folder).unwrap();
+        let mut manifest = DatabaseManifest::new(3);
+        manifest.set_hash_type(HashType::Sha1);
+        manifest.set_source(SourceInformation::new(
+            "base.txt",
+            BASE_FILE.len() as u64,
+            BASE_CHECKSUM,
+        ));
+        for line in BASE_FILE.lines() {
+            write_prefix_file(&database_folder, &line[..3], &[line], &mut manifest);
+        }
+        manifest.write_to_folder(&database_folder).unwrap();
+        database_folder
+    }
+
+    /// Check that the optimized database contains exactly the hashes of the target file.
+    fn assert_patched_database(database_folder: &Path) {
+        let manifest = DatabaseManifest::from_folder(database_folder).unwrap();
+        assert_eq!(1, manifest.get_version());
+        assert_eq!(
+            Some(TARGET_CHECKSUM),
+            manifest.get_source().map(|source| source.get_sha256())
+        );
+        assert_eq!(0, manifest.verify_folder(database_folder, true).len());
+
+        // the file of the removed hash became empty, so it is removed as well
+        assert_eq!(false, database_folder.join("000.txt").exists());
+        assert_eq!(false, database_folder.join(STAGING_FOLDER_NAME).exists());
+        let patched_file: String = ["5BA.txt", "7C4.txt", "F00.txt"]
+            .iter()
+            .map(|file_name| read_to_string(database_folder.join(file_name)).unwrap())
+            .collect();
+        assert_eq!(TARGET_FILE, patched_file);
+    }
+
+    #[test]
+    fn applying_a_patch_to_an_optimized_database_works() {
+        let database_folder = create_sample_database("apply-patch-optimized");
+        let patch_path = create_sample_patch("apply-patch-optimized.patch");
+
+        let patch_reader = PatchReader::from_file(&patch_path).unwrap();
+        patch_optimized_database(&database_folder, patch_reader, false).unwrap();
+        assert_patched_database(&database_folder);
+
+        // applying the patch again does not change the database
+        let patch_reader = PatchReader::from_file(&patch_path).unwrap();
+        patch_optimized_database(&database_folder, patch_reader, false).unwrap();
+        assert_patched_database(&database_folder);
+
+        let _ = remove_dir_all(database_folder);
+        let _ = remove_file(patch_path);
+    }
+
+    #[test]
+    fn applying_a_patch_to_a_compiled_database_works() {
+        let database_path = create_temp_path("apply-patch-compiled.bin");
+        let mut database_writer =
+            CompiledDatabaseWriter::create(&database_path, HashType::Sha1).unwrap();
+        for line in BASE_FILE.lines() {
+            database_writer
+                .write_entry(&PasswordHashEntry::from_str(line).unwrap())
+                .unwrap();
+        }
+        database_writer.finish(BASE_CHECKSUM).unwrap();
+        let patch_path = create_sample_patch("apply-patch-compiled.patch");
+
+        let patch_reader = PatchReader::from_file(&patch_path).unwrap();
+        patch_compiled_database(&database_path, patch_reader, false).unwrap();
+        let database = CompiledDatabase::from_file(&database_path).unwrap();
+        assert_eq!(1, database.get_version());
+        assert_eq!(3, database.get_record_count());
+        assert_eq!(TARGET_CHECKSUM, database.get_source_checksum());
+        for (password, count) in &[("password", Some(5)), ("123456", Some(2))] {
+            let digest = HashDigest::from_password(password, HashType::Sha1);
+            assert_eq!(*count, database.occurrences(&digest).unwrap());
+        }
+        let removed_digest =
+            HashDigest::from_hex("000000005AD76BD555C1D6D771DE417A4B87E4B4", HashType::Sha1)
+                .unwrap();
+        assert_eq!(None, database.occurrences(&removed_digest).unwrap());
+
+        // the patch does not fit the patched database, which is not touched then
+        let patch_reader = PatchReader::from_file(&patch_path).unwrap();
+        let result = patch_compiled_database(&database_path, patch_reader, false);
+        assert_eq!(
+            true,
+            matches!(
+                result,
+                Err(PwnedError::Patch(PatchError::BaseMismatch { .. }))
+            )
+        );
+        assert_eq!(
+            1,
+            CompiledDatabase::from_file(&database_path)
+                .unwrap()
+                .get_version()
+        );
+
+        let _ = remove_file(database_path);
+        let _ = remove_file(patch_path);
+    }
+
+    #[test]
+    fn a_complete_staging_folder_of_an_interrupted_patch_is_finished() {
+        let database_folder = create_sample_database("apply-patch-complete-staging");
+        let patch_path = create_sample_patch("apply-patch-complete-staging.patch");
+
+        // stage the patched files like an earlier run which was interrupted while moving them
+        let staging_folder = database_folder.join(STAGING_FOLDER_NAME);
+        create_dir(&staging_folder).unwrap();
+        let mut manifest = DatabaseManifest::from_folder(&database_folder).unwrap();
+        let target_lines: Vec<&str> = TARGET_FILE.lines().collect();
+        write_prefix_file(&staging_folder, "5BA", &target_lines[..1], &mut manifest);
+        write_prefix_file(&staging_folder, "7C4", &target_lines[1..2], &mut manifest);
+        manifest.remove_file("000.txt");
+        manifest.set_source(get_target_information());
+        manifest.set_version(1);
+        manifest.set_updated_at("2026-01-01T00:00:00+00:00");
+        write(staging_folder.join(REMOVED_FILES_NAME), "000.txt\n").unwrap();
+        manifest.write_to_folder(&staging_folder).unwrap();
+        rename(
+            staging_folder.join("5BA.txt"),
+            database_folder.join("5BA.txt"),
+        )
+        .unwrap();
+
+        // the staged manifest is used, the patch is not applied a second time
+        let patch_reader = PatchReader::from_file(&patch_path).unwrap();
+        patch_optimized_database(&database_folder, patch_reader, false).unwrap();
+        assert_patched_database(&database_folder);
+        let manifest = DatabaseManifest::from_folder(&database_folder).unwrap();
+        assert_eq!(Some("2026-01-01T00:00:00+00:00"), manifest.get_updated_at());
+
+        let _ = remove_dir_all(database_folder);
+        let _ = remove_file(patch_path);
+    }
+
+    #[test]
+    fn an_incomplete_staging_folder_of_an_interrupted_patch_is_discarded() {
+        let database_folder = create_sample_database("apply-patch-incomplete-staging");
+        let patch_path = create_sample_patch("apply-patch-incomplete-staging.patch");
+
+        // an earlier run was interrupted while staging the files, so there is no manifest yet
+        let staging_folder = database_folder.join(STAGING_FOLDER_NAME);
+        create_dir(&staging_folder).unwrap();
+        write(staging_folder.join("5BA.txt"), "5BAA61E4C9B93F3F0682").unwrap();
+
+        let patch_reader = PatchReader::from_file(&patch_path).unwrap();
+        patch_optimized_database(&database_folder, patch_reader, false).unwrap();
+        assert_patched_database(&database_folder);
+
+        let _ = remove_dir_all(database_folder);
+        let _ = remove_file(patch_path);
+    }
+}
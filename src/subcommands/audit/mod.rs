@@ -0,0 +1,105 @@
+use crate::database::{LookupOutcome, PasswordDatabase};
+use crate::error::PwnedError;
+use crate::export::{read_export, ExportFormat};
+use crate::output::LookupRecord;
+use crate::subcommands::{
+    create_record_writer, get_hash_type, open_database, open_remote_database, write_lookup_record,
+};
+use crate::HashType;
+use clap::ArgMatches;
+use log::{debug, info};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Look up every password of a password manager export and report the entries whose password was
+/// found. The outcome is `Found` with the number of affected entries if at least one was found.
+pub fn run_subcommand(matches: &ArgMatches) -> Result<LookupOutcome, PwnedError> {
+    // get the path to the export of the password manager and its format
+    let export_path = match matches.value_of("export-file") {
+        Some(path) => Path::new(path),
+        None => return Err(PwnedError::InvalidArgument("It seems that the path to the export of the password manager was not provided, please see the help for usage instructions.".to_string())),
+    };
+    let export_format = match matches.value_of("format") {
+        Some(format) => ExportFormat::from_str(format).map_err(PwnedError::InvalidArgument)?,
+        None => match ExportFormat::from_path(export_path) {
+            Some(format) => format,
+            None => return Err(PwnedError::InvalidArgument("The format of the export could not be guessed from its file name, please select it with --format.".to_string())),
+        },
+    };
+
+    // open the password database (either a local one or, with --remote, the range API)
+    let (hash_type, database): (HashType, Box<dyn PasswordDatabase>) = if matches
+        .is_present("remote")
+    {
+        let (hash_type, database) = open_remote_database(matches)?;
+        (hash_type, Box::new(database))
+    } else {
+        let database_path = match matches.value_of("password-database") {
+                Some(path) => Path::new(path),
+                None => return Err(PwnedError::InvalidArgument("It seems that the path to the password database was not provided (or --remote for using the range API), please see the help for usage instructions.".to_string())),
+            };
+        let hash_type = get_hash_type(matches, database_path)?;
+        match open_database(matches, database_path) {
+            Ok(database) => (hash_type, database),
+            Err(error) => {
+                return Err(PwnedError::Database(
+                    "Could not open the database".to_string(),
+                    error,
+                ))
+            }
+        }
+    };
+
+    // the passwords are hashed while the export is read, the plaintext is never kept
+    let exported_entries = read_export(export_path, export_format, hash_type)
+        .map_err(|error| PwnedError::Export(export_format, error))?;
+    debug!(
+        "Read {} entries with a password from the {} export",
+        exported_entries.len(),
+        export_format
+    );
+
+    // report the result of every entry by its title, username and URL
+    let mut record_writer = create_record_writer(matches)?;
+    let mut affected_entries = 0;
+    for exported_entry in &exported_entries {
+        let digest = match exported_entry.get_password_hash().get_digest() {
+            Some(digest) => digest,
+            None => continue,
+        };
+        let occurrences = database.occurrences(&digest)?.unwrap_or(0);
+        let message = if occurrences > 0 {
+            affected_entries += 1;
+            format!(
+                "The password of {} was found {} times in password breaches. Please change it!",
+                exported_entry.get_label(),
+                occurrences
+            )
+        } else {
+            format!(
+                "The password of {} could not be found in any of the available breaches.",
+                exported_entry.get_label()
+            )
+        };
+        let record = LookupRecord::new(
+            &exported_entry.get_label(),
+            &digest,
+            matches.is_present("full-hash"),
+            occurrences > 0,
+            Some(occurrences),
+            database.get_backend_name(),
+        );
+        write_lookup_record(&mut record_writer, &record, &message)?;
+    }
+
+    info!(
+        "The passwords of {} of {} entries were found in password breaches",
+        affected_entries,
+        exported_entries.len()
+    );
+    if affected_entries > 0 {
+        Ok(LookupOutcome::Found(affected_entries))
+    } else {
+        Ok(LookupOutcome::NotFound)
+    }
+}
@@ -0,0 +1,250 @@
+use crate::compiled::is_compiled_database;
+use crate::database::PasswordDatabase;
+use crate::error::PwnedError;
+use crate::haveibeenpwned::DatabaseIterator;
+use crate::merge::{MergeCandidate, MergeJoin, MERGE_BACKEND_NAME};
+use crate::output::LookupRecord;
+use crate::subcommands::{
+    create_record_writer, get_hash_type, open_database, verify_optimized_database,
+    write_lookup_record,
+};
+use crate::{HashType, PasswordHashEntry};
+use clap::ArgMatches;
+use log::debug;
+use std::fs::File;
+use std::io::{stdin, BufRead, BufReader, Error};
+use std::path::Path;
+
+/// A single password which was read from the batch input together with the line it was found on.
+struct BatchEntry {
+    line_number: usize,
+    password_hash: PasswordHashEntry,
+    occurrences: Option<u64>,
+}
+
+fn read_batch_entries(
+    reader: &mut dyn BufRead,
+    hash_type: HashType,
+) -> Result<Vec<BatchEntry>, Error> {
+    let mut batch_entries = Vec::new();
+    let mut line_number = 0;
+
+    // read the input line by line and hash the passwords as soon as possible
+    let mut line_buffer = String::new();
+    loop {
+        line_buffer.clear();
+        if reader.read_line(&mut line_buffer)? == 0 {
+            break;
+        }
+        line_number += 1;
+
+        // remove the line ending (LF or CRLF) but keep all other whitespaces as part of the password
+        let password = line_buffer.trim_end_matches('\n').trim_end_matches('\r');
+        if password.is_empty() {
+            continue;
+        }
+
+        batch_entries.push(BatchEntry {
+            line_number,
+            password_hash: PasswordHashEntry::from_password_with_type(password, hash_type),
+            occurrences: None,
+        });
+    }
+
+    Ok(batch_entries)
+}
+
+impl MergeCandidate for BatchEntry {
+    fn get_password_hash(&self) -> &PasswordHashEntry {
+        &self.password_hash
+    }
+}
+
+/// Merge the ordered batch entries with the password file, so the whole file has to be read just
+/// once in a single forward pass.
+fn lookup_in_ordered_file(
+    password_file: &str,
+    batch_entries: Vec<BatchEntry>,
+    hash_type: HashType,
+) -> Result<Vec<BatchEntry>, PwnedError> {
+    let parser = match DatabaseIterator::from_file(password_file) {
+        Ok(parser) => parser,
+        Err(error) => {
+            return Err(PwnedError::Database(
+                "Could not get an instance of the parser".to_string(),
+                error,
+            ))
+        }
+    };
+    if parser.get_hash_type() != hash_type {
+        return Err(PwnedError::InvalidArgument(format!(
+            "The password file contains {} hashes, but the passwords were hashed with {}.",
+            parser.get_hash_type(),
+            hash_type
+        )));
+    }
+
+    let mut merge_join = MergeJoin::new(parser, batch_entries.into_iter());
+    let mut looked_up_entries = Vec::new();
+    for (mut batch_entry, occurrences) in merge_join.by_ref() {
+        batch_entry.occurrences = occurrences;
+        looked_up_entries.push(batch_entry);
+    }
+
+    // if the merge stopped early, the remaining passwords could be part of the password file
+    match merge_join.take_error() {
+        Some(error) => Err(error),
+        None => Ok(looked_up_entries),
+    }
+}
+
+fn lookup_in_database<D: PasswordDatabase + ?Sized>(
+    database: &D,
+    batch_entries: &mut [BatchEntry],
+) -> Result<(), PwnedError> {
+    for batch_entry in batch_entries.iter_mut() {
+        if let Some(digest) = batch_entry.password_hash.get_digest() {
+            batch_entry.occurrences = database.occurrences(&digest)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn run_subcommand(matches: &ArgMatches) -> Result<(), PwnedError> {
+    // get the path to the password database (either the original file or the optimized folder)
+    let password_database_path = match matches.value_of("password-database") {
+        Some(path) => path,
+        None => return Err(PwnedError::InvalidArgument("It seems that the path to the password database was not provided, please see the help for usage instructions.".to_string())),
+    };
+
+    // an optimized database is validated the same way as for a single lookup
+    if Path::new(password_database_path).is_dir() {
+        verify_optimized_database(Path::new(password_database_path))?;
+    }
+
+    // determine the type of the hashes stored in the password database
+    let hash_type = get_hash_type(matches, Path::new(password_database_path))?;
+
+    // read all passwords from the input file or from stdin if no file was provided
+    let read_entries = match matches.value_of("input") {
+        Some(path) if path != "-" => match File::open(path) {
+            Ok(file_handle) => read_batch_entries(&mut BufReader::new(file_handle), hash_type),
+            Err(error) => {
+                return Err(PwnedError::Io(
+                    "Could not open the file with the passwords".to_string(),
+                    error,
+                ))
+            }
+        },
+        _ => read_batch_entries(&mut stdin().lock(), hash_type),
+    };
+    let mut batch_entries = match read_entries {
+        Ok(entries) => entries,
+        Err(error) => {
+            return Err(PwnedError::Io(
+                "Could not read the passwords".to_string(),
+                error,
+            ))
+        }
+    };
+    debug!("Read {} passwords for the lookup", batch_entries.len());
+
+    // sort the input by the hash, so the database can be read in a single forward pass
+    batch_entries.sort_by(|first, second| first.password_hash.cmp(&second.password_hash));
+    // a plain password file is merged with the input, all other databases are searched directly
+    let database_path = Path::new(password_database_path);
+    let backend_name = if database_path.is_dir() || is_compiled_database(database_path) {
+        match open_database(matches, database_path) {
+            Ok(database) => {
+                lookup_in_database(database.as_ref(), &mut batch_entries)?;
+                database.get_backend_name()
+            }
+            Err(error) => {
+                return Err(PwnedError::Database(
+                    "Could not open the database".to_string(),
+                    error,
+                ))
+            }
+        }
+    } else {
+        batch_entries = lookup_in_ordered_file(password_database_path, batch_entries, hash_type)?;
+        MERGE_BACKEND_NAME
+    };
+
+    // report the results in the same order as the passwords were supplied
+    let mut record_writer = create_record_writer(matches)?;
+    batch_entries.sort_by_key(|entry| entry.line_number);
+    for batch_entry in &batch_entries {
+        let digest = match batch_entry.password_hash.get_digest() {
+            Some(digest) => digest,
+            None => continue,
+        };
+        let message = match batch_entry.occurrences {
+            Some(count) => format!(
+                "The password on line {} was found {} times in password breaches.",
+                batch_entry.line_number, count
+            ),
+            None => format!(
+                "The password on line {} could not be found in any of the available breaches.",
+                batch_entry.line_number
+            ),
+        };
+        let record = LookupRecord::new(
+            &format!("line:{}", batch_entry.line_number),
+            &digest,
+            matches.is_present("full-hash"),
+            batch_entry.occurrences.is_some(),
+            Some(batch_entry.occurrences.unwrap_or(0)),
+            backend_name,
+        );
+        write_lookup_record(&mut record_writer, &record, &message)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::create_sample_file;
+
+    #[test]
+    fn reading_batch_entries_skips_empty_lines_and_strips_line_endings() {
+        let mut input = "sample_password\r\n\nsample_password\n".as_bytes();
+
+        let batch_entries = read_batch_entries(&mut input, HashType::Sha1).unwrap();
+        assert_eq!(2, batch_entries.len());
+        assert_eq!(1, batch_entries[0].line_number);
+        assert_eq!(3, batch_entries[1].line_number);
+        assert_eq!(
+            "fdc625010c4beb998e590924df39b7e59298612d",
+            batch_entries[1].password_hash.get_hash()
+        );
+    }
+
+    #[test]
+    fn looking_up_entries_in_a_file_of_another_hash_type_fails() {
+        let file_path = create_sample_file(
+            "batch-lookup-sha1.txt",
+            "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3730471\r\n\
+             FDC625010C4BEB998E590924DF39B7E59298612D:2\r\n",
+        );
+        let password_file = file_path.to_str().unwrap();
+
+        let mut input = "password\nunknown\n".as_bytes();
+        let mut batch_entries = read_batch_entries(&mut input, HashType::Sha1).unwrap();
+        batch_entries.sort_by(|first, second| first.password_hash.cmp(&second.password_hash));
+        let mut looked_up_entries =
+            lookup_in_ordered_file(password_file, batch_entries, HashType::Sha1).unwrap();
+        looked_up_entries.sort_by_key(|entry| entry.line_number);
+        assert_eq!(Some(3730471), looked_up_entries[0].occurrences);
+        assert_eq!(None, looked_up_entries[1].occurrences);
+
+        // NTLM hashes could never be found, which must not be reported as not pwned
+        let mut input = "password\n".as_bytes();
+        let batch_entries = read_batch_entries(&mut input, HashType::Ntlm).unwrap();
+        let result = lookup_in_ordered_file(password_file, batch_entries, HashType::Ntlm);
+        assert_eq!(true, matches!(result, Err(PwnedError::InvalidArgument(_))));
+
+        let _ = std::fs::remove_file(file_path);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::error::PwnedError;
+use crate::filter::BloomFilterBuilder;
+use crate::haveibeenpwned::{DatabaseIterator, STDIN_PATH};
+use crate::subcommands::create_progress_bar;
+use clap::ArgMatches;
+use log::{debug, info, warn};
+use std::path::Path;
+
+/// Get a new instance of the password parser for the supplied password file. The filter does not
+/// depend on the order of the hashes, so the file can be ordered by prevalence as well.
+fn open_password_file(password_hash_path: &str) -> Result<DatabaseIterator, PwnedError> {
+    let mut parser = DatabaseIterator::from_file(password_hash_path).map_err(|error| {
+        PwnedError::Database("Could not get an instance of the parser".to_string(), error)
+    })?;
+    parser.set_order_check(false);
+    Ok(parser)
+}
+
+/// Return the error which stopped the parser before the whole password file was read.
+fn ensure_end_was_reached(parser: &mut DatabaseIterator) -> Result<(), PwnedError> {
+    match parser.take_error() {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+pub fn run_subcommand(matches: &ArgMatches) -> Result<(), PwnedError> {
+    // get the path to the password file
+    let password_hash_path = match matches.value_of("password-hashes") {
+        Some(path) => path,
+        None => return Err(PwnedError::InvalidArgument("It seems that the path to the file for the password hashes was not provided, please see the help for usage instructions.".to_string())),
+    };
+    debug!("Got {} as a password hash file", password_hash_path);
+
+    // get the path of the filter which should be written
+    let output_file = match matches.value_of("output-file") {
+        Some(path) => Path::new(path),
+        None => return Err(PwnedError::InvalidArgument("It seems that the path where the filter should be stored was not provided, please see the help for usage instructions.".to_string())),
+    };
+
+    // get the false positive rate and the minimal number of occurrences of the included hashes
+    let false_positive_rate = match matches
+        .value_of("false-positive-rate")
+        .unwrap_or("0.01")
+        .parse::<f64>()
+    {
+        Ok(rate) if rate > 0.0 && rate < 1.0 => rate,
+        _ => {
+            return Err(PwnedError::InvalidArgument(
+                "The false positive rate has to be a number between 0 and 1 (e.g. 0.01)."
+                    .to_string(),
+            ))
+        }
+    };
+    let minimal_occurrences = match matches.value_of("min-occurrences") {
+        Some(value) => match value.parse::<u64>() {
+            Ok(count) => count,
+            Err(_) => {
+                return Err(PwnedError::InvalidArgument(
+                    "The minimal number of occurrences has to be a positive number.".to_string(),
+                ))
+            }
+        },
+        None => 0,
+    };
+
+    // the size of the filter depends on the number of entries, so count them if they are not known
+    let expected_entries = match matches.value_of("expected-entries") {
+        Some(value) => match value.parse::<u64>() {
+            Ok(count) if count > 0 => count,
+            _ => {
+                return Err(PwnedError::InvalidArgument(
+                    "The expected number of entries has to be a positive number.".to_string(),
+                ))
+            }
+        },
+        None if password_hash_path == STDIN_PATH => {
+            return Err(PwnedError::InvalidArgument("The password hashes can only be read once from the standard input. Please supply the number of entries with --expected-entries.".to_string()));
+        }
+        None => {
+            info!("Counting the password hashes to determine the size of the filter...");
+            let mut parser = open_password_file(password_hash_path)?;
+            let progress_bar = create_progress_bar(parser.get_file_size());
+            let mut counted_entries = 0;
+            while let Some(password_hash_entry) = parser.next() {
+                if password_hash_entry.get_occurrences() >= minimal_occurrences {
+                    counted_entries += 1;
+                }
+                progress_bar.set_position(parser.get_consumed_bytes());
+            }
+            progress_bar.finish_and_clear();
+            ensure_end_was_reached(&mut parser)?;
+            counted_entries
+        }
+    };
+    debug!(
+        "Building a filter for {} password hashes with a false positive rate of {}",
+        expected_entries, false_positive_rate
+    );
+
+    // add all entries of the password file to the filter
+    let mut parser = open_password_file(password_hash_path)?;
+    let mut filter_builder = BloomFilterBuilder::new(
+        parser.get_hash_type(),
+        expected_entries,
+        false_positive_rate,
+        minimal_occurrences,
+    );
+    let progress_bar = create_progress_bar(parser.get_file_size());
+    while let Some(password_hash_entry) = parser.next() {
+        filter_builder.insert(&password_hash_entry)?;
+        progress_bar.set_position(parser.get_consumed_bytes());
+    }
+    progress_bar.finish_with_message("filtered");
+    ensure_end_was_reached(&mut parser)?;
+    if filter_builder.get_entry_count() > expected_entries {
+        warn!(
+            "The filter contains {} instead of the expected {} password hashes, so the false positive rate is higher than requested.",
+            filter_builder.get_entry_count(),
+            expected_entries
+        );
+    }
+
+    // write the filter into the output file
+    filter_builder.write_to_file(output_file)?;
+    info!(
+        "Added {} password hashes to the filter {} ({} bytes)",
+        filter_builder.get_entry_count(),
+        output_file.display(),
+        filter_builder.get_size_in_bytes()
+    );
+    Ok(())
+}
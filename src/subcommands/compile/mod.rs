@@ -0,0 +1,71 @@
+use crate::compiled::{CompileError, CompiledDatabaseWriter};
+use crate::error::PwnedError;
+use crate::haveibeenpwned::DatabaseIterator;
+use crate::subcommands::create_progress_bar;
+use clap::ArgMatches;
+use log::{debug, info};
+use std::path::Path;
+
+pub fn run_subcommand(matches: &ArgMatches) -> Result<(), PwnedError> {
+    // get the path to the password file
+    let password_hash_path = match matches.value_of("password-hashes") {
+        Some(path) => path,
+        None => return Err(PwnedError::InvalidArgument("It seems that the path to the file for the password hashes was not provided, please see the help for usage instructions.".to_string())),
+    };
+    debug!("Got {} as a password hash file", password_hash_path);
+
+    // get the path of the compiled database which should be written
+    let output_file = match matches.value_of("output-file") {
+        Some(path) => Path::new(path),
+        None => return Err(PwnedError::InvalidArgument("It seems that the path where the compiled database should be stored was not provided, please see the help for usage instructions.".to_string())),
+    };
+    debug!("Got {} as the output file", output_file.display());
+
+    // get an instance of the password parser
+    let mut parser = match DatabaseIterator::from_file(password_hash_path) {
+        Ok(parser) => parser,
+        Err(error) => {
+            return Err(PwnedError::Database(
+                "Could not get an instance of the parser".to_string(),
+                error,
+            ))
+        }
+    };
+
+    // create the compiled database for the hash type of the password file
+    let mut database_writer = CompiledDatabaseWriter::create(output_file, parser.get_hash_type())?;
+
+    // get an instance from  the progress bar to indicate the compilation progress (of the compressed data)
+    let progress_bar = create_progress_bar(parser.get_file_size());
+
+    // convert all entries of the password file into fixed-width records
+    while let Some(password_hash_entry) = parser.next() {
+        match database_writer.write_entry(&password_hash_entry) {
+            Ok(_) => {}
+            Err(CompileError::UnsortedEntries) => {
+                return Err(PwnedError::UnsortedInput {
+                    line_number: parser.get_line_number(),
+                    byte_offset: parser.get_byte_offset(),
+                })
+            }
+            Err(error) => return Err(PwnedError::Compile(error)),
+        }
+        progress_bar.set_position(parser.get_consumed_bytes());
+    }
+    progress_bar.finish_with_message("compiled");
+
+    // if the parser stopped early, the password file could not be read completely
+    if let Some(error) = parser.take_error() {
+        return Err(error);
+    }
+
+    // write the header with the checksum of the source file
+    let source_checksum = parser.get_checksum().unwrap_or_default();
+    let record_count = database_writer.finish(&source_checksum)?;
+    info!(
+        "Compiled {} password hashes into {}",
+        record_count,
+        output_file.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,849 @@
+use crate::database::LookupOutcome;
+use crate::error::PwnedError;
+use crate::manifest::MANIFEST_FILE_NAME;
+use crate::range::RANGE_PREFIX_LENGTH;
+use crate::subcommands::get_hash_type;
+use crate::subcommands::serve::RangeBackend;
+use crate::{HashDigest, HashType};
+use clap::ArgMatches;
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::SystemTime;
+
+#[cfg(unix)]
+use log::{debug, info, warn};
+#[cfg(unix)]
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(unix)]
+use std::time::{Duration, Instant};
+
+/// The maximal number of bytes of a single request. The connection of a client which sends a longer
+/// line is closed.
+const MAXIMAL_REQUEST_LENGTH: usize = 4096;
+
+/// How long the daemon waits for a new connection (or a client for its next request) before it
+/// checks if it should shut down.
+#[cfg(unix)]
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long the daemon tries to send a response to a client which does not read it.
+#[cfg(unix)]
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Set by the signal handlers, the daemon stops accepting clients and shuts down.
+#[cfg(unix)]
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Set by the signal handler for SIGHUP, the daemon reloads its database.
+#[cfg(unix)]
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// A request which was sent to the daemon, either as a plain line or as a JSON object.
+#[derive(Debug, PartialEq)]
+enum DaemonRequest {
+    /// Look up how often the hash occurred in password breaches.
+    Hash(HashDigest),
+    /// Get all hashes (and their occurrences) which start with the prefix.
+    Prefix(String),
+    Ping,
+    Reload,
+    /// Close the connection.
+    Quit,
+}
+
+/// The answer to a request, which is written in the format of the request.
+#[derive(Debug, PartialEq)]
+enum DaemonResponse {
+    Occurrences {
+        hash: String,
+        occurrences: Option<u64>,
+    },
+    Range {
+        prefix: String,
+        entries: Vec<(String, u64)>,
+    },
+    Pong,
+    Reloaded,
+    Error(String),
+}
+
+/// A request in the JSON protocol, which has to contain exactly one of `hash`, `prefix` or
+/// `command`. The `id` is sent back with the response.
+#[derive(Deserialize)]
+struct JsonRequest {
+    id: Option<Value>,
+    hash: Option<String>,
+    prefix: Option<String>,
+    command: Option<String>,
+}
+
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    hash: &'a str,
+    occurrences: u64,
+}
+
+#[derive(Serialize, Default)]
+struct JsonResponse<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<&'a Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    found: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    occurrences: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prefix: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entries: Option<Vec<JsonEntry<'a>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+/// Parse a complete hash or a prefix (at least as long as the prefixes of the range API) of one.
+fn parse_query(query: &str, hash_type: HashType) -> Result<DaemonRequest, String> {
+    let hash_length = hash_type.get_hash_length();
+    if !query.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!(
+            "The request is neither a {} hash nor a prefix of one",
+            hash_type
+        ));
+    }
+    if query.len() == hash_length {
+        HashDigest::from_hex(query, hash_type)
+            .map(DaemonRequest::Hash)
+            .map_err(|error| error.to_string())
+    } else if (RANGE_PREFIX_LENGTH..hash_length).contains(&query.len()) {
+        Ok(DaemonRequest::Prefix(query.to_uppercase()))
+    } else {
+        Err(format!(
+            "Expected a {} hash with {} characters or a prefix with {} to {} characters",
+            hash_type,
+            hash_length,
+            RANGE_PREFIX_LENGTH,
+            hash_length - 1
+        ))
+    }
+}
+
+/// Parse a request of the line protocol, which is a command or a hash (prefix).
+fn parse_line_request(line: &str, hash_type: HashType) -> Result<DaemonRequest, String> {
+    match line.to_uppercase().as_str() {
+        "PING" => Ok(DaemonRequest::Ping),
+        "RELOAD" => Ok(DaemonRequest::Reload),
+        "QUIT" => Ok(DaemonRequest::Quit),
+        _ => parse_query(line, hash_type),
+    }
+}
+
+/// Parse a request of the JSON protocol and return it together with its ID.
+fn parse_json_request(
+    line: &str,
+    hash_type: HashType,
+) -> (Option<Value>, Result<DaemonRequest, String>) {
+    let request: JsonRequest = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(error) => return (None, Err(format!("The request is not valid: {}", error))),
+    };
+
+    let parsed_request = match (request.hash, request.prefix, request.command) {
+        (Some(hash), None, None) => match parse_query(&hash, hash_type) {
+            Ok(DaemonRequest::Prefix(_)) => Err(format!(
+                "The hash has to be a complete {} hash, use prefix for looking up a prefix",
+                hash_type
+            )),
+            parsed => parsed,
+        },
+        (None, Some(prefix), None) => match parse_query(&prefix, hash_type) {
+            Ok(DaemonRequest::Hash(_)) => Err(format!(
+                "The prefix has to be shorter than a {} hash, use hash for looking up a hash",
+                hash_type
+            )),
+            parsed => parsed,
+        },
+        (None, None, Some(command)) => match command.to_lowercase().as_str() {
+            "ping" => Ok(DaemonRequest::Ping),
+            "reload" => Ok(DaemonRequest::Reload),
+            "quit" => Ok(DaemonRequest::Quit),
+            _ => Err(format!("The command {} is not known", command)),
+        },
+        _ => Err("A request needs exactly one of hash, prefix or command".to_string()),
+    };
+    (request.id, parsed_request)
+}
+
+fn format_line_response(response: &DaemonResponse) -> String {
+    match response {
+        DaemonResponse::Occurrences {
+            occurrences: Some(occurrences),
+            ..
+        } => format!("FOUND {}\n", occurrences),
+        DaemonResponse::Occurrences {
+            occurrences: None, ..
+        } => "NOT_FOUND\n".to_string(),
+        DaemonResponse::Range { entries, .. } => {
+            let mut lines = format!("RANGE {}\n", entries.len());
+            for (hash, occurrences) in entries {
+                lines.push_str(&format!("{}:{}\n", hash, occurrences));
+            }
+            lines
+        }
+        DaemonResponse::Pong => "PONG\n".to_string(),
+        DaemonResponse::Reloaded => "RELOADED\n".to_string(),
+        DaemonResponse::Error(message) => format!("ERROR {}\n", message),
+    }
+}
+
+fn format_json_response(response: &DaemonResponse, id: Option<&Value>) -> String {
+    let mut json_response = JsonResponse {
+        id,
+        ..Default::default()
+    };
+    match response {
+        DaemonResponse::Occurrences { hash, occurrences } => {
+            json_response.hash = Some(hash);
+            json_response.found = Some(occurrences.is_some());
+            json_response.occurrences = Some(occurrences.unwrap_or(0));
+        }
+        DaemonResponse::Range { prefix, entries } => {
+            json_response.prefix = Some(prefix);
+            json_response.entries = Some(
+                entries
+                    .iter()
+                    .map(|(hash, occurrences)| JsonEntry {
+                        hash,
+                        occurrences: *occurrences,
+                    })
+                    .collect(),
+            );
+        }
+        DaemonResponse::Pong => json_response.status = Some("pong"),
+        DaemonResponse::Reloaded => json_response.status = Some("reloaded"),
+        DaemonResponse::Error(message) => json_response.error = Some(message),
+    }
+    match serde_json::to_string(&json_response) {
+        Ok(line) => format!("{}\n", line),
+        Err(_) => "{\"error\":\"Could not serialize the response\"}\n".to_string(),
+    }
+}
+
+/// Get the time the database was changed the last time. For an optimized database, the manifest
+/// is checked since it is written after all files were replaced (e.g. by `apply-patch`).
+fn get_modification_time(database_path: &Path) -> Option<SystemTime> {
+    let changed_file = if database_path.is_dir() {
+        database_path.join(MANIFEST_FILE_NAME)
+    } else {
+        database_path.to_path_buf()
+    };
+    changed_file
+        .metadata()
+        .and_then(|data| data.modified())
+        .ok()
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// The database which answers the requests. It is replaced as a whole when it is reloaded, so
+/// requests which are answered at the same time just keep using the previous version.
+struct DaemonState<'a, 'b> {
+    matches: &'a ArgMatches<'b>,
+    database_path: PathBuf,
+    hash_type: HashType,
+    backend: RwLock<Arc<RangeBackend>>,
+    loaded_modification: Mutex<Option<SystemTime>>,
+}
+
+impl<'a, 'b> DaemonState<'a, 'b> {
+    fn open(matches: &'a ArgMatches<'b>, database_path: &Path) -> Result<Self, PwnedError> {
+        let hash_type = get_hash_type(matches, database_path)?;
+        let loaded_modification = get_modification_time(database_path);
+        let backend = RangeBackend::open(matches, database_path, hash_type)?;
+        Ok(DaemonState {
+            matches,
+            database_path: database_path.to_path_buf(),
+            hash_type,
+            backend: RwLock::new(Arc::new(backend)),
+            loaded_modification: Mutex::new(loaded_modification),
+        })
+    }
+
+    fn get_backend(&self) -> Arc<RangeBackend> {
+        match self.backend.read() {
+            Ok(backend) => Arc::clone(&backend),
+            Err(poisoned) => Arc::clone(&poisoned.into_inner()),
+        }
+    }
+
+    /// Open the database again and use it for all following requests. If it cannot be opened, the
+    /// previous version is kept.
+    fn reload(&self) -> Result<(), PwnedError> {
+        // the lock makes sure that the database is not reloaded by two clients at the same time
+        let mut loaded_modification = lock(&self.loaded_modification);
+        let modification = get_modification_time(&self.database_path);
+        let backend = RangeBackend::open(self.matches, &self.database_path, self.hash_type)?;
+        match self.backend.write() {
+            Ok(mut current_backend) => *current_backend = Arc::new(backend),
+            Err(poisoned) => *poisoned.into_inner() = Arc::new(backend),
+        }
+        *loaded_modification = modification;
+        Ok(())
+    }
+
+    /// Reload the database if it was changed. It is just reloaded once the modification time did
+    /// not change since the last check (`last_seen`), so an update which is still written is not
+    /// loaded half-way.
+    #[cfg(unix)]
+    fn reload_if_modified(&self, last_seen: &mut Option<SystemTime>) {
+        let modification = get_modification_time(&self.database_path);
+        let loaded_modification = *lock(&self.loaded_modification);
+        if modification != loaded_modification && modification == *last_seen {
+            match self.reload() {
+                Ok(_) => info!(
+                    "Reloaded the updated database {}",
+                    self.database_path.display()
+                ),
+                Err(error) => {
+                    // the broken version is not tried again until the database changes the next time
+                    *lock(&self.loaded_modification) = modification;
+                    error!(
+                        "Could not reload the updated database, the previous version is still used. The error was: {}",
+                        error
+                    );
+                }
+            }
+        }
+        *last_seen = modification;
+    }
+}
+
+/// Answer the supplied request. `None` is returned if the connection should be closed.
+fn answer_request(state: &DaemonState, request: &DaemonRequest) -> Option<DaemonResponse> {
+    let response = match request {
+        DaemonRequest::Hash(digest) => match state.get_backend().get_database().lookup(digest) {
+            Ok(outcome) => DaemonResponse::Occurrences {
+                hash: digest.to_hex(),
+                occurrences: match outcome {
+                    LookupOutcome::Found(occurrences) => Some(occurrences),
+                    LookupOutcome::NotFound => None,
+                },
+            },
+            Err(error) => {
+                // a missing answer must not be mistaken for a hash which was not found
+                error!("Could not look up a hash. The error was: {}", error);
+                DaemonResponse::Error("The lookup failed".to_string())
+            }
+        },
+        DaemonRequest::Prefix(prefix) => {
+            match state.get_backend().get_entries_with_prefix(prefix) {
+                Ok(found_entries) => DaemonResponse::Range {
+                    prefix: prefix.clone(),
+                    entries: found_entries
+                        .iter()
+                        .map(|entry| (entry.get_hash().to_uppercase(), entry.get_occurrences()))
+                        .collect(),
+                },
+                Err(error) => {
+                    error!(
+                        "Could not look up the prefix {}. The error was: {}",
+                        prefix, error
+                    );
+                    DaemonResponse::Error("The lookup failed".to_string())
+                }
+            }
+        }
+        DaemonRequest::Ping => DaemonResponse::Pong,
+        DaemonRequest::Reload => match state.reload() {
+            Ok(_) => DaemonResponse::Reloaded,
+            Err(error) => {
+                DaemonResponse::Error(format!("Could not reload the database: {}", error))
+            }
+        },
+        DaemonRequest::Quit => return None,
+    };
+    Some(response)
+}
+
+/// Answer a single line of a client. A line which starts with `{` is handled as JSON request and
+/// answered with a JSON object, all other lines are handled as requests of the line protocol.
+fn answer_line(state: &DaemonState, line: &[u8]) -> Option<String> {
+    let line = match std::str::from_utf8(line) {
+        Ok(line) => line.trim(),
+        Err(_) => {
+            let response = DaemonResponse::Error("The request is not valid UTF-8".to_string());
+            return Some(format_line_response(&response));
+        }
+    };
+    if line.is_empty() {
+        return Some(String::new());
+    }
+
+    if line.starts_with('{') {
+        let (id, request) = parse_json_request(line, state.hash_type);
+        let response = match request {
+            Ok(request) => answer_request(state, &request)?,
+            Err(message) => DaemonResponse::Error(message),
+        };
+        Some(format_json_response(&response, id.as_ref()))
+    } else {
+        let response = match parse_line_request(line, state.hash_type) {
+            Ok(request) => answer_request(state, &request)?,
+            Err(message) => DaemonResponse::Error(message),
+        };
+        Some(format_line_response(&response))
+    }
+}
+
+/// Answer the requests of a client until it closes the connection (or sends `QUIT`) or the daemon
+/// shuts down.
+#[cfg(unix)]
+fn handle_client(stream: UnixStream, state: &DaemonState) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let mut writer = BufWriter::new(stream.try_clone()?);
+    let mut reader = BufReader::new(stream);
+
+    let mut line = Vec::new();
+    while !SHUTDOWN_REQUESTED.load(Ordering::SeqCst) {
+        // a partial line is kept if the timeout expires before the client finished it
+        let remaining_length = (MAXIMAL_REQUEST_LENGTH + 1 - line.len()) as u64;
+        match reader
+            .by_ref()
+            .take(remaining_length)
+            .read_until(b'\n', &mut line)
+        {
+            Ok(_) => {}
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                ) =>
+            {
+                continue
+            }
+            Err(error) => return Err(error),
+        }
+
+        // without a line break, the line was either too long or the client closed the connection
+        let is_complete = line.last() == Some(&b'\n');
+        if !is_complete && line.len() > MAXIMAL_REQUEST_LENGTH {
+            let response = DaemonResponse::Error(format!(
+                "The request is longer than {} bytes",
+                MAXIMAL_REQUEST_LENGTH
+            ));
+            writer.write_all(format_line_response(&response).as_bytes())?;
+            return writer.flush();
+        }
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        let response = answer_line(state, &line);
+        line.clear();
+        match response {
+            Some(response) => {
+                writer.write_all(response.as_bytes())?;
+                writer.flush()?;
+            }
+            None => return Ok(()),
+        }
+        if !is_complete {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+extern "C" fn request_shutdown(_signal: libc::c_int) {
+    SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+extern "C" fn request_reload(_signal: libc::c_int) {
+    RELOAD_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Shut down gracefully on SIGTERM and SIGINT and reload the database on SIGHUP. The handlers
+/// just set a flag, which is checked by the loop accepting the clients.
+#[cfg(unix)]
+fn install_signal_handlers() -> Result<(), PwnedError> {
+    let handlers: [(libc::c_int, extern "C" fn(libc::c_int)); 3] = [
+        (libc::SIGTERM, request_shutdown),
+        (libc::SIGINT, request_shutdown),
+        (libc::SIGHUP, request_reload),
+    ];
+    for (signal, handler) in handlers {
+        if unsafe { libc::signal(signal, handler as libc::sighandler_t) } == libc::SIG_ERR {
+            return Err(PwnedError::Io(
+                "Could not install the signal handlers".to_string(),
+                std::io::Error::last_os_error(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Get the ID of the group with the supplied name (or numeric ID).
+#[cfg(unix)]
+fn get_group_id(group: &str) -> Result<u32, PwnedError> {
+    if let Ok(group_id) = group.parse::<u32>() {
+        return Ok(group_id);
+    }
+    let group_name = match std::ffi::CString::new(group) {
+        Ok(group_name) => group_name,
+        Err(_) => {
+            return Err(PwnedError::InvalidArgument(format!(
+                "{} is not a valid group name.",
+                group
+            )))
+        }
+    };
+    let group_entry = unsafe { libc::getgrnam(group_name.as_ptr()) };
+    if group_entry.is_null() {
+        return Err(PwnedError::InvalidArgument(format!(
+            "The group {} does not exist.",
+            group
+        )));
+    }
+    Ok(unsafe { (*group_entry).gr_gid })
+}
+
+/// Create the socket at the supplied path with the supplied permissions (and group). A socket
+/// which is left over from a daemon which did not shut down gracefully is replaced, but not the
+/// one of a daemon which is still running.
+#[cfg(unix)]
+fn bind_socket(
+    socket_path: &Path,
+    mode: u32,
+    group_id: Option<u32>,
+) -> Result<UnixListener, PwnedError> {
+    use std::fs::{remove_file, set_permissions, Permissions};
+    use std::os::unix::fs::{chown, FileTypeExt, PermissionsExt};
+
+    if let Ok(metadata) = socket_path.symlink_metadata() {
+        if !metadata.file_type().is_socket() {
+            return Err(PwnedError::InvalidArgument(format!(
+                "{} already exists and is not a socket.",
+                socket_path.display()
+            )));
+        }
+        if UnixStream::connect(socket_path).is_ok() {
+            return Err(PwnedError::InvalidArgument(format!(
+                "Another daemon is already listening on {}.",
+                socket_path.display()
+            )));
+        }
+        debug!("Removing the stale socket {}", socket_path.display());
+        if let Err(error) = remove_file(socket_path) {
+            return Err(PwnedError::Io(
+                format!(
+                    "Could not remove the stale socket {}",
+                    socket_path.display()
+                ),
+                error,
+            ));
+        }
+    }
+
+    // nobody else can connect before the permissions were set
+    let previous_mask = unsafe { libc::umask(0o177) };
+    let bound_listener = UnixListener::bind(socket_path);
+    unsafe { libc::umask(previous_mask) };
+    let io_error = |message: &str, error| {
+        PwnedError::Io(format!("{} {}", message, socket_path.display()), error)
+    };
+    let listener = bound_listener.map_err(|error| io_error("Could not listen on", error))?;
+
+    if let Some(group_id) = group_id {
+        chown(socket_path, None, Some(group_id))
+            .map_err(|error| io_error("Could not change the group of", error))?;
+    }
+    set_permissions(socket_path, Permissions::from_mode(mode))
+        .map_err(|error| io_error("Could not change the permissions of", error))?;
+    listener
+        .set_nonblocking(true)
+        .map_err(|error| io_error("Could not configure", error))?;
+    Ok(listener)
+}
+
+/// Wait until a client connects to the socket or the timeout expires. Returns `true` if a client
+/// is waiting to be accepted.
+#[cfg(unix)]
+fn wait_for_client(listener: &UnixListener, timeout: Duration) -> bool {
+    use std::os::unix::io::AsRawFd;
+
+    let mut poll_request = libc::pollfd {
+        fd: listener.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut poll_request, 1, timeout.as_millis() as libc::c_int) > 0 }
+}
+
+/// Parse a positive number which was supplied with the option of the supplied name.
+#[cfg(unix)]
+fn parse_number(matches: &ArgMatches, name: &str, default_value: &str) -> Result<u64, PwnedError> {
+    match matches
+        .value_of(name)
+        .unwrap_or(default_value)
+        .parse::<u64>()
+    {
+        Ok(number) => Ok(number),
+        Err(_) => Err(PwnedError::InvalidArgument(format!(
+            "The value of --{} has to be a number.",
+            name
+        ))),
+    }
+}
+
+#[cfg(unix)]
+pub fn run_subcommand(matches: &ArgMatches) -> Result<(), PwnedError> {
+    // get the path to the password database (either the original file or the optimized folder)
+    let database_path = match matches.value_of("password-database") {
+        Some(path) => Path::new(path),
+        None => return Err(PwnedError::InvalidArgument("It seems that the path to the password database was not provided, please see the help for usage instructions.".to_string())),
+    };
+    let socket_path = match matches.value_of("socket") {
+        Some(path) => Path::new(path),
+        None => return Err(PwnedError::InvalidArgument("It seems that the path of the socket was not provided, please see the help for usage instructions.".to_string())),
+    };
+
+    // get the permissions of the socket and the limits of the daemon
+    let socket_mode = match u32::from_str_radix(matches.value_of("socket-mode").unwrap_or("600"), 8)
+    {
+        Ok(mode) if mode <= 0o777 => mode,
+        _ => {
+            return Err(PwnedError::InvalidArgument(
+                "The mode of the socket has to be an octal number like 660.".to_string(),
+            ))
+        }
+    };
+    let socket_group = match matches.value_of("socket-group") {
+        Some(group) => Some(get_group_id(group)?),
+        None => None,
+    };
+    let maximal_clients = match parse_number(matches, "max-clients", "64")? {
+        0 => {
+            return Err(PwnedError::InvalidArgument(
+                "The number of clients has to be a positive number.".to_string(),
+            ))
+        }
+        count => count as usize,
+    };
+    let reload_interval = match parse_number(matches, "reload-interval", "10")? {
+        0 => None,
+        seconds => Some(Duration::from_secs(seconds)),
+    };
+
+    let state = DaemonState::open(matches, database_path)?;
+    install_signal_handlers()?;
+    let listener = bind_socket(socket_path, socket_mode, socket_group)?;
+    info!(
+        "Answering lookups of {} hashes in {} on {}",
+        state.hash_type,
+        database_path.display(),
+        socket_path.display()
+    );
+
+    let connected_clients = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+        let mut last_check = Instant::now();
+        let mut last_seen_modification = *lock(&state.loaded_modification);
+        while !SHUTDOWN_REQUESTED.load(Ordering::SeqCst) {
+            if RELOAD_REQUESTED.swap(false, Ordering::SeqCst) {
+                match state.reload() {
+                    Ok(_) => info!("Reloaded the database {}", database_path.display()),
+                    Err(error) => error!(
+                        "Could not reload the database, the previous version is still used. The error was: {}",
+                        error
+                    ),
+                }
+            }
+            if let Some(reload_interval) = reload_interval {
+                if last_check.elapsed() >= reload_interval {
+                    state.reload_if_modified(&mut last_seen_modification);
+                    last_check = Instant::now();
+                }
+            }
+
+            if !wait_for_client(&listener, POLL_INTERVAL) {
+                continue;
+            }
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(error)
+                    if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) =>
+                {
+                    continue
+                }
+                Err(error) => {
+                    error!("Could not accept a client. The error was: {}", error);
+                    continue;
+                }
+            };
+            if connected_clients.load(Ordering::SeqCst) >= maximal_clients {
+                warn!(
+                    "Rejected a client since {} clients are connected already",
+                    maximal_clients
+                );
+                let _ = (&stream).write_all(b"ERROR Too many clients are connected\n");
+                continue;
+            }
+            if let Err(error) = stream.set_nonblocking(false) {
+                error!("Could not configure a client. The error was: {}", error);
+                continue;
+            }
+
+            connected_clients.fetch_add(1, Ordering::SeqCst);
+            let state = &state;
+            let connected_clients = &connected_clients;
+            scope.spawn(move || {
+                if let Err(error) = handle_client(stream, state) {
+                    debug!("The connection to a client failed: {}", error);
+                }
+                connected_clients.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+
+        // the clients notice the shutdown after their current request
+        info!(
+            "Shutting down, waiting for {} connected clients",
+            connected_clients.load(Ordering::SeqCst)
+        );
+    });
+
+    drop(listener);
+    if let Err(error) = std::fs::remove_file(socket_path) {
+        warn!(
+            "Could not remove the socket {}: {}",
+            socket_path.display(),
+            error
+        );
+    }
+    info!("The daemon was shut down");
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn run_subcommand(_matches: &ArgMatches) -> Result<(), PwnedError> {
+    Err(PwnedError::InvalidArgument(
+        "The daemon needs Unix domain sockets, which are not available on this platform."
+            .to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::create_temp_path;
+
+    #[test]
+    fn parsing_requests_works() {
+        let hash = "5baa61e4c9b93f3f0682250b6cf8331b7ee68fd8";
+        let digest = HashDigest::from_hex(hash, HashType::Sha1).unwrap();
+        assert_eq!(
+            Ok(DaemonRequest::Hash(digest.clone())),
+            parse_line_request(hash, HashType::Sha1)
+        );
+        assert_eq!(
+            Ok(DaemonRequest::Prefix("5BAA6".to_string())),
+            parse_line_request("5baa6", HashType::Sha1)
+        );
+        assert_eq!(
+            Ok(DaemonRequest::Ping),
+            parse_line_request("ping", HashType::Sha1)
+        );
+        assert_eq!(true, parse_line_request("5BAA", HashType::Sha1).is_err());
+        assert_eq!(true, parse_line_request(hash, HashType::Ntlm).is_err());
+        assert_eq!(
+            true,
+            parse_line_request("password", HashType::Sha1).is_err()
+        );
+
+        let (id, request) = parse_json_request(
+            &format!("{{\"id\": 7, \"hash\": \"{}\"}}", hash),
+            HashType::Sha1,
+        );
+        assert_eq!(Some(Value::from(7)), id);
+        assert_eq!(Ok(DaemonRequest::Hash(digest)), request);
+        let (_, request) = parse_json_request("{\"command\": \"RELOAD\"}", HashType::Sha1);
+        assert_eq!(Ok(DaemonRequest::Reload), request);
+        let (_, request) =
+            parse_json_request(&format!("{{\"prefix\": \"{}\"}}", hash), HashType::Sha1);
+        assert_eq!(true, request.is_err());
+        let (_, request) = parse_json_request("{\"prefix\": 5}", HashType::Sha1);
+        assert_eq!(true, request.is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn answering_clients_and_reloading_the_database_works() {
+        let database_file = create_temp_path("daemon-test.txt");
+        std::fs::write(
+            &database_file,
+            "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3\n5BAA6AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA:1\n",
+        )
+        .unwrap();
+        let matches = ArgMatches::default();
+        let state = DaemonState::open(&matches, &database_file).unwrap();
+
+        let (client, server) = UnixStream::pair().unwrap();
+        let requests = "5baa61e4c9b93f3f0682250b6cf8331b7ee68fd8\n\
+                        {\"id\":\"a\",\"hash\":\"7C4A8D09CA3762AF61E59520943DC26494F8941B\"}\n\
+                        5BAA6\n\
+                        unknown\n\
+                        QUIT\n\
+                        PING\n";
+        (&client).write_all(requests.as_bytes()).unwrap();
+        handle_client(server, &state).unwrap();
+
+        let mut responses = String::new();
+        (&client).read_to_string(&mut responses).unwrap();
+        let responses: Vec<&str> = responses.lines().collect();
+        assert_eq!("FOUND 3", responses[0]);
+        assert_eq!(
+            "{\"id\":\"a\",\"hash\":\"7C4A8D09CA3762AF61E59520943DC26494F8941B\",\"found\":false,\"occurrences\":0}",
+            responses[1]
+        );
+        assert_eq!("RANGE 2", responses[2]);
+        assert_eq!("5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3", responses[3]);
+        assert_eq!("5BAA6AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA:1", responses[4]);
+        assert_eq!(true, responses[5].starts_with("ERROR "));
+        assert_eq!(6, responses.len());
+
+        // the updated database replaces the mapped one and is used after it was reloaded
+        let updated_file = database_file.with_extension("updated");
+        std::fs::write(
+            &updated_file,
+            "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:5\n",
+        )
+        .unwrap();
+        std::fs::rename(&updated_file, &database_file).unwrap();
+        let (_, request) = parse_json_request("{\"command\":\"reload\"}", HashType::Sha1);
+        assert_eq!(
+            Some(DaemonResponse::Reloaded),
+            answer_request(&state, &request.unwrap())
+        );
+        assert_eq!(
+            Some("FOUND 5\n".to_string()),
+            answer_line(&state, b"5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8\n")
+        );
+
+        let _ = std::fs::remove_file(database_file);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::error::PwnedError;
+use crate::haveibeenpwned::DatabaseIterator;
+use crate::manifest::SourceInformation;
+use crate::patch::{DatabaseDiff, PatchWriter};
+use crate::subcommands::create_progress_bar;
+use clap::ArgMatches;
+use log::{debug, info};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// The number of operations between two updates of the progress bar.
+const PROGRESS_INTERVAL: u64 = 4096;
+
+/// Get an instance of the parser for the supplied password file.
+fn open_password_file(path_to_file: &str) -> Result<DatabaseIterator, PwnedError> {
+    match DatabaseIterator::from_file(path_to_file) {
+        Ok(parser) => Ok(parser),
+        Err(error) => Err(PwnedError::Database(
+            format!(
+                "Could not get an instance of the parser for {}",
+                path_to_file
+            ),
+            error,
+        )),
+    }
+}
+
+pub fn run_subcommand(matches: &ArgMatches) -> Result<(), PwnedError> {
+    // get the paths to both versions of the password file
+    let old_password_hash_path = match matches.value_of("old-password-hashes") {
+        Some(path) => path,
+        None => return Err(PwnedError::InvalidArgument("It seems that the path to the old password file was not provided, please see the help for usage instructions.".to_string())),
+    };
+    let new_password_hash_path = match matches.value_of("new-password-hashes") {
+        Some(path) => path,
+        None => return Err(PwnedError::InvalidArgument("It seems that the path to the new password file was not provided, please see the help for usage instructions.".to_string())),
+    };
+    debug!(
+        "Comparing {} with {}",
+        old_password_hash_path, new_password_hash_path
+    );
+
+    // get the path of the patch which should be written
+    let patch_file = match matches.value_of("patch-file") {
+        Some(path) => Path::new(path),
+        None => return Err(PwnedError::InvalidArgument("It seems that the path where the patch should be stored was not provided, please see the help for usage instructions.".to_string())),
+    };
+    debug!("Got {} as the patch file", patch_file.display());
+
+    let mut diff = DatabaseDiff::new(
+        open_password_file(old_password_hash_path)?,
+        open_password_file(new_password_hash_path)?,
+    )?;
+    let hash_type = diff.get_base().get_hash_type();
+
+    let write_error = |error| {
+        PwnedError::Io(
+            format!("Could not write the patch {}", patch_file.display()),
+            error,
+        )
+    };
+    let mut writer = match File::create(patch_file) {
+        Ok(file) => PatchWriter::new(BufWriter::new(file), hash_type).map_err(write_error)?,
+        Err(error) => {
+            return Err(PwnedError::Io(
+                format!("Could not create the file {}", patch_file.display()),
+                error,
+            ))
+        }
+    };
+
+    // the progress is shown for the new password file, which is usually the larger one
+    let progress_bar = create_progress_bar(diff.get_target().get_file_size());
+    let mut written_operations: u64 = 0;
+    while let Some(operation) = diff.next() {
+        if let Err(error) = writer.write_operation(&operation) {
+            progress_bar.abandon();
+            return Err(write_error(error));
+        }
+        written_operations += 1;
+        if written_operations.is_multiple_of(PROGRESS_INTERVAL) {
+            progress_bar.set_position(diff.get_target().get_consumed_bytes());
+        }
+    }
+
+    // if one of the parsers stopped early, the patch would remove or add hashes by mistake
+    if let Some(error) = diff.take_error() {
+        progress_bar.abandon();
+        return Err(error);
+    }
+    progress_bar.finish_with_message("compared");
+
+    // the checksums identify the password file the patch belongs to and the one it produces
+    let target_name = Path::new(new_password_hash_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let target = SourceInformation::new(
+        &target_name,
+        diff.get_target().get_consumed_bytes(),
+        &diff.get_target().get_checksum().unwrap_or_default(),
+    );
+    let base_checksum = diff.get_base().get_checksum().unwrap_or_default();
+    let summary = writer.finish(&base_checksum, target).map_err(write_error)?;
+
+    info!(
+        "Wrote {} added, {} removed and {} changed password hashes into {}",
+        summary.get_added(),
+        summary.get_removed(),
+        summary.get_changed(),
+        patch_file.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use crate::error::PwnedError;
+use crate::filter::{BloomFilter, FilterLookupResult};
+use crate::output::LookupRecord;
+use crate::subcommands::{
+    create_record_writer, get_input_identifier, read_password_digest, write_lookup_record,
+};
+use clap::ArgMatches;
+use log::debug;
+use std::path::Path;
+
+/// The name of the backend which is reported in the machine-readable output.
+const FILTER_BACKEND_NAME: &str = "bloom-filter";
+
+pub fn run_subcommand(matches: &ArgMatches) -> Result<(), PwnedError> {
+    // get the path to the password filter
+    let filter_path = match matches.value_of("filter-file") {
+        Some(path) => Path::new(path),
+        None => return Err(PwnedError::InvalidArgument("It seems that the path to the password filter was not provided, please see the help for usage instructions.".to_string())),
+    };
+
+    // open the filter, it knows the type of the hashes it contains
+    let filter = match BloomFilter::from_file(filter_path) {
+        Ok(filter) => filter,
+        Err(error) => {
+            return Err(PwnedError::Database(
+                "Could not open the password filter".to_string(),
+                error,
+            ))
+        }
+    };
+    debug!(
+        "The filter contains {} {} hashes which occurred at least {} times",
+        filter.get_entry_count(),
+        filter.get_hash_type(),
+        filter.get_minimal_occurrences()
+    );
+
+    // get the hashed password (either from the user input or the supplied hash)
+    let password_digest = read_password_digest(matches, filter.get_hash_type())?;
+
+    // report the result of the lookup, the filter does not know how often a password was found
+    let lookup_result = filter.lookup(&password_digest)?;
+    let message = match lookup_result {
+        FilterLookupResult::ProbablyPwned => format!(
+            "The password was probably found in password breaches (with a false positive rate of {}). Please change the password!",
+            filter.get_false_positive_rate()
+        ),
+        FilterLookupResult::DefinitelyNotPwned if filter.get_minimal_occurrences() > 1 => format!(
+            "The password is not one of the passwords which were found at least {} times in password breaches.",
+            filter.get_minimal_occurrences()
+        ),
+        FilterLookupResult::DefinitelyNotPwned => {
+            "Perfect! Could not find the password in any of the available breaches. Go on!"
+                .to_string()
+        }
+    };
+    let record = LookupRecord::new(
+        get_input_identifier(matches),
+        &password_digest,
+        matches.is_present("full-hash"),
+        lookup_result == FilterLookupResult::ProbablyPwned,
+        None,
+        FILTER_BACKEND_NAME,
+    );
+    write_lookup_record(&mut create_record_writer(matches)?, &record, &message)
+}
@@ -1,57 +1,52 @@
-use crate::haveibeenpwned::DatabaseReader;
-use crate::PasswordHashEntry;
+use crate::database::LookupOutcome;
+use crate::error::PwnedError;
+use crate::subcommands::{
+    get_hash_type, lookup_password, open_database, read_password_digest, verify_optimized_database,
+};
 use clap::ArgMatches;
-use log::{debug, error, info};
-use rpassword::read_password_from_tty;
+use log::debug;
 use std::path::Path;
-use std::process::exit;
 
-pub fn run_subcommand(matches: &ArgMatches) {
+/// Look up the password and return if it was found. If the lookup failed, an error is returned, so
+/// a broken database is never reported as not containing the password.
+pub fn run_subcommand(matches: &ArgMatches) -> Result<LookupOutcome, PwnedError> {
     // get the path to the optimized password database
     let password_hash_folder = match matches.value_of("optimized-db-folder") {
-        Some(path) => path,
-        None => {
-            error!("It seems that the path to the folder for the optimized password hash files was not provided, please see the help for usage instructions.");
-            exit(-1);
-        }
+        Some(path) => Path::new(path),
+        None => return Err(PwnedError::InvalidArgument("It seems that the path to the folder for the optimized password hash files was not provided, please see the help for usage instructions.".to_string())),
     };
 
-    // try to read the password from the user
-    let read_password =
-        match read_password_from_tty(Some("Enter the password you are looking for: ")) {
-            Ok(password) => password,
-            Err(_) => {
-                error!("Could not read the password from the user.");
-                return;
-            }
-        };
+    // be sure that no file of the database is missing
+    let manifest = verify_optimized_database(password_hash_folder)?;
 
-    // get the SHA-1 hashed password
-    let password_entry = PasswordHashEntry::from_password(&read_password);
-    debug!(
-        "Looking up password in {}.txt...",
-        password_entry.get_prefix()
-    );
+    // determine the type of the hashes stored in the optimized database
+    let hash_type = get_hash_type(matches, password_hash_folder)?;
+
+    // get the hashed password (either from the user input or the supplied hash)
+    let password_digest = read_password_digest(matches, hash_type)?;
 
-    // try to get the reader for the database
-    let file_path =
-        Path::new(password_hash_folder).join(format!("{}.txt", password_entry.get_prefix()));
-    let read_database = match DatabaseReader::from_file(&file_path) {
-        Ok(parser) => parser,
+    // open the database with the backend selected by the user
+    let database = match open_database(matches, password_hash_folder) {
+        Ok(database) => database,
         Err(error) => {
-            error!("Could not open the database. The error was: {}", error);
-            return;
+            return Err(PwnedError::Database(
+                "Could not open the database".to_string(),
+                error,
+            ))
         }
     };
 
-    //
-    match read_database.get_password_count(password_entry.get_hash()) {
-        Some(count) => info!(
+    // try to lookup the password
+    debug!(
+        "Looking up password in {}...",
+        manifest
+            .get_file_path(password_hash_folder, &password_digest)
+            .display()
+    );
+    lookup_password(matches, database.as_ref(), &password_digest, |count| {
+        format!(
             "The password was found {} times in password breaches. Please change the password!",
             count
-        ),
-        None => {
-            info!("Perfect! Could not find the password in any of the available breaches. Go on!")
-        }
-    }
+        )
+    })
 }
@@ -0,0 +1,172 @@
+use crate::error::PwnedError;
+use crate::haveibeenpwned::DatabaseIterator;
+use crate::merge::{is_valid_source_id, CountMode, SourceMerge};
+use crate::subcommands::create_progress_bar;
+use clap::ArgMatches;
+use log::{debug, error, info};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// The number of merged entries between two updates of the progress bar.
+const PROGRESS_INTERVAL: u64 = 4096;
+
+/// A password file which should be merged together with the ID it is tagged with.
+struct Source {
+    id: String,
+    path: String,
+}
+
+/// Parse a source argument, which is either `ID=PATH` or just the path. Without an ID, the name
+/// of the file up to the first dot is used.
+fn parse_source(argument: &str) -> Result<Source, PwnedError> {
+    let (id, path) = match argument.split_once('=') {
+        Some((id, path)) if is_valid_source_id(id) => (id.to_string(), path.to_string()),
+        _ => {
+            let file_name = Path::new(argument)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let id = file_name.split('.').next().unwrap_or_default().to_string();
+            (id, argument.to_string())
+        }
+    };
+    if !is_valid_source_id(&id) {
+        return Err(PwnedError::InvalidArgument(format!(
+            "The source {} needs an ID which starts with a letter and just contains letters, digits, - and _. Please supply it as ID=PATH.",
+            argument
+        )));
+    }
+    Ok(Source { id, path })
+}
+
+pub fn run_subcommand(matches: &ArgMatches) -> Result<(), PwnedError> {
+    // get the path of the merged password file which should be written
+    let output_file = match matches.value_of("output-file") {
+        Some(path) => Path::new(path),
+        None => return Err(PwnedError::InvalidArgument("It seems that the path where the merged password file should be stored was not provided, please see the help for usage instructions.".to_string())),
+    };
+    debug!("Got {} as the output file", output_file.display());
+
+    // get the password files which should be merged and their IDs
+    let sources = match matches.values_of("sources") {
+        Some(arguments) => arguments
+            .map(parse_source)
+            .collect::<Result<Vec<Source>, PwnedError>>()?,
+        None => return Err(PwnedError::InvalidArgument("It seems that the password files which should be merged were not provided, please see the help for usage instructions.".to_string())),
+    };
+    for (index, source) in sources.iter().enumerate() {
+        if sources[..index]
+            .iter()
+            .any(|other| other.id.eq_ignore_ascii_case(&source.id))
+        {
+            return Err(PwnedError::InvalidArgument(format!(
+                "The ID {} is used for more than one source.",
+                source.id
+            )));
+        }
+        if sources[..index]
+            .iter()
+            .any(|other| other.path == source.path)
+        {
+            return Err(PwnedError::InvalidArgument(format!(
+                "The password file {} was supplied more than once.",
+                source.path
+            )));
+        }
+    }
+
+    // get how the counts of the sources are combined
+    let count_mode = CountMode::from_str(matches.value_of("counts").unwrap_or("sum"))
+        .map_err(PwnedError::InvalidArgument)?;
+    let source_ids: Vec<String> = sources.iter().map(|source| source.id.clone()).collect();
+    let tag_sources = matches.is_present("tag-sources");
+    debug!(
+        "Merging the sources {} with the count mode {}",
+        source_ids.join(", "),
+        count_mode
+    );
+
+    // get an instance of the password parser for every source
+    let mut parsers = Vec::with_capacity(sources.len());
+    for source in &sources {
+        match DatabaseIterator::from_file(&source.path) {
+            Ok(parser) => parsers.push(parser),
+            Err(error) => {
+                return Err(PwnedError::Database(
+                    format!(
+                        "Could not get an instance of the parser for {}",
+                        source.path
+                    ),
+                    error,
+                ))
+            }
+        }
+    }
+    let mut merge = SourceMerge::new(parsers)?;
+
+    let mut writer = match File::create(output_file) {
+        Ok(file) => BufWriter::new(file),
+        Err(error) => {
+            return Err(PwnedError::Io(
+                format!("Could not create the file {}", output_file.display()),
+                error,
+            ))
+        }
+    };
+
+    // write every hash of all sources just once
+    let progress_bar = create_progress_bar(merge.get_file_size());
+    let tags = if tag_sources {
+        Some(source_ids.as_slice())
+    } else {
+        None
+    };
+    let mut written_entries: u64 = 0;
+    while let Some(merged_entry) = merge.next() {
+        let line = merged_entry.get_line_to_write(count_mode, tags);
+        if let Err(error) = writer.write_all(line.as_bytes()) {
+            progress_bar.abandon();
+            return Err(PwnedError::Io(
+                format!("Could not write the file {}", output_file.display()),
+                error,
+            ));
+        }
+        written_entries += 1;
+        if written_entries.is_multiple_of(PROGRESS_INTERVAL) {
+            progress_bar.set_position(merge.get_consumed_bytes());
+        }
+    }
+
+    // if one of the parsers stopped early, the merged file is incomplete
+    if let Some(error) = merge.take_error() {
+        progress_bar.abandon();
+        if let Some(index) = merge.get_failed_source() {
+            error!("Could not merge the password file {}", sources[index].path);
+        }
+        return Err(error);
+    }
+    if let Err(error) = writer.flush() {
+        progress_bar.abandon();
+        return Err(PwnedError::Io(
+            format!("Could not write the file {}", output_file.display()),
+            error,
+        ));
+    }
+    progress_bar.finish_with_message("merged");
+
+    if count_mode == CountMode::Columns {
+        info!(
+            "The columns after the total count belong to the sources {}",
+            source_ids.join(", ")
+        );
+    }
+    info!(
+        "Merged {} password files into {} password hashes in {}",
+        sources.len(),
+        written_entries,
+        output_file.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,458 @@
+use crate::error::PwnedError;
+use crate::manifest::{
+    DatabaseManifest, SourceInformation, DEFAULT_PREFIX_LENGTH, MAXIMAL_PREFIX_LENGTH,
+    MINIMAL_PREFIX_LENGTH,
+};
+use crate::range::{
+    get_range_prefix, parse_range_response, RangeCache, RangeClient, RangeError, RangeResponse,
+    DEFAULT_BASE_URL, NUMBER_OF_PREFIXES,
+};
+use crate::subcommands::create_counting_progress_bar;
+use crate::subcommands::optimize::PrefixFileWriter;
+use crate::{HashType, PasswordHashEntry};
+use chrono::Utc;
+use clap::ArgMatches;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir_all, remove_file, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The file in the cache folder which records when the current download was started.
+const SESSION_FILE_NAME: &str = "session.json";
+
+/// The download which is in progress. Responses which were stored after it was started are not
+/// requested again if the download is resumed.
+#[derive(Serialize, Deserialize)]
+struct MirrorSession {
+    base_url: String,
+    hash_type: HashType,
+    /// The seconds since the Unix epoch at which the download was started.
+    started_at: u64,
+}
+
+/// Read the session of an interrupted download from the cache folder or start a new one. A
+/// download from another server or for another hash type is never resumed.
+fn start_session(cache: &RangeCache, client: &RangeClient) -> Result<SystemTime, PwnedError> {
+    let session_path = cache.get_folder().join(SESSION_FILE_NAME);
+    if let Ok(session_file) = File::open(&session_path) {
+        if let Ok(session) =
+            serde_json::from_reader::<_, MirrorSession>(BufReader::new(session_file))
+        {
+            if session.base_url == client.get_base_url()
+                && session.hash_type == client.get_hash_type()
+            {
+                info!("Resuming the download which was started before");
+                return Ok(UNIX_EPOCH + Duration::from_secs(session.started_at));
+            }
+        }
+    }
+
+    let started_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let session = MirrorSession {
+        base_url: client.get_base_url().to_string(),
+        hash_type: client.get_hash_type(),
+        started_at,
+    };
+    let write_result = File::create(&session_path)
+        .and_then(|session_file| Ok(serde_json::to_writer(session_file, &session)?));
+    if let Err(error) = write_result {
+        return Err(PwnedError::Io(
+            format!("Could not write the file {}", session_path.display()),
+            error,
+        ));
+    }
+    Ok(UNIX_EPOCH + Duration::from_secs(started_at))
+}
+
+/// The counters of the download, which are shared by all threads.
+#[derive(Default)]
+struct DownloadStatistics {
+    downloaded: AtomicU64,
+    revalidated: AtomicU64,
+    resumed: AtomicU64,
+}
+
+/// Fetch the suffixes of a single prefix and store them in the cache. A response which was stored
+/// during the current download is kept, a cached one from an earlier download is revalidated
+/// with its ETag.
+fn mirror_prefix(
+    client: &RangeClient,
+    cache: &RangeCache,
+    prefix: &str,
+    session_start: SystemTime,
+    statistics: &DownloadStatistics,
+) -> Result<(), RangeError> {
+    if cache
+        .get_modification_time(prefix)
+        .is_some_and(|modified| modified >= session_start)
+    {
+        statistics.resumed.fetch_add(1, Ordering::Relaxed);
+        return Ok(());
+    }
+
+    let etag = cache.read_etag(prefix)?;
+    match client.fetch(prefix, etag.as_deref())? {
+        RangeResponse::Modified { body, etag } => {
+            // an invalid response must not end up in the cache
+            parse_range_response(prefix, &body, client.get_hash_type())?;
+            cache.write(prefix, etag.as_deref(), &body)?;
+            statistics.downloaded.fetch_add(1, Ordering::Relaxed);
+        }
+        RangeResponse::NotModified => {
+            cache.touch(prefix)?;
+            statistics.revalidated.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    Ok(())
+}
+
+/// Fetch all prefixes with the supplied number of threads. The first error stops all threads.
+fn download_all_prefixes(
+    client: &RangeClient,
+    cache: &RangeCache,
+    session_start: SystemTime,
+    number_of_threads: usize,
+) -> Result<DownloadStatistics, PwnedError> {
+    let statistics = DownloadStatistics::default();
+    let next_prefix = AtomicU32::new(0);
+    let failed = AtomicBool::new(false);
+    let first_error = Mutex::new(None);
+    let progress_bar = create_counting_progress_bar(NUMBER_OF_PREFIXES as u64, "prefixes");
+
+    thread::scope(|scope| {
+        for _ in 0..number_of_threads {
+            scope.spawn(|| loop {
+                let index = next_prefix.fetch_add(1, Ordering::Relaxed);
+                if index >= NUMBER_OF_PREFIXES || failed.load(Ordering::Relaxed) {
+                    break;
+                }
+                let prefix = get_range_prefix(index);
+                if let Err(error) =
+                    mirror_prefix(client, cache, &prefix, session_start, &statistics)
+                {
+                    failed.store(true, Ordering::Relaxed);
+                    if let Ok(mut first_error) = first_error.lock() {
+                        first_error.get_or_insert(error);
+                    }
+                    break;
+                }
+                progress_bar.inc(1);
+            });
+        }
+    });
+
+    let first_error = match first_error.into_inner() {
+        Ok(first_error) => first_error,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    if let Some(error) = first_error {
+        progress_bar.abandon();
+        return Err(PwnedError::Range(error));
+    }
+    progress_bar.finish_with_message("downloaded");
+    Ok(statistics)
+}
+
+/// Read the cached responses of all prefixes in their order and pass the entries (ordered by
+/// hash) together with their lines to the sink. The returned information describes the password
+/// file which consists of all lines.
+fn rebuild_corpus<S>(
+    cache: &RangeCache,
+    client: &RangeClient,
+    source_name: &str,
+    mut sink: S,
+) -> Result<(u64, SourceInformation), PwnedError>
+where
+    S: FnMut(&PasswordHashEntry, &str) -> Result<(), PwnedError>,
+{
+    let progress_bar = create_counting_progress_bar(NUMBER_OF_PREFIXES as u64, "prefixes");
+    let mut hasher = Sha256::new();
+    let mut written_entries = 0;
+    let mut written_bytes = 0;
+    for index in 0..NUMBER_OF_PREFIXES {
+        let prefix = get_range_prefix(index);
+        let rebuild_error = |error: PwnedError| {
+            progress_bar.abandon();
+            error
+        };
+        let body = cache.read_body(&prefix).map_err(|error| {
+            rebuild_error(PwnedError::Io(
+                format!(
+                    "Could not read the cached response for the prefix {}",
+                    prefix
+                ),
+                error,
+            ))
+        })?;
+        let entries = parse_range_response(&prefix, &body, client.get_hash_type())
+            .map_err(|error| rebuild_error(PwnedError::Range(error)))?;
+        for entry in &entries {
+            let line = entry.get_line_to_write();
+            sink(entry, &line).map_err(rebuild_error)?;
+            hasher.input(line.as_bytes());
+            written_entries += 1;
+            written_bytes += line.len() as u64;
+        }
+        progress_bar.inc(1);
+    }
+    progress_bar.finish_with_message("rebuilt");
+
+    let source = SourceInformation::new(source_name, written_bytes, &hasher.result_str());
+    Ok((written_entries, source))
+}
+
+/// Write all cached entries into a single password file ordered by hash.
+fn write_password_file(
+    cache: &RangeCache,
+    client: &RangeClient,
+    output_file: &Path,
+) -> Result<u64, PwnedError> {
+    let write_error = |error| {
+        PwnedError::Io(
+            format!("Could not write the file {}", output_file.display()),
+            error,
+        )
+    };
+    let mut writer = File::create(output_file)
+        .map(BufWriter::new)
+        .map_err(write_error)?;
+    let source_name = output_file
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let (written_entries, _) = rebuild_corpus(cache, client, &source_name, |_, line| {
+        writer.write_all(line.as_bytes()).map_err(write_error)
+    })?;
+    writer.flush().map_err(write_error)?;
+    Ok(written_entries)
+}
+
+/// Write all cached entries into the folder of an optimized database, which is split by prefixes
+/// of the supplied length.
+fn write_optimized_database(
+    cache: &RangeCache,
+    client: &RangeClient,
+    output_folder: &Path,
+    prefix_length: usize,
+) -> Result<u64, PwnedError> {
+    let write_error =
+        |error| PwnedError::Io("Could not write the optimized database".to_string(), error);
+    if let Err(error) = create_dir_all(output_folder) {
+        return Err(write_error(error));
+    }
+    let mut manifest = DatabaseManifest::new(prefix_length);
+    manifest.set_hash_type(client.get_hash_type());
+    manifest.set_created_at(&Utc::now().to_rfc3339());
+
+    let mut current_output_file: Option<PrefixFileWriter> = None;
+    let (written_entries, source) =
+        rebuild_corpus(cache, client, client.get_base_url(), |entry, line| {
+            let current_prefix = match entry.get_dynamic_prefix(prefix_length) {
+                Some(prefix) => prefix.to_uppercase(),
+                None => {
+                    return Err(PwnedError::InvalidArgument(
+                        "The prefix length is longer than the hashes of the range API.".to_string(),
+                    ))
+                }
+            };
+            let prefix_changed = match current_output_file {
+                Some(ref output_file) => output_file.get_prefix() != current_prefix,
+                None => true,
+            };
+            if prefix_changed {
+                if let Some(finished_file) = current_output_file.take() {
+                    finished_file.finish(&mut manifest).map_err(write_error)?;
+                }
+                current_output_file = Some(
+                    PrefixFileWriter::create(output_folder, current_prefix).map_err(write_error)?,
+                );
+            }
+            match current_output_file {
+                Some(ref mut output_file) => {
+                    output_file.write_line(line.as_bytes()).map_err(write_error)
+                }
+                None => Ok(()),
+            }
+        })?;
+    if let Some(finished_file) = current_output_file.take() {
+        finished_file.finish(&mut manifest).map_err(write_error)?;
+    }
+
+    // the checksum is the one of the password file which would be written without --optimized
+    manifest.set_source(source);
+    if let Err(error) = manifest.write_to_folder(output_folder) {
+        return Err(PwnedError::Io(
+            "Could not write the manifest of the optimized database".to_string(),
+            error,
+        ));
+    }
+    Ok(written_entries)
+}
+
+/// Parse the value of a numeric argument, which has to be positive unless zero is allowed.
+fn get_number_argument(
+    matches: &ArgMatches,
+    name: &str,
+    default_value: &str,
+    allow_zero: bool,
+) -> Result<u32, PwnedError> {
+    match matches
+        .value_of(name)
+        .unwrap_or(default_value)
+        .parse::<u32>()
+    {
+        Ok(number) if number > 0 || allow_zero => Ok(number),
+        _ => Err(PwnedError::InvalidArgument(format!(
+            "The value of --{} has to be a positive number.",
+            name
+        ))),
+    }
+}
+
+pub fn run_subcommand(matches: &ArgMatches) -> Result<(), PwnedError> {
+    // get the path of the password file (or the folder of the optimized database) to write
+    let output_path = match matches.value_of("output") {
+        Some(path) => Path::new(path),
+        None => return Err(PwnedError::InvalidArgument("It seems that the path where the mirrored password hashes should be stored was not provided, please see the help for usage instructions.".to_string())),
+    };
+    let optimized = matches.is_present("optimized");
+    debug!("Got {} as the output path", output_path.display());
+
+    // get the number of characters of the prefix which is used for splitting the hashes into files
+    let prefix_length = match matches.value_of("prefix-length") {
+        Some(value) => match value.parse::<usize>() {
+            Ok(length) if (MINIMAL_PREFIX_LENGTH..=MAXIMAL_PREFIX_LENGTH).contains(&length) => {
+                length
+            }
+            _ => {
+                return Err(PwnedError::InvalidArgument(format!(
+                    "The prefix length has to be a number between {} and {}.",
+                    MINIMAL_PREFIX_LENGTH, MAXIMAL_PREFIX_LENGTH
+                )))
+            }
+        },
+        None => DEFAULT_PREFIX_LENGTH,
+    };
+
+    // configure the client for the range API
+    let base_url = matches.value_of("base-url").unwrap_or(DEFAULT_BASE_URL);
+    let hash_type = HashType::from_str(matches.value_of("hash-type").unwrap_or("sha1"))
+        .map_err(|error| PwnedError::InvalidArgument(error.to_string()))?;
+    let number_of_threads = get_number_argument(matches, "threads", "8", false)? as usize;
+    let mut client = RangeClient::new(base_url, hash_type);
+    client.set_retries(get_number_argument(matches, "retries", "5", true)?);
+    debug!(
+        "Mirroring the {} hashes of {} with {} threads",
+        hash_type,
+        client.get_base_url(),
+        number_of_threads
+    );
+
+    // the responses are kept next to the output by default, so the next mirror can revalidate them
+    let cache_folder = match matches.value_of("cache-dir") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let mut cache_name = output_path.as_os_str().to_owned();
+            cache_name.push(".mirror-cache");
+            PathBuf::from(cache_name)
+        }
+    };
+    let cache = match RangeCache::new(&cache_folder) {
+        Ok(cache) => cache,
+        Err(error) => {
+            return Err(PwnedError::Io(
+                format!(
+                    "Could not create the cache folder {}",
+                    cache_folder.display()
+                ),
+                error,
+            ))
+        }
+    };
+    debug!("Caching the responses in {}", cache_folder.display());
+
+    // fetch all prefixes which were not stored during the current download yet
+    let session_start = start_session(&cache, &client)?;
+    let statistics = download_all_prefixes(&client, &cache, session_start, number_of_threads)?;
+    info!(
+        "Downloaded {} prefixes, {} were unchanged and {} were already fetched before resuming",
+        statistics.downloaded.load(Ordering::Relaxed),
+        statistics.revalidated.load(Ordering::Relaxed),
+        statistics.resumed.load(Ordering::Relaxed)
+    );
+
+    // rebuild the password file (or the optimized database) from the cached responses
+    let written_entries = if optimized {
+        write_optimized_database(&cache, &client, output_path, prefix_length)?
+    } else {
+        write_password_file(&cache, &client, output_path)?
+    };
+
+    // the download is complete, so the next mirror starts a new one
+    match remove_file(cache.get_folder().join(SESSION_FILE_NAME)) {
+        Err(ref error) if error.kind() != ErrorKind::NotFound => {
+            warn!(
+                "Could not remove the session of the finished download: {}",
+                error
+            )
+        }
+        _ => {}
+    }
+    info!(
+        "Mirrored {} password hashes into {}",
+        written_entries,
+        output_path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::create_temp_path;
+
+    #[test]
+    fn an_interrupted_download_is_resumed() {
+        let cache_folder = create_temp_path("mirror-session-test");
+        let _ = std::fs::remove_dir_all(&cache_folder);
+        let cache = RangeCache::new(&cache_folder).unwrap();
+        let client = RangeClient::new("http://127.0.0.1:1", HashType::Sha1);
+
+        // a response of the current download is not requested again (the server is not reachable)
+        let session_start = start_session(&cache, &client).unwrap();
+        cache
+            .write("5BAA6", None, "1E4C9B93F3F0682250B6CF8331B7EE68FD8:3\r\n")
+            .unwrap();
+        let statistics = DownloadStatistics::default();
+        mirror_prefix(&client, &cache, "5BAA6", session_start, &statistics).unwrap();
+        assert_eq!(1, statistics.resumed.load(Ordering::Relaxed));
+        assert_eq!(session_start, start_session(&cache, &client).unwrap());
+
+        // a download from another server starts a new session
+        let other_client = RangeClient::new("http://127.0.0.1:2", HashType::Sha1);
+        assert_eq!(
+            true,
+            start_session(&cache, &other_client).unwrap() >= session_start
+        );
+        let mut saved_session = String::new();
+        std::io::Read::read_to_string(
+            &mut File::open(cache_folder.join(SESSION_FILE_NAME)).unwrap(),
+            &mut saved_session,
+        )
+        .unwrap();
+        assert_eq!(true, saved_session.contains("127.0.0.1:2"));
+
+        let _ = std::fs::remove_dir_all(cache_folder);
+    }
+}
@@ -1,3 +1,414 @@
+use crate::compiled::{is_compiled_database, CompiledDatabase};
+use crate::database::{LookupOutcome, PasswordDatabase};
+use crate::error::PwnedError;
+use crate::haveibeenpwned::{CreateInstanceError, DatabaseIterator};
+use crate::manifest::DatabaseManifest;
+use crate::mapped::{MappedDatabase, MappedPrefixDatabase};
+use crate::optimized::OptimizedDatabase;
+use crate::ordered::DivideAndConquerLookup;
+use crate::output::{LookupRecord, OutputFormat, RecordWriter};
+use crate::range::{RangeCache, RangeClient, DEFAULT_BASE_URL};
+use crate::remote::RemoteDatabase;
+use crate::secret::read_secret_from_tty;
+use crate::{HashDigest, HashType};
+use clap::ArgMatches;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{debug, error, warn};
+use std::fs::File;
+use std::io::{stdout, Stdout, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+pub mod adaudit;
+pub mod applypatch;
+pub mod audit;
+pub mod batchlookup;
+pub mod buildfilter;
+pub mod compile;
+pub mod daemon;
+pub mod diff;
+pub mod filterlookup;
 pub mod lookup;
+pub mod merge;
+pub mod mirror;
 pub mod optimize;
 pub mod quicklookup;
+pub mod serve;
+pub mod sort;
+pub mod streamlookup;
+pub mod verify;
+
+/// Get the type of the hashes stored in the password database. If the type was not selected with
+/// `--hash-type`, it is detected from the first (decompressed) line of the database file (or the
+/// header of a compiled database). For an optimized database, the type is taken from its manifest or, if the
+/// manifest does not contain it, the first non-empty file in the folder is used.
+pub(crate) fn get_hash_type(
+    matches: &ArgMatches,
+    database_path: &Path,
+) -> Result<HashType, PwnedError> {
+    if let Some(selected_type) = matches.value_of("hash-type") {
+        return HashType::from_str(selected_type)
+            .map_err(|error| PwnedError::InvalidArgument(error.to_string()));
+    }
+
+    let mut database_file = database_path.to_path_buf();
+    if database_path.is_dir() {
+        if let Some(hash_type) = DatabaseManifest::from_folder(database_path)
+            .ok()
+            .and_then(|manifest| manifest.get_hash_type())
+        {
+            return Ok(hash_type);
+        }
+        let mut database_files: Vec<_> = match database_path.read_dir() {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+                .filter(|path| path.metadata().is_ok_and(|data| data.len() > 0))
+                .collect(),
+            Err(error) => {
+                return Err(PwnedError::Io(
+                    "Could not read the content of the database folder".to_string(),
+                    error,
+                ))
+            }
+        };
+        database_files.sort();
+        database_file = match database_files.into_iter().next() {
+            Some(path) => path,
+            None => {
+                return Err(PwnedError::InvalidArgument(
+                    "Could not find any password hash file in the database folder.".to_string(),
+                ))
+            }
+        };
+    }
+
+    if is_compiled_database(&database_file) {
+        return match CompiledDatabase::from_file(&database_file) {
+            Ok(database) => Ok(database.get_hash_type()),
+            Err(error) => Err(PwnedError::Database(
+                "Could not open the compiled database".to_string(),
+                error,
+            )),
+        };
+    }
+
+    // the password file may be compressed, so it is read by the parser which decompresses it (a
+    // path is never treated as the standard input here, since it may supply the passwords)
+    let parser = File::open(&database_file)
+        .map_err(CreateInstanceError::Io)
+        .and_then(|file_handle| {
+            let file_size = file_handle.metadata().ok().map(|data| data.len());
+            DatabaseIterator::from_reader(Box::new(file_handle), file_size)
+        });
+    match parser {
+        Ok(parser) => {
+            debug!(
+                "Detected {} hashes in the password database",
+                parser.get_hash_type()
+            );
+            Ok(parser.get_hash_type())
+        }
+        Err(error) => Err(PwnedError::Database(
+            "Could not detect the type of the password hashes".to_string(),
+            error,
+        )),
+    }
+}
+
+/// Create the progress bar which shows how many bytes of the password file were read. If the size
+/// of the password file is not known (e.g. it is read from the standard input), a spinner is shown.
+pub(crate) fn create_progress_bar(total_bytes: Option<u64>) -> ProgressBar {
+    let progress_bar = match total_bytes {
+        Some(size) => {
+            let progress_bar = ProgressBar::new(size);
+            progress_bar.set_style(ProgressStyle::default_bar()
+                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta_precise})")
+                .progress_chars("#>-"));
+            progress_bar
+        }
+        None => {
+            let progress_bar = ProgressBar::new_spinner();
+            progress_bar.set_style(
+                ProgressStyle::default_spinner()
+                    .template("{spinner:.green} [{elapsed_precise}] {bytes} read"),
+            );
+            progress_bar
+        }
+    };
+    progress_bar.set_draw_delta(1024 * 1024 * 8);
+    progress_bar
+}
+
+/// Create the progress bar which shows how many items (like prefixes or operations of a patch) of
+/// the supplied total were processed.
+pub(crate) fn create_counting_progress_bar(total_items: u64, unit: &str) -> ProgressBar {
+    let progress_bar = ProgressBar::new(total_items);
+    progress_bar.set_style(
+        ProgressStyle::default_bar()
+            .template(&format!(
+                "{{spinner:.green}} [{{elapsed_precise}}] [{{bar:40.cyan/blue}}] {{pos}}/{{len}} {} ({{eta_precise}})",
+                unit
+            ))
+            .progress_chars("#>-"),
+    );
+    progress_bar.set_draw_delta(total_items / 1000 + 1);
+    progress_bar
+}
+
+/// Get the hash of the password which should be looked up. If a pre-computed hash was supplied
+/// with `--hash`, it is used directly. Otherwise the password is read from the terminal into a
+/// [SecretString](../secret/struct.SecretString.html).
+pub(crate) fn read_password_digest(
+    matches: &ArgMatches,
+    hash_type: HashType,
+) -> Result<HashDigest, PwnedError> {
+    if let Some(hash) = matches.value_of("hash") {
+        return HashDigest::from_hex(hash, hash_type).map_err(|error| {
+            PwnedError::InvalidArgument(format!(
+                "The supplied hash could not be used. The error was: {}",
+                error
+            ))
+        });
+    }
+
+    // the password is wiped as soon as it was hashed
+    match read_secret_from_tty("Enter the password you are looking for: ") {
+        Ok(password) => Ok(HashDigest::from_secret(&password, hash_type)),
+        Err(error) => Err(PwnedError::Io(
+            "Could not read the password from the user".to_string(),
+            error,
+        )),
+    }
+}
+
+/// Create the writer for the results of the lookups in the format selected with `--output`. The
+/// results are written to stdout, all log messages go to stderr.
+pub(crate) fn create_record_writer(
+    matches: &ArgMatches,
+) -> Result<RecordWriter<Stdout>, PwnedError> {
+    let format = OutputFormat::from_str(matches.value_of("output").unwrap_or("plain"))
+        .map_err(PwnedError::InvalidArgument)?;
+    Ok(RecordWriter::new(stdout(), format))
+}
+
+/// Write the result of a lookup with the supplied writer.
+pub(crate) fn write_lookup_record<W: Write>(
+    writer: &mut RecordWriter<W>,
+    record: &LookupRecord,
+    plain_message: &str,
+) -> Result<(), PwnedError> {
+    writer
+        .write_record(record, plain_message)
+        .and_then(|_| writer.flush())
+        .map_err(|error| {
+            PwnedError::Io(
+                "Could not write the result of the lookup".to_string(),
+                error,
+            )
+        })
+}
+
+/// Look up the supplied password hash in the database and report how often it was found. The
+/// message for a found password is created by the calling subcommand.
+pub(crate) fn lookup_password<D: PasswordDatabase + ?Sized>(
+    matches: &ArgMatches,
+    database: &D,
+    digest: &HashDigest,
+    found_message: fn(u64) -> String,
+) -> Result<LookupOutcome, PwnedError> {
+    let outcome = database.lookup(digest)?;
+    let mut sources = Vec::new();
+    let (occurrences, mut message) = match outcome {
+        LookupOutcome::Found(count) => {
+            sources = database.sources(digest)?;
+            (count, found_message(count))
+        }
+        LookupOutcome::NotFound => (
+            0,
+            "Perfect! Could not find the password in any of the available breaches. Go on!"
+                .to_string(),
+        ),
+    };
+    if !sources.is_empty() {
+        message.push_str(&format!(" It is part of: {}", sources.join(", ")));
+    }
+
+    // the record never contains the password, just where it came from
+    let record = LookupRecord::new(
+        get_input_identifier(matches),
+        digest,
+        matches.is_present("full-hash"),
+        occurrences > 0,
+        Some(occurrences),
+        database.get_backend_name(),
+    )
+    .with_sources(sources);
+    write_lookup_record(&mut create_record_writer(matches)?, &record, &message)?;
+    Ok(outcome)
+}
+
+/// Get the identifier of a single password which was looked up. The password itself must never
+/// be part of the output, so it just tells where the password came from.
+pub(crate) fn get_input_identifier(matches: &ArgMatches) -> &'static str {
+    if matches.is_present("hash") {
+        "argument"
+    } else {
+        "terminal"
+    }
+}
+
+/// Read the manifest of the optimized database in the supplied folder and be sure that no file of
+/// the database is missing. The size of a file is checked when the lookup opens it.
+pub(crate) fn verify_optimized_database(
+    password_hash_folder: &Path,
+) -> Result<DatabaseManifest, PwnedError> {
+    // a missing folder would look like a database without a file list, so it is reported first
+    match password_hash_folder.metadata() {
+        Ok(data) if !data.is_dir() => {
+            return Err(PwnedError::InvalidArgument(format!(
+                "{} is not the folder of an optimized database.",
+                password_hash_folder.display()
+            )))
+        }
+        Ok(_) => {}
+        Err(error) => {
+            return Err(PwnedError::Io(
+                format!(
+                    "Could not open the folder {} of the optimized database",
+                    password_hash_folder.display()
+                ),
+                error,
+            ))
+        }
+    }
+
+    // read the manifest to know how the optimized database was split into files
+    let manifest = match DatabaseManifest::from_folder(password_hash_folder) {
+        Ok(manifest) => manifest,
+        Err(error) => {
+            return Err(PwnedError::Database(
+                "Could not read the manifest of the database".to_string(),
+                error,
+            ))
+        }
+    };
+
+    if manifest.has_file_list() {
+        let found_errors = manifest.verify_folder(password_hash_folder, false);
+        if !found_errors.is_empty() {
+            for found_error in &found_errors {
+                error!("The database is corrupted: {}", found_error);
+            }
+            return Err(PwnedError::CorruptDatabase(found_errors));
+        }
+    } else {
+        warn!(
+            "The manifest of the database does not list its files, so it could not be validated."
+        );
+    }
+    Ok(manifest)
+}
+
+/// Open the password database at the supplied path with the backend which fits it best. A folder
+/// is opened as an optimized database, a compiled database directly and an ordered password file
+/// is searched with the divide and conquer algorithm. With `--mmap`, folders and password files are
+/// mapped into memory instead.
+pub(crate) fn open_database(
+    matches: &ArgMatches,
+    database_path: &Path,
+) -> Result<Box<dyn PasswordDatabase>, CreateInstanceError> {
+    let use_mmap = matches.is_present("mmap");
+    if database_path.is_dir() {
+        if use_mmap {
+            return Ok(Box::new(MappedPrefixDatabase::from_folder(database_path)?));
+        }
+        return Ok(Box::new(OptimizedDatabase::from_folder(database_path)?));
+    }
+
+    if is_compiled_database(database_path) {
+        Ok(Box::new(CompiledDatabase::from_file(database_path)?))
+    } else if use_mmap {
+        Ok(Box::new(MappedDatabase::from_file(database_path)?))
+    } else {
+        Ok(Box::new(DivideAndConquerLookup::from_file(database_path)?))
+    }
+}
+
+/// How often a failed request to the range API is retried during a lookup. The user waits for the
+/// result, so it is given up earlier than a download.
+const REMOTE_LOOKUP_RETRIES: u32 = 2;
+
+/// Get the folder in which the responses of the range API are cached by default (the cache folder
+/// of the user, if it is known).
+fn get_default_cache_folder() -> Option<PathBuf> {
+    let user_cache_folder = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(folder) if !folder.is_empty() => PathBuf::from(folder),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(user_cache_folder.join("pwned-rs").join("range"))
+}
+
+/// Open the range API at `--remote-url` as password database for the hash type selected with
+/// `--hash-type` (SHA-1 by default). Unless `--no-cache` is used, the responses are cached in
+/// `--cache-dir` (or the cache folder of the user) for `--cache-ttl` seconds.
+pub(crate) fn open_remote_database(
+    matches: &ArgMatches,
+) -> Result<(HashType, RemoteDatabase), PwnedError> {
+    let hash_type = HashType::from_str(matches.value_of("hash-type").unwrap_or("sha1"))
+        .map_err(|error| PwnedError::InvalidArgument(error.to_string()))?;
+    let base_url = matches.value_of("remote-url").unwrap_or(DEFAULT_BASE_URL);
+    let mut client = RangeClient::new(base_url, hash_type);
+    client.set_padding(true);
+    client.set_retries(REMOTE_LOOKUP_RETRIES);
+    debug!(
+        "Looking up {} hashes through the range API at {}",
+        hash_type,
+        client.get_base_url()
+    );
+    let mut database = RemoteDatabase::new(client);
+    if matches.is_present("no-cache") {
+        return Ok((hash_type, database));
+    }
+
+    let time_to_live = match matches
+        .value_of("cache-ttl")
+        .unwrap_or("86400")
+        .parse::<u64>()
+    {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => {
+            return Err(PwnedError::InvalidArgument(
+                "The time to live of the cache has to be a number of seconds.".to_string(),
+            ))
+        }
+    };
+    let cache_folder = match matches.value_of("cache-dir") {
+        Some(folder) => PathBuf::from(folder),
+        None => match get_default_cache_folder() {
+            Some(folder) => folder,
+            None => {
+                debug!("The cache folder of the user is unknown, the responses are not cached");
+                return Ok((hash_type, database));
+            }
+        },
+    };
+
+    // the prefixes of both hash types are the same, so their responses are kept apart
+    let cache_folder = cache_folder.join(hash_type.to_string());
+    match RangeCache::new(&cache_folder) {
+        Ok(cache) => database.set_cache(cache, time_to_live),
+        Err(error) => {
+            return Err(PwnedError::Io(
+                format!(
+                    "Could not create the cache folder {}",
+                    cache_folder.display()
+                ),
+                error,
+            ))
+        }
+    }
+    Ok((hash_type, database))
+}
@@ -1,21 +1,244 @@
-use crate::haveibeenpwned::DatabaseIterator;
+use crate::error::PwnedError;
+use crate::haveibeenpwned::{DatabaseIterator, STDIN_PATH};
+use crate::manifest::{
+    DatabaseManifest, FileInformation, SourceInformation, DEFAULT_PREFIX_LENGTH,
+    MANIFEST_FILE_NAME, MAXIMAL_PREFIX_LENGTH, MINIMAL_PREFIX_LENGTH,
+};
+use crate::subcommands::applypatch::STAGING_FOLDER_NAME;
+use crate::subcommands::create_progress_bar;
+use crate::{compare_hashes, HashType, PasswordHashEntry};
+use chrono::Utc;
 use clap::ArgMatches;
-use indicatif::{ProgressBar, ProgressStyle};
-use log::{debug, error, info};
-use std::cmp::min;
-use std::fs::{File, OpenOptions};
-use std::io::Write;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use log::{debug, info};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs::{remove_dir_all, remove_file, File, OpenOptions};
+use std::io::{BufWriter, Error, Write};
 use std::path::Path;
-use std::process::exit;
+use std::str::FromStr;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
 
-pub fn run_subcommand(matches: &ArgMatches) {
+/// The number of bytes of the password file which are parsed at once by a worker thread.
+const CHUNK_SIZE: usize = 1024 * 1024 * 4;
+
+/// The size of the buffer which is used for writing each file of the optimized database.
+const OUTPUT_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// The file of the optimized database into which the hashes of one prefix are written. It keeps
+/// track of the information which is stored for the file in the manifest.
+pub(crate) struct PrefixFileWriter {
+    prefix: String,
+    output_file: BufWriter<File>,
+    hasher: Sha256,
+    entries: u64,
+    size: u64,
+}
+
+impl PrefixFileWriter {
+    pub(crate) fn create(output_folder: &Path, prefix: String) -> Result<PrefixFileWriter, Error> {
+        let output_file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(output_folder.join(format!("{}.txt", prefix)))?;
+        Ok(PrefixFileWriter {
+            prefix,
+            output_file: BufWriter::with_capacity(OUTPUT_BUFFER_SIZE, output_file),
+            hasher: Sha256::new(),
+            entries: 0,
+            size: 0,
+        })
+    }
+
+    pub(crate) fn write_line(&mut self, line: &[u8]) -> Result<(), Error> {
+        self.output_file.write_all(line)?;
+        self.hasher.input(line);
+        self.entries += 1;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    pub(crate) fn get_prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub(crate) fn get_entries(&self) -> u64 {
+        self.entries
+    }
+
+    /// Flush the remaining buffered data into the file and add its information to the manifest.
+    pub(crate) fn finish(mut self, manifest: &mut DatabaseManifest) -> Result<(), Error> {
+        self.output_file.flush()?;
+        manifest.add_file(
+            &format!("{}.txt", self.prefix),
+            FileInformation::new(self.entries, self.size, &self.hasher.result_str()),
+        );
+        Ok(())
+    }
+}
+
+/// Remove the files of a previous database from the output folder, so they cannot be mixed up with
+/// the files of the new database (e.g. if the prefix length changed). This includes the manifest,
+/// all `*.txt` files and the staging folder of an interrupted patch, all other files are kept.
+fn remove_previous_database(output_folder: &Path) -> Result<(), Error> {
+    for entry in output_folder.read_dir()? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if file_name == STAGING_FOLDER_NAME && entry.file_type()?.is_dir() {
+            debug!("Removing the staging folder of an interrupted patch");
+            remove_dir_all(entry.path())?;
+        } else if (file_name.ends_with(".txt") || file_name == MANIFEST_FILE_NAME)
+            && entry.file_type()?.is_file()
+        {
+            debug!("Removing {} of a previous database", file_name);
+            remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// A chunk of the (decompressed) password file together with its position in the file.
+struct RawChunk {
+    index: u64,
+    data: Vec<u8>,
+    /// The number of (compressed) bytes of the password file which were read up to this chunk.
+    consumed_bytes: u64,
+}
+
+/// The line of a chunk which could not be parsed. The position is relative to the chunk, since
+/// the workers do not know where in the file the chunk starts.
+struct ChunkError {
+    line_index: u64,
+    byte_offset: u64,
+    /// The reason why the line could not be parsed, `None` if it is not ordered by hash.
+    reason: Option<String>,
+}
+
+/// The entries which were parsed from a chunk of the password file by a worker thread.
+struct ParsedChunk {
+    index: u64,
+    entries: Vec<PasswordHashEntry>,
+    /// The number of (decompressed) bytes of the chunk.
+    size: u64,
+    consumed_bytes: u64,
+    /// The line at which parsing the chunk stopped.
+    error: Option<ChunkError>,
+}
+
+/// Parse all lines of a chunk of the password file. Parsing stops at the first line which is not
+/// valid, contains a hash of another type or is not ordered by hash, just like the
+/// [DatabaseIterator] does.
+fn parse_chunk(chunk: &RawChunk, hash_type: HashType) -> ParsedChunk {
+    let mut parsed_chunk = ParsedChunk {
+        index: chunk.index,
+        entries: Vec::with_capacity(chunk.data.len() / (hash_type.get_hash_length() + 4)),
+        size: chunk.data.len() as u64,
+        consumed_bytes: chunk.consumed_bytes,
+        error: None,
+    };
+
+    let mut byte_offset = 0;
+    for (line_index, line) in chunk.data.split_inclusive(|c| *c == b'\n').enumerate() {
+        let reason = match std::str::from_utf8(line) {
+            Ok(entry_line) => {
+                match PasswordHashEntry::from_line_with_type(entry_line.trim(), hash_type) {
+                    Ok(entry) if entry.get_hash_type() != hash_type => Some(format!(
+                        "found a {} hash in a file with {} hashes",
+                        entry.get_hash_type(),
+                        hash_type
+                    )),
+                    Ok(entry)
+                        if parsed_chunk
+                            .entries
+                            .last()
+                            .is_some_and(|last| *last > entry) =>
+                    {
+                        None
+                    }
+                    Ok(entry) => {
+                        parsed_chunk.entries.push(entry);
+                        byte_offset += line.len() as u64;
+                        continue;
+                    }
+                    Err(error) => Some(error.to_string()),
+                }
+            }
+            Err(_) => Some("the line is not valid UTF-8".to_string()),
+        };
+        parsed_chunk.error = Some(ChunkError {
+            line_index: line_index as u64,
+            byte_offset,
+            reason,
+        });
+        break;
+    }
+
+    parsed_chunk
+}
+
+/// Read the password file in chunks which end at line boundaries and pass them to the workers. The
+/// parser is returned afterwards, so the checksum of the password file can be queried.
+fn read_chunks(
+    mut parser: DatabaseIterator,
+    chunk_sender: SyncSender<RawChunk>,
+) -> Result<DatabaseIterator, PwnedError> {
+    let mut index = 0;
+    loop {
+        match parser.read_chunk(CHUNK_SIZE) {
+            Ok(Some(data)) => {
+                // if the sending fails, the writer stopped and there is nothing more to do
+                let chunk = RawChunk {
+                    index,
+                    data,
+                    consumed_bytes: parser.get_consumed_bytes(),
+                };
+                if chunk_sender.send(chunk).is_err() {
+                    break;
+                }
+                index += 1;
+            }
+            Ok(None) => break,
+            Err(error) => {
+                return Err(PwnedError::Io(
+                    "Could not read from the password file".to_string(),
+                    error,
+                ))
+            }
+        }
+    }
+    Ok(parser)
+}
+
+/// Parse the chunks received from the reader until there are no more chunks.
+fn parse_chunks(
+    chunk_receiver: Arc<Mutex<Receiver<RawChunk>>>,
+    parsed_sender: SyncSender<ParsedChunk>,
+    hash_type: HashType,
+) {
+    loop {
+        let received_chunk = match chunk_receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => break,
+        };
+        let chunk = match received_chunk {
+            Ok(chunk) => chunk,
+            Err(_) => break,
+        };
+        if parsed_sender.send(parse_chunk(&chunk, hash_type)).is_err() {
+            break;
+        }
+    }
+}
+
+pub fn run_subcommand(matches: &ArgMatches) -> Result<(), PwnedError> {
     // get the path to the password file
     let password_hash_path = match matches.value_of("password-hashes") {
         Some(path) => path,
-        None => {
-            error!("It seems that the path to the file for the password hashes was not provided, please see the help for usage instructions.");
-            exit(-1);
-        }
+        None => return Err(PwnedError::InvalidArgument("It seems that the path to the file for the password hashes was not provided, please see the help for usage instructions.".to_string())),
     };
     debug!("Got {} as a password hash file", password_hash_path);
 
@@ -23,111 +246,302 @@ pub fn run_subcommand(matches: &ArgMatches) {
     let output_folder = match matches.value_of("output-folder") {
         Some(path) => {
             if !Path::new(path).exists() {
-                error!(
+                return Err(PwnedError::InvalidArgument(format!(
                     "The supplied path ('{}') does not exists. Please select an existing folder.",
                     path
-                );
-                exit(-1);
+                )));
             }
             path
         }
-        None => {
-            error!("It seems that the path where the optimized hashes should be stored was not provided, please see the help for usage instructions.");
-            exit(-2);
-        }
+        None => return Err(PwnedError::InvalidArgument("It seems that the path where the optimized hashes should be stored was not provided, please see the help for usage instructions.".to_string())),
     };
     debug!("Got {} as the output folder", output_folder);
 
+    // get the number of characters of the prefix which is used for splitting the hashes into files
+    let prefix_length = match matches.value_of("prefix-length") {
+        Some(value) => match value.parse::<usize>() {
+            Ok(length) if (MINIMAL_PREFIX_LENGTH..=MAXIMAL_PREFIX_LENGTH).contains(&length) => {
+                length
+            }
+            _ => {
+                return Err(PwnedError::InvalidArgument(format!(
+                    "The prefix length has to be a number between {} and {}.",
+                    MINIMAL_PREFIX_LENGTH, MAXIMAL_PREFIX_LENGTH
+                )))
+            }
+        },
+        None => DEFAULT_PREFIX_LENGTH,
+    };
+    debug!(
+        "Splitting the hashes by prefixes of length {}",
+        prefix_length
+    );
+
+    // get the number of threads which are used for parsing the password file
+    let number_of_threads = match matches.value_of("threads") {
+        Some(value) => match value.parse::<usize>() {
+            Ok(count) if count > 0 => count,
+            _ => {
+                return Err(PwnedError::InvalidArgument(
+                    "The number of threads has to be a positive number.".to_string(),
+                ))
+            }
+        },
+        None => thread::available_parallelism()
+            .map(|count| count.get())
+            .unwrap_or(1),
+    };
+    debug!(
+        "Parsing the password file with {} threads",
+        number_of_threads
+    );
+
     // get an instance of the password parser
-    let mut parser = match DatabaseIterator::from_file(password_hash_path) {
+    let parser = match DatabaseIterator::from_file(password_hash_path) {
         Ok(parser) => parser,
         Err(error) => {
-            error!(
-                "Could not get an instance of the parser. The error was: {}",
-                error
-            );
-            exit(-3);
+            return Err(PwnedError::Database(
+                "Could not get an instance of the parser".to_string(),
+                error,
+            ))
         }
     };
 
-    // try to get the size of the whole password file
-    let file_size = match parser.get_file_size() {
-        Some(size) => size,
-        None => {
-            error!("Could not determine the size of the original password file.");
-            exit(-4);
+    // if a hash type was selected, be sure that the file contains hashes of this type
+    if let Some(selected_type) = matches.value_of("hash-type") {
+        if HashType::from_str(selected_type).ok() != Some(parser.get_hash_type()) {
+            return Err(PwnedError::InvalidArgument(format!(
+                "The password file contains {} hashes instead of the selected {} hashes.",
+                parser.get_hash_type(),
+                selected_type
+            )));
         }
-    };
+    }
+    debug!(
+        "The password file contains {} hashes",
+        parser.get_hash_type()
+    );
+
+    // the files of a previous database in the output folder would become part of the new one
+    if let Err(error) = remove_previous_database(Path::new(output_folder)) {
+        return Err(PwnedError::Io(
+            "Could not remove the files of the previous database from the output folder"
+                .to_string(),
+            error,
+        ));
+    }
 
-    // get an instance from  the progress bar to indicate the optimization progress
-    let progress_bar = ProgressBar::new(file_size);
-    progress_bar.set_style(ProgressStyle::default_bar()
-        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta_precise})")
-        .progress_chars("#>-"));
-    progress_bar.set_draw_delta(1024 * 1024 * 8);
+    // get an instance from  the progress bar to indicate the optimization progress (of the compressed data)
+    let progress_bar = create_progress_bar(parser.get_file_size());
 
-    // start processing (and optimizing) the information stored in the password hash file
-    let mut processed_bytes = 0;
-    let mut last_prefix = "".to_string();
-    let mut number_of_subfiles = 0;
-    let mut output_file_name = Path::new(output_folder).join("tmp_file.txt");
-    let mut current_output_file: File = OpenOptions::new()
-        .write(true)
-        .append(false)
-        .read(false)
-        .create(true)
-        .open(output_file_name)
-        .unwrap();
-    while processed_bytes < file_size {
-        // get the entry or exit the loop if there is no next entry
-        let password_hash_entry = match parser.next() {
-            Some(entry) => entry,
-            None => break,
-        };
+    // the manifest describes the source and all files of the optimized database
+    let hash_type = parser.get_hash_type();
+    let mut manifest = DatabaseManifest::new(prefix_length);
+    manifest.set_hash_type(hash_type);
+    manifest.set_created_at(&Utc::now().to_rfc3339());
 
-        // if the hash prefix changed, we have to change the output file into we which are writing
-        let current_prefix = password_hash_entry.get_prefix();
-        if !last_prefix.eq_ignore_ascii_case(current_prefix.as_str()) {
-            output_file_name = Path::new(output_folder).join(format!("{}.txt", current_prefix));
-            current_output_file = match OpenOptions::new()
-                .write(true)
-                .append(false)
-                .read(false)
-                .create(true)
-                .open(output_file_name)
+    // start the pipeline: one thread reads the password file in chunks, the workers parse the
+    // chunks and this thread writes the parsed entries in the original order
+    let (chunk_sender, chunk_receiver) = sync_channel(number_of_threads * 2);
+    let (parsed_sender, parsed_receiver) = sync_channel(number_of_threads * 2);
+    let reader_thread = thread::spawn(move || read_chunks(parser, chunk_sender));
+    let chunk_receiver = Arc::new(Mutex::new(chunk_receiver));
+    for _ in 0..number_of_threads {
+        let chunk_receiver = Arc::clone(&chunk_receiver);
+        let parsed_sender = parsed_sender.clone();
+        thread::spawn(move || parse_chunks(chunk_receiver, parsed_sender, hash_type));
+    }
+    drop(parsed_sender);
+
+    // start processing (and optimizing) the information stored in the password hash file
+    let write_error =
+        |error| PwnedError::Io("Could not write the optimized database".to_string(), error);
+    let mut current_output_file: Option<PrefixFileWriter> = None;
+    let mut pending_chunks = BTreeMap::new();
+    let mut next_index = 0;
+    let mut written_lines = 0;
+    let mut written_bytes = 0;
+    let mut last_written_hash: Option<String> = None;
+    for parsed_chunk in parsed_receiver.iter() {
+        // the chunks can arrive out of order, so keep them until all previous ones were written
+        pending_chunks.insert(parsed_chunk.index, parsed_chunk);
+        while let Some(parsed_chunk) = pending_chunks.remove(&next_index) {
+            // the workers check the order within the chunks, so just the boundary is left
+            if let (Some(last_hash), Some(first_entry)) =
+                (&last_written_hash, parsed_chunk.entries.first())
             {
-                Ok(file_handle) => file_handle,
-                Err(_) => {
-                    error!("Could not open the output file for the optimized data set.");
-                    exit(-5);
+                if compare_hashes(&first_entry.get_hash(), last_hash) == Ordering::Less {
+                    progress_bar.abandon();
+                    return Err(PwnedError::UnsortedInput {
+                        line_number: written_lines + 1,
+                        byte_offset: written_bytes,
+                    });
                 }
-            };
-            number_of_subfiles += 1;
-            last_prefix = current_prefix;
-        }
+            }
+            if let Some(last_entry) = parsed_chunk.entries.last() {
+                last_written_hash = Some(last_entry.get_hash());
+            }
 
-        // write the current entry to the file
-        let _ = match current_output_file.write(password_hash_entry.get_line_to_write().as_bytes())
-        {
-            Ok(count) => count,
-            Err(_) => {
-                error!("Could not write a password entry into the new file.");
-                exit(-6);
+            for password_hash_entry in &parsed_chunk.entries {
+                // if the hash prefix changed, we have to change the output file into we which are writing
+                let current_prefix = match password_hash_entry.get_dynamic_prefix(prefix_length) {
+                    Some(prefix) => prefix.to_uppercase(),
+                    None => {
+                        return Err(PwnedError::InvalidArgument(
+                            "The prefix length is longer than the hashes in the password file."
+                                .to_string(),
+                        ))
+                    }
+                };
+                let prefix_changed = match current_output_file {
+                    Some(ref output_file) => output_file.prefix != current_prefix,
+                    None => true,
+                };
+                if prefix_changed {
+                    if let Some(finished_file) = current_output_file.take() {
+                        finished_file.finish(&mut manifest).map_err(write_error)?;
+                    }
+                    current_output_file = Some(
+                        PrefixFileWriter::create(Path::new(output_folder), current_prefix)
+                            .map_err(write_error)?,
+                    );
+                }
+
+                // write the current entry to the file
+                if let Some(ref mut output_file) = current_output_file {
+                    output_file
+                        .write_line(password_hash_entry.get_line_to_write().as_bytes())
+                        .map_err(write_error)?;
+                }
             }
-        };
 
-        // set the new current position for the progress bar
-        let new = min(
-            processed_bytes + password_hash_entry.get_size_in_bytes(),
-            file_size,
-        );
-        processed_bytes = new;
-        progress_bar.set_position(processed_bytes);
+            // set the new current position for the progress bar
+            progress_bar.set_position(parsed_chunk.consumed_bytes);
+            next_index += 1;
+
+            // stop at the first line which could not be parsed, since the optimized database would
+            // silently miss password hashes otherwise
+            if let Some(chunk_error) = parsed_chunk.error {
+                progress_bar.abandon();
+                let line_number = written_lines + chunk_error.line_index + 1;
+                let byte_offset = written_bytes + chunk_error.byte_offset;
+                return Err(match chunk_error.reason {
+                    Some(reason) => PwnedError::Format {
+                        line_number,
+                        byte_offset,
+                        reason,
+                    },
+                    None => PwnedError::UnsortedInput {
+                        line_number,
+                        byte_offset,
+                    },
+                });
+            }
+            written_lines += parsed_chunk.entries.len() as u64;
+            written_bytes += parsed_chunk.size;
+        }
+    }
+    if let Some(finished_file) = current_output_file.take() {
+        finished_file.finish(&mut manifest).map_err(write_error)?;
     }
     progress_bar.finish_with_message("optimized");
 
+    // the reader is done, so the checksum of the whole password file is available
+    drop(parsed_receiver);
+    let parser = match reader_thread.join() {
+        Ok(parser) => parser?,
+        Err(_) => {
+            return Err(PwnedError::Io(
+                "The thread which reads the password file failed".to_string(),
+                Error::other("the thread panicked"),
+            ))
+        }
+    };
+
+    // store the manifest, so the lookup knows how the database was split and can verify it
+    let source_name = match Path::new(password_hash_path).file_name() {
+        Some(name) if password_hash_path != STDIN_PATH => name.to_string_lossy().to_string(),
+        _ => "stdin".to_string(),
+    };
+    manifest.set_source(SourceInformation::new(
+        &source_name,
+        parser.get_consumed_bytes(),
+        &parser.get_checksum().unwrap_or_default(),
+    ));
+    if let Err(error) = manifest.write_to_folder(Path::new(output_folder)) {
+        return Err(PwnedError::Io(
+            "Could not write the manifest of the optimized database".to_string(),
+            error,
+        ));
+    }
+
     info!(
         "Optimized password database and splitted it into {} files",
-        number_of_subfiles
+        manifest.get_files().len()
     );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::create_temp_path;
+
+    #[test]
+    fn parsing_a_chunk_stops_at_the_first_invalid_line() {
+        let chunk = b"5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3\r\nINVALID:1\n5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD9:1\n";
+
+        let parsed_chunk = parse_chunk(
+            &RawChunk {
+                index: 7,
+                data: chunk.to_vec(),
+                consumed_bytes: 42,
+            },
+            HashType::Sha1,
+        );
+        assert_eq!(7, parsed_chunk.index);
+        assert_eq!(1, parsed_chunk.entries.len());
+        assert_eq!(42, parsed_chunk.consumed_bytes);
+        let chunk_error = parsed_chunk.error.unwrap();
+        assert_eq!(1, chunk_error.line_index);
+        assert_eq!(44, chunk_error.byte_offset);
+
+        let ntlm_chunk = parse_chunk(
+            &RawChunk {
+                index: 0,
+                data: b"8846F7EAEE8FB117AD06BDD830B7586C:7\n".to_vec(),
+                consumed_bytes: 35,
+            },
+            HashType::Sha1,
+        );
+        assert_eq!(0, ntlm_chunk.entries.len());
+        assert_eq!(true, ntlm_chunk.error.is_some());
+    }
+
+    #[test]
+    fn removing_the_previous_database_keeps_other_files() {
+        let output_folder = create_temp_path("optimize-previous-database");
+        std::fs::create_dir_all(output_folder.join(STAGING_FOLDER_NAME)).unwrap();
+        std::fs::create_dir_all(output_folder.join("folder.txt")).unwrap();
+        for file_name in &[
+            "5BA.txt",
+            "5BAA6.txt",
+            "tmp_file.txt",
+            "manifest.json",
+            "notes.md",
+        ] {
+            std::fs::write(output_folder.join(file_name), "").unwrap();
+        }
+
+        remove_previous_database(&output_folder).unwrap();
+        let mut remaining_files = output_folder
+            .read_dir()
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        remaining_files.sort();
+        assert_eq!(vec!["folder.txt", "notes.md"], remaining_files);
+
+        let _ = std::fs::remove_dir_all(output_folder);
+    }
 }
@@ -0,0 +1,345 @@
+use crate::database::{LookupError, PasswordDatabase};
+use crate::error::PwnedError;
+use crate::mapped::{MappedDatabase, MappedPrefixDatabase};
+use crate::range::RANGE_PREFIX_LENGTH;
+use crate::subcommands::get_hash_type;
+use crate::{HashType, PasswordHashEntry};
+use clap::ArgMatches;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use log::{debug, error, info};
+use rand::Rng;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use tiny_http::{Header, Request, Response, Server};
+
+/// The lower and upper limit of the number of entries a padded response contains.
+const MINIMAL_PADDED_ENTRIES: usize = 800;
+const MAXIMAL_PADDED_ENTRIES: usize = 1000;
+
+/// The database which is used for answering the range requests (and the requests of the daemon).
+/// Both variants map the password hashes into memory, so they can look up hashes and prefixes.
+pub(crate) enum RangeBackend {
+    OrderedFile(MappedDatabase),
+    OptimizedFolder(Box<MappedPrefixDatabase>),
+}
+
+impl RangeBackend {
+    pub(crate) fn open(
+        matches: &ArgMatches,
+        database_path: &Path,
+        expected_type: HashType,
+    ) -> Result<RangeBackend, PwnedError> {
+        // be sure that the database contains the hashes which should be served through it
+        let hash_type = get_hash_type(matches, database_path)?;
+        if hash_type != expected_type {
+            return Err(PwnedError::InvalidArgument(format!(
+                "The database {} contains {} hashes instead of {} hashes.",
+                database_path.display(),
+                hash_type,
+                expected_type
+            )));
+        }
+
+        let opened_backend = if database_path.is_dir() {
+            MappedPrefixDatabase::from_folder(database_path)
+                .map(|database| RangeBackend::OptimizedFolder(Box::new(database)))
+        } else {
+            MappedDatabase::from_file(database_path).map(RangeBackend::OrderedFile)
+        };
+        opened_backend.map_err(|error| {
+            PwnedError::Database(
+                format!("Could not open the database {}", database_path.display()),
+                error,
+            )
+        })
+    }
+
+    pub(crate) fn get_entries_with_prefix(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<PasswordHashEntry>, LookupError> {
+        match self {
+            RangeBackend::OrderedFile(database) => database.get_entries_with_prefix(prefix),
+            RangeBackend::OptimizedFolder(database) => database.get_entries_with_prefix(prefix),
+        }
+    }
+
+    /// Get the backend as password database for looking up single hashes.
+    pub(crate) fn get_database(&self) -> &dyn PasswordDatabase {
+        match self {
+            RangeBackend::OrderedFile(database) => database,
+            RangeBackend::OptimizedFolder(database) => database.as_ref(),
+        }
+    }
+}
+
+/// A request for all hash suffixes which belong to a prefix.
+#[derive(Debug, PartialEq)]
+struct RangeRequest {
+    prefix: String,
+    hash_type: HashType,
+}
+
+/// Parse the URL of a request in the form `/range/{prefix}` with the optional query parameter `mode=ntlm`.
+fn parse_range_request(url: &str) -> Result<RangeRequest, (u16, &'static str)> {
+    let (path, query) = match url.find('?') {
+        Some(position) => (&url[..position], &url[position + 1..]),
+        None => (url, ""),
+    };
+    let prefix = match path.strip_prefix("/range/") {
+        Some(prefix) => prefix,
+        None => return Err((404, "Not found")),
+    };
+    if prefix.len() != RANGE_PREFIX_LENGTH || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err((400, "The hash prefix was not in a valid format"));
+    }
+
+    // the hash type is selected through the mode parameter (SHA-1 is the default)
+    let mut hash_type = HashType::Sha1;
+    for parameter in query.split('&') {
+        match parameter {
+            "mode=ntlm" => hash_type = HashType::Ntlm,
+            "mode=sha1" => hash_type = HashType::Sha1,
+            _ if parameter.starts_with("mode=") => return Err((400, "The mode is not supported")),
+            _ => {}
+        }
+    }
+
+    Ok(RangeRequest {
+        prefix: prefix.to_uppercase(),
+        hash_type,
+    })
+}
+
+/// Build the body of the response with one `SUFFIX:COUNT` line per found entry. If requested, the
+/// response is padded with random suffixes which have an occurrence count of zero.
+fn build_range_response(
+    found_entries: &[PasswordHashEntry],
+    hash_type: HashType,
+    add_padding: bool,
+) -> String {
+    let mut response_lines: Vec<String> = found_entries
+        .iter()
+        .map(|entry| {
+            format!(
+                "{}:{}",
+                entry.get_hash()[RANGE_PREFIX_LENGTH..].to_uppercase(),
+                entry.get_occurrences()
+            )
+        })
+        .collect();
+
+    if add_padding {
+        let mut random_generator = rand::thread_rng();
+        let padded_size =
+            random_generator.gen_range(MINIMAL_PADDED_ENTRIES..=MAXIMAL_PADDED_ENTRIES);
+        let suffix_length = hash_type.get_hash_length() - RANGE_PREFIX_LENGTH;
+        while response_lines.len() < padded_size {
+            let random_suffix: String = (0..suffix_length)
+                .map(|_| format!("{:X}", random_generator.gen_range(0..16)))
+                .collect();
+            response_lines.push(format!("{}:0", random_suffix));
+        }
+        response_lines.sort();
+    }
+
+    response_lines
+        .iter()
+        .map(|line| format!("{}\r\n", line))
+        .collect()
+}
+
+/// Get the ETag of a response, which is derived from its body. Clients (like the mirror
+/// subcommand) can send it back to find out if the suffixes of a prefix changed.
+fn get_entity_tag(body: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input_str(body);
+    format!("\"{}\"", &hasher.result_str()[..32])
+}
+
+fn handle_request(
+    request: Request,
+    sha1_backend: &RangeBackend,
+    ntlm_backend: Option<&RangeBackend>,
+) {
+    let add_padding = request.headers().iter().any(|header| {
+        header.field.equiv("Add-Padding") && header.value.as_str().eq_ignore_ascii_case("true")
+    });
+
+    let (status_code, body) = match parse_range_request(request.url()) {
+        Ok(range_request) => {
+            let backend = match range_request.hash_type {
+                HashType::Sha1 => Some(sha1_backend),
+                HashType::Ntlm => ntlm_backend,
+            };
+            match backend {
+                Some(backend) => match backend.get_entries_with_prefix(&range_request.prefix) {
+                    Ok(found_entries) => {
+                        debug!(
+                            "Found {} {} hashes for the prefix {}",
+                            found_entries.len(),
+                            range_request.hash_type,
+                            range_request.prefix
+                        );
+                        (
+                            200,
+                            build_range_response(
+                                &found_entries,
+                                range_request.hash_type,
+                                add_padding,
+                            ),
+                        )
+                    }
+                    Err(error) => {
+                        // an empty response would tell the client that none of the hashes was pwned
+                        error!(
+                            "Could not look up the prefix {}. The error was: {}",
+                            range_request.prefix, error
+                        );
+                        (500, "The lookup failed".to_string())
+                    }
+                },
+                None => (400, "The mode is not supported".to_string()),
+            }
+        }
+        Err((status_code, message)) => (status_code, message.to_string()),
+    };
+
+    // padded responses differ for every request, so just the others can be revalidated
+    let entity_tag = if status_code == 200 && !add_padding {
+        Some(get_entity_tag(&body))
+    } else {
+        None
+    };
+    let not_modified = entity_tag.as_ref().is_some_and(|entity_tag| {
+        request.headers().iter().any(|header| {
+            header.field.equiv("If-None-Match") && header.value.as_str() == entity_tag
+        })
+    });
+
+    let mut response = if not_modified {
+        Response::from_string("").with_status_code(304)
+    } else {
+        Response::from_string(body)
+            .with_status_code(status_code)
+            .with_header(Header::from_bytes(&b"Content-Type"[..], &b"text/plain"[..]).unwrap())
+    };
+    if let Some(entity_tag) = entity_tag {
+        response.add_header(Header::from_bytes(&b"ETag"[..], entity_tag.as_bytes()).unwrap());
+    }
+    if let Err(error) = request.respond(response) {
+        error!("Could not send the response. The error was: {}", error);
+    }
+}
+
+pub fn run_subcommand(matches: &ArgMatches) -> Result<(), PwnedError> {
+    // get the path to the SHA-1 password database (either the original file or the optimized folder)
+    let password_database_path = match matches.value_of("password-database") {
+        Some(path) => Path::new(path),
+        None => return Err(PwnedError::InvalidArgument("It seems that the path to the password database was not provided, please see the help for usage instructions.".to_string())),
+    };
+
+    // get the address on which the server should listen and the number of worker threads
+    let listen_address = matches.value_of("listen").unwrap_or("127.0.0.1:8080");
+    let number_of_threads = match matches.value_of("threads").unwrap_or("4").parse::<usize>() {
+        Ok(count) if count > 0 => count,
+        _ => {
+            return Err(PwnedError::InvalidArgument(
+                "The number of threads has to be a positive number.".to_string(),
+            ))
+        }
+    };
+
+    // open the databases which are used for answering the requests
+    let sha1_backend = Arc::new(RangeBackend::open(
+        matches,
+        password_database_path,
+        HashType::Sha1,
+    )?);
+    let ntlm_backend = Arc::new(match matches.value_of("ntlm-database") {
+        Some(path) => Some(RangeBackend::open(
+            matches,
+            Path::new(path),
+            HashType::Ntlm,
+        )?),
+        None => None,
+    });
+
+    // start the server and handle the requests with the configured number of threads
+    let server = match Server::http(listen_address) {
+        Ok(server) => Arc::new(server),
+        Err(error) => {
+            return Err(PwnedError::Io(
+                format!("Could not listen on {}", listen_address),
+                std::io::Error::other(error.to_string()),
+            ))
+        }
+    };
+    info!("Serving the range API on http://{}/range/", listen_address);
+
+    let mut worker_threads = Vec::with_capacity(number_of_threads);
+    for _ in 0..number_of_threads {
+        let server = Arc::clone(&server);
+        let sha1_backend = Arc::clone(&sha1_backend);
+        let ntlm_backend = Arc::clone(&ntlm_backend);
+        worker_threads.push(thread::spawn(move || {
+            for request in server.incoming_requests() {
+                handle_request(request, &sha1_backend, ntlm_backend.as_ref().as_ref());
+            }
+        }));
+    }
+    for worker_thread in worker_threads {
+        let _ = worker_thread.join();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn parsing_range_requests_works() {
+        let sha1_request = parse_range_request("/range/5baa6").unwrap();
+        assert_eq!("5BAA6", sha1_request.prefix);
+        assert_eq!(HashType::Sha1, sha1_request.hash_type);
+
+        let ntlm_request = parse_range_request("/range/8846F?mode=ntlm").unwrap();
+        assert_eq!(HashType::Ntlm, ntlm_request.hash_type);
+
+        assert_eq!(404, parse_range_request("/unknown/5BAA6").err().unwrap().0);
+        assert_eq!(400, parse_range_request("/range/5BAA").err().unwrap().0);
+        assert_eq!(400, parse_range_request("/range/5BAAX").err().unwrap().0);
+        assert_eq!(
+            400,
+            parse_range_request("/range/5BAA6?mode=md5")
+                .err()
+                .unwrap()
+                .0
+        );
+    }
+
+    #[test]
+    fn building_a_padded_range_response_works() {
+        let found_entries =
+            vec![
+                PasswordHashEntry::from_str("5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3").unwrap(),
+            ];
+
+        let response = build_range_response(&found_entries, HashType::Sha1, false);
+        assert_eq!("1E4C9B93F3F0682250B6CF8331B7EE68FD8:3\r\n", response);
+
+        let padded_response = build_range_response(&found_entries, HashType::Sha1, true);
+        let padded_lines: Vec<&str> = padded_response.split_terminator("\r\n").collect();
+        assert_eq!(true, padded_lines.len() >= MINIMAL_PADDED_ENTRIES);
+        assert_eq!(true, padded_lines.len() <= MAXIMAL_PADDED_ENTRIES);
+        assert_eq!(
+            true,
+            padded_lines.contains(&"1E4C9B93F3F0682250B6CF8331B7EE68FD8:3")
+        );
+        assert_eq!(true, padded_lines.iter().all(|line| line.len() >= 37));
+    }
+}
@@ -0,0 +1,133 @@
+use crate::error::PwnedError;
+use crate::haveibeenpwned::DatabaseIterator;
+use crate::sort::ExternalSorter;
+use crate::subcommands::create_progress_bar;
+use clap::ArgMatches;
+use log::{debug, info};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// The number of entries which are parsed between two updates of the progress bar.
+const PROGRESS_INTERVAL: u64 = 4096;
+
+pub fn run_subcommand(matches: &ArgMatches) -> Result<(), PwnedError> {
+    // get the path to the password file
+    let password_hash_path = match matches.value_of("password-hashes") {
+        Some(path) => path,
+        None => return Err(PwnedError::InvalidArgument("It seems that the path to the file for the password hashes was not provided, please see the help for usage instructions.".to_string())),
+    };
+    debug!("Got {} as a password hash file", password_hash_path);
+
+    // get the path of the ordered password file which should be written
+    let output_file = match matches.value_of("output-file") {
+        Some(path) => Path::new(path),
+        None => return Err(PwnedError::InvalidArgument("It seems that the path where the ordered password file should be stored was not provided, please see the help for usage instructions.".to_string())),
+    };
+    let output_folder = match output_file.parent() {
+        Some(folder) if folder.as_os_str().is_empty() => Path::new("."),
+        Some(folder) => folder,
+        None => Path::new("."),
+    };
+    if !output_folder.is_dir() {
+        return Err(PwnedError::InvalidArgument(format!(
+            "The folder {} for the ordered password file does not exist.",
+            output_folder.display()
+        )));
+    }
+    debug!("Got {} as the output file", output_file.display());
+
+    // the temporary files are stored next to the output file by default, since the system wide
+    // folder for them is often too small for the password files
+    let temp_dir = matches
+        .value_of("temp-dir")
+        .map(Path::new)
+        .unwrap_or(output_folder);
+    if !temp_dir.is_dir() {
+        return Err(PwnedError::InvalidArgument(format!(
+            "The folder {} for the temporary files does not exist.",
+            temp_dir.display()
+        )));
+    }
+
+    // get the amount of memory which can be used for sorting the entries
+    let memory_limit = match matches
+        .value_of("memory-limit")
+        .unwrap_or("1024")
+        .parse::<usize>()
+    {
+        Ok(megabytes) if megabytes > 0 => megabytes * 1024 * 1024,
+        _ => {
+            return Err(PwnedError::InvalidArgument(
+                "The memory limit has to be a positive number of megabytes.".to_string(),
+            ))
+        }
+    };
+    debug!(
+        "Sorting with {} bytes of memory and temporary files in {}",
+        memory_limit,
+        temp_dir.display()
+    );
+
+    // get an instance of the password parser, which accepts the entries in any order
+    let mut parser = match DatabaseIterator::from_file(password_hash_path) {
+        Ok(parser) => parser,
+        Err(error) => {
+            return Err(PwnedError::Database(
+                "Could not get an instance of the parser".to_string(),
+                error,
+            ))
+        }
+    };
+    parser.set_order_check(false);
+    debug!(
+        "The password file contains {} hashes",
+        parser.get_hash_type()
+    );
+
+    // collect all entries and write the sorted runs to the temporary folder
+    let progress_bar = create_progress_bar(parser.get_file_size());
+    let mut sorter = ExternalSorter::new(temp_dir, memory_limit);
+    let mut read_entries: u64 = 0;
+    while let Some(password_hash_entry) = parser.next() {
+        sorter.add_entry(password_hash_entry)?;
+        read_entries += 1;
+        if read_entries.is_multiple_of(PROGRESS_INTERVAL) {
+            progress_bar.set_position(parser.get_consumed_bytes());
+        }
+    }
+    if let Some(error) = parser.take_error() {
+        progress_bar.abandon();
+        return Err(error);
+    }
+    progress_bar.finish_with_message("read");
+    info!(
+        "Read {} entries, merging {} temporary files",
+        read_entries,
+        sorter.get_number_of_runs()
+    );
+
+    // the output file is created just now, so it can even replace the original password file
+    let mut writer = match File::create(output_file) {
+        Ok(file) => BufWriter::new(file),
+        Err(error) => {
+            return Err(PwnedError::Io(
+                format!("Could not create the file {}", output_file.display()),
+                error,
+            ))
+        }
+    };
+    let written_entries = sorter.finish(&mut writer)?;
+    if let Err(error) = writer.flush() {
+        return Err(PwnedError::Io(
+            format!("Could not write the file {}", output_file.display()),
+            error,
+        ));
+    }
+    info!(
+        "Wrote {} password hashes ordered by hash into {}",
+        written_entries,
+        output_file.display()
+    );
+    Ok(())
+}
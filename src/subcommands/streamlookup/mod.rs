@@ -0,0 +1,133 @@
+use crate::error::PwnedError;
+use crate::haveibeenpwned::{DatabaseIterator, STDIN_PATH};
+use crate::merge::{CandidateReader, MergeCandidate, MergeJoin, MERGE_BACKEND_NAME};
+use crate::output::{LookupRecord, OutputFormat, RecordWriter};
+use crate::subcommands::create_progress_bar;
+use clap::ArgMatches;
+use log::{debug, info};
+use std::fs::File;
+use std::io::{stdin, stdout, BufRead, BufReader, BufWriter, Error, Write};
+use std::str::FromStr;
+
+/// The size of the buffer for reading the candidates, large reads keep the disk busy.
+const CANDIDATE_BUFFER_SIZE: usize = 1024 * 1024 * 8;
+
+/// The number of candidates after which the progress bar is updated.
+const PROGRESS_INTERVAL: u64 = 4096;
+
+/// Merge the candidates with the password file and write every candidate which was found. The
+/// number of looked up and found candidates is returned.
+fn write_matches(
+    parser: DatabaseIterator,
+    candidate_reader: &mut CandidateReader<Box<dyn BufRead>>,
+    record_writer: &mut RecordWriter<impl Write>,
+) -> Result<(u64, u64), PwnedError> {
+    let write_error = |error: Error| {
+        PwnedError::Io(
+            "Could not write the result of the lookup".to_string(),
+            error,
+        )
+    };
+
+    let progress_bar = create_progress_bar(parser.get_file_size());
+    let mut merge_join = MergeJoin::new(parser, candidate_reader.by_ref());
+    let (mut looked_up_candidates, mut found_candidates) = (0, 0);
+    while let Some((candidate, occurrences)) = merge_join.next() {
+        looked_up_candidates += 1;
+        if let Some(count) = occurrences {
+            found_candidates += 1;
+            if let Some(digest) = candidate.get_password_hash().get_digest() {
+                let record = LookupRecord::new(
+                    &format!("line:{}", candidate.get_line_number()),
+                    &digest,
+                    true,
+                    true,
+                    Some(count),
+                    MERGE_BACKEND_NAME,
+                );
+                let plain_message = format!(
+                    "{}:{}",
+                    candidate.get_password_hash().get_hash().to_uppercase(),
+                    count
+                );
+                record_writer
+                    .write_record(&record, &plain_message)
+                    .map_err(write_error)?;
+            }
+        }
+        if looked_up_candidates % PROGRESS_INTERVAL == 0 {
+            progress_bar.set_position(merge_join.get_consumed_bytes());
+        }
+    }
+    progress_bar.finish_and_clear();
+    record_writer.flush().map_err(write_error)?;
+
+    // an early stop must never look like a list of candidates which were not found
+    if let Some(error) = merge_join.take_error() {
+        return Err(error);
+    }
+    Ok((looked_up_candidates, found_candidates))
+}
+
+pub fn run_subcommand(matches: &ArgMatches) -> Result<(), PwnedError> {
+    // get the path to the password file ordered by hash
+    let password_hash_path = match matches.value_of("password-hashes") {
+        Some(path) => path,
+        None => return Err(PwnedError::InvalidArgument("It seems that the path to the file for the password hashes was not provided, please see the help for usage instructions.".to_string())),
+    };
+    let output_format = OutputFormat::from_str(matches.value_of("output").unwrap_or("plain"))
+        .map_err(PwnedError::InvalidArgument)?;
+
+    // the candidates are read from the file or from stdin if no file was provided
+    let candidates_path = matches.value_of("candidates").unwrap_or(STDIN_PATH);
+    if candidates_path == STDIN_PATH && password_hash_path == STDIN_PATH {
+        return Err(PwnedError::InvalidArgument(
+            "The password hashes and the candidates cannot both be read from the standard input."
+                .to_string(),
+        ));
+    }
+    let candidate_input: Box<dyn BufRead> = if candidates_path == STDIN_PATH {
+        Box::new(BufReader::with_capacity(CANDIDATE_BUFFER_SIZE, stdin()))
+    } else {
+        match File::open(candidates_path) {
+            Ok(file_handle) => {
+                Box::new(BufReader::with_capacity(CANDIDATE_BUFFER_SIZE, file_handle))
+            }
+            Err(error) => {
+                return Err(PwnedError::Io(
+                    "Could not open the file with the candidates".to_string(),
+                    error,
+                ))
+            }
+        }
+    };
+
+    let parser = match DatabaseIterator::from_file(password_hash_path) {
+        Ok(parser) => parser,
+        Err(error) => {
+            return Err(PwnedError::Database(
+                "Could not get an instance of the parser".to_string(),
+                error,
+            ))
+        }
+    };
+    let mut candidate_reader = CandidateReader::new(candidate_input, parser.get_hash_type());
+    debug!(
+        "Looking up the {} candidates in a single pass",
+        parser.get_hash_type()
+    );
+
+    // the output is buffered, since millions of matches can be written
+    let mut record_writer = RecordWriter::new(BufWriter::new(stdout().lock()), output_format);
+    let merge_result = write_matches(parser, &mut candidate_reader, &mut record_writer);
+    if let Some(error) = candidate_reader.take_error() {
+        return Err(error);
+    }
+    let (looked_up_candidates, found_candidates) = merge_result?;
+
+    info!(
+        "{} of {} candidates were found in password breaches",
+        found_candidates, looked_up_candidates
+    );
+    Ok(())
+}
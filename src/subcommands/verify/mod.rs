@@ -0,0 +1,50 @@
+use crate::error::PwnedError;
+use crate::manifest::DatabaseManifest;
+use clap::ArgMatches;
+use log::{error, info};
+use std::path::Path;
+
+pub fn run_subcommand(matches: &ArgMatches) -> Result<(), PwnedError> {
+    // get the path to the optimized password database
+    let password_hash_folder = match matches.value_of("optimized-db-folder") {
+        Some(path) => Path::new(path),
+        None => return Err(PwnedError::InvalidArgument("It seems that the path to the folder for the optimized password hash files was not provided, please see the help for usage instructions.".to_string())),
+    };
+
+    // read the manifest which describes the files of the database
+    let manifest = match DatabaseManifest::from_folder(password_hash_folder) {
+        Ok(manifest) => manifest,
+        Err(error) => {
+            return Err(PwnedError::Database(
+                "Could not read the manifest of the database".to_string(),
+                error,
+            ))
+        }
+    };
+    if !manifest.has_file_list() {
+        return Err(PwnedError::InvalidArgument("The manifest of the database does not list its files, so it cannot be verified. Please optimize the password file again.".to_string()));
+    }
+    if let Some(source) = manifest.get_source() {
+        info!(
+            "The database was created from {} ({} bytes, SHA-256 {})",
+            source.get_file_name(),
+            source.get_size(),
+            source.get_sha256()
+        );
+    }
+
+    // read all files and compare them with the information of the manifest
+    let found_errors = manifest.verify_folder(password_hash_folder, true);
+    if !found_errors.is_empty() {
+        for found_error in &found_errors {
+            error!("The database is corrupted: {}", found_error);
+        }
+        return Err(PwnedError::CorruptDatabase(found_errors));
+    }
+    info!(
+        "The database is complete. All {} files with {} password hashes match the manifest.",
+        manifest.get_files().len(),
+        manifest.get_total_entries()
+    );
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use crate::haveibeenpwned::DatabaseIterator;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The number of temporary paths which were handed out by this test process so far.
+static TEMP_PATH_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Get a path in the temporary folder which is not used by any other test, even if the tests run
+/// in parallel or several test processes run at the same time. Nothing is created at the path.
+pub(crate) fn create_temp_path(name: &str) -> PathBuf {
+    let counter = TEMP_PATH_COUNTER.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!(
+        "pwned-rs-{}-{}-{}",
+        std::process::id(),
+        counter,
+        name
+    ))
+}
+
+/// Write the supplied content into a new file with a unique path and return the path.
+pub(crate) fn create_sample_file(file_name: &str, content: &str) -> PathBuf {
+    let file_path = create_temp_path(file_name);
+    std::fs::write(&file_path, content).unwrap();
+    file_path
+}
+
+/// Create a parser which reads the password hashes from the supplied content instead of a file.
+pub(crate) fn create_parser(content: &'static str) -> DatabaseIterator {
+    DatabaseIterator::from_reader(Box::new(content.as_bytes()), None).unwrap()
+}